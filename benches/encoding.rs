@@ -0,0 +1,106 @@
+// Compares the fixed and compact ssa encodings by encoded size and by the
+// time to decode every instruction.
+//
+//   cargo bench --bench encoding
+
+use lilac::arena::Arena;
+use lilac::compile;
+use lilac::mir;
+use lilac::mir::EffectType;
+use lilac::mir::Expression;
+use lilac::mir::Function;
+use lilac::mir::Interner;
+use lilac::mir::Symbol;
+use lilac::mir::Type;
+use lilac::ssa;
+use lilac::ssa::Builder;
+use lilac::ssa::Encoding;
+use lilac::ssa::Op2;
+use std::hint::black_box;
+use std::time::Instant;
+
+fn fib_rec<'a>(b: mir::Builder<'a>) -> Function<'a> {
+  let fib = b.symbol("fib");
+  let n = b.variable(b.symbol("n"));
+  let sub = Symbol::op2(Op2::SUB_I64);
+  b.function(
+    fib,
+    &[(b.symbol("n"), Type::I64)],
+    &[&[Type::I64]],
+    EffectType::DIVERGE,
+    b.if_(
+      b.call(Symbol::op2(Op2::IS_LT_U_I64), &[n, Expression::ConstI64(2)]),
+      n,
+      b.call(Symbol::op2(Op2::ADD_I64), &[
+        b.call(fib, &[b.call(sub, &[n, Expression::ConstI64(1)])]),
+        b.call(fib, &[b.call(sub, &[n, Expression::ConstI64(2)])]),
+      ])
+    )
+  )
+}
+
+// A long straight-line function, in which most operands refer to recent
+// values.
+
+fn chain(encoding: Encoding, n: u32) -> Vec<u8> {
+  let mut b = Builder::with_encoding(encoding);
+  b.emit_function(1, 2);
+  let mut x = b.emit_param(ssa::Type::I64);
+  let mut y = b.emit_param(ssa::Type::I64);
+  b.emit_results(1);
+  let _ = b.emit_type(ssa::Type::I64);
+  for i in 0 .. n {
+    let c = b.emit_const_i64(i as u64);
+    let z = b.emit_op2(Op2::ADD_I64, x, c);
+    x = y;
+    y = b.emit_op2(Op2::XOR_I64, z, y);
+  }
+  b.emit_return(0, 1);
+  b.emit_value(y);
+  b.view().to_vec()
+}
+
+fn module(encoding: Encoding, funs: &[Function<'_>], names: &Interner<'_>) -> Vec<u8> {
+  compile::compile_module_with(compile::Env::with_encoding(encoding), funs, names).code().to_vec()
+}
+
+fn decode(code: &[u8]) -> usize {
+  ssa::Reader::new(code).filter(|inst| inst.is_ok()).count()
+}
+
+fn bench(name: &str, fixed: &[u8], compact: &[u8]) {
+  assert!(decode(fixed) == decode(compact));
+
+  let iters = (1 << 24) / fixed.len().max(1);
+  let mut times = [0.0; 2];
+
+  for (i, code) in [fixed, compact].iter().enumerate() {
+    let start = Instant::now();
+    for _ in 0 .. iters {
+      let _ = black_box(decode(black_box(code)));
+    }
+    times[i] = start.elapsed().as_secs_f64() * 1e9 / (iters * decode(code)) as f64;
+  }
+
+  println!(
+    "{:<12} fixed {:>8} bytes {:>6.2} ns/inst   compact {:>8} bytes {:>6.2} ns/inst   ratio {:.2}",
+    name,
+    fixed.len(),
+    times[0],
+    compact.len(),
+    times[1],
+    compact.len() as f64 / fixed.len() as f64,
+  );
+}
+
+fn main() {
+  let arena = Arena::new();
+  let names = Interner::new(&arena);
+  let b = mir::Builder::new(&names);
+  let fib = [mir::fib(b)];
+  let fib_rec = [fib_rec(b)];
+  bench("mir-fib", &module(Encoding::Fixed, &fib, &names), &module(Encoding::Compact, &fib, &names));
+  bench("fib-rec", &module(Encoding::Fixed, &fib_rec, &names), &module(Encoding::Compact, &fib_rec, &names));
+  bench("chain-100", &chain(Encoding::Fixed, 100), &chain(Encoding::Compact, 100));
+  bench("chain-10000", &chain(Encoding::Fixed, 10000), &chain(Encoding::Compact, 10000));
+}
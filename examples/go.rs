@@ -12,6 +12,8 @@ fn main() {
 
   buf.emit_function(1, 1);
   let _ = buf.emit_param(Type::I64);
+  buf.emit_results(1);
+  let _ = buf.emit_type(Type::I64);
   let _ = buf.emit_const_i64(1);
   let _ = buf.emit_const_i64(0);
   let _ = buf.emit_goto(Label(1), 3);
@@ -39,5 +41,10 @@ fn main() {
 
   print!("\n\n");
 
-  lilac::compile::compile(&lilac::mir::FIB);
+  let arena = lilac::arena::Arena::new();
+  let names = lilac::mir::Interner::new(&arena);
+  let fib = lilac::mir::fib(lilac::mir::Builder::new(&names));
+  lilac::mir::print::display(core::slice::from_ref(&fib), &names);
+  print!("\n");
+  lilac::compile::compile(&fib, &names);
 }
@@ -0,0 +1,55 @@
+#![no_main]
+
+// Random well-typed mir modules must compile to code that verifies, and the
+// interpreter must agree with a direct evaluation of the mir, both before
+// and after optimization.
+
+use libfuzzer_sys::fuzz_target;
+use lilac::arena::Arena;
+use lilac::compile;
+use lilac::generate;
+use lilac::generate::Source;
+use lilac::interp;
+use lilac::mir;
+use lilac::mir::Interner;
+use lilac::passes;
+use lilac::ssa;
+
+const FUEL: u64 = 1 << 20;
+
+fn check(code: &[u8], args: &[u64], expected: &(u32, Vec<u64>)) {
+  match interp::run_with_fuel(code, 0, args, FUEL) {
+    Ok(r) => assert!((r.index, r.values) == *expected),
+    Err(interp::Error::OutOfFuel) => {}
+    Err(e) => panic!("{}", e),
+  }
+}
+
+fuzz_target!(|data: &[u8]| {
+  let arena = Arena::new();
+  let names = Interner::new(&arena);
+  let mut src = Source::new(data);
+  let funs = generate::mir_module(&names, &mut src);
+  let args = generate::mir_args(&mut src, &funs[0]);
+
+  let Ok(expected) = generate::eval(&funs, 0, &args, FUEL) else { return; };
+
+  let encoding = if src.bool() { ssa::Encoding::Compact } else { ssa::Encoding::Fixed };
+  let env = compile::Env::with_options(src.below(8), encoding);
+  let module = compile::compile_module_with(env, &funs, &names);
+
+  if let Err(e) = ssa::verify(module.code()) {
+    mir::print::display(&funs, &names);
+    panic!("{}", e);
+  }
+
+  check(module.code(), &args, &expected);
+
+  let mut pm = passes::PassManager::with_level(passes::OptLevel::O2);
+  pm.set_verify(true);
+
+  match pm.run(module.code()) {
+    Ok(code) => check(&code, &args, &expected),
+    Err(e) => panic!("{}: {}", e.name, e.error),
+  }
+});
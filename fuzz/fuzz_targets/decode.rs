@@ -0,0 +1,45 @@
+#![no_main]
+
+// Decoding arbitrary bytes must never panic or read out of bounds, and
+// neither may anything that only consumes decoded instructions: the
+// verifier, the container reader, and the interpreter once code verifies.
+
+use libfuzzer_sys::fuzz_target;
+use lilac::container;
+use lilac::interp;
+use lilac::ssa;
+use lilac::ssa::Instruction;
+
+fuzz_target!(|data: &[u8]| {
+  for inst in ssa::Reader::new(data) {
+    let Ok((_, inst)) = inst else { break; };
+    match inst {
+      Instruction::Function(konts, args) => {
+        for rets in konts.iter() {
+          rets.iter().for_each(drop);
+        }
+        args.iter().for_each(drop);
+      }
+      Instruction::Join(args) | Instruction::Kont(args) => {
+        args.iter().for_each(drop);
+      }
+      Instruction::Goto(_, args) | Instruction::Return(_, args) | Instruction::TailCall(_, args) => {
+        args.iter().for_each(drop);
+      }
+      Instruction::Call(_, args, konts) => {
+        args.iter().for_each(drop);
+        konts.iter().for_each(drop);
+      }
+      _ => {}
+    }
+  }
+
+  let _ = container::read_module(data);
+
+  if ssa::verify(data).is_ok() {
+    if let Some(Ok((_, Instruction::Function(_, params)))) = ssa::Reader::new(data).next() {
+      let args = vec![0; params.iter().count()];
+      let _ = interp::run_with_fuel(data, 0, &args, 1 << 16);
+    }
+  }
+});
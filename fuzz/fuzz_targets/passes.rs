@@ -0,0 +1,44 @@
+#![no_main]
+
+// Random ssa code that verifies must still verify after optimization, and
+// must compute the same results, whenever both versions finish within the
+// fuel limit.
+
+use libfuzzer_sys::fuzz_target;
+use lilac::generate;
+use lilac::generate::Source;
+use lilac::interp;
+use lilac::passes;
+use lilac::ssa;
+
+const FUEL: u64 = 1 << 16;
+
+fuzz_target!(|data: &[u8]| {
+  let mut src = Source::new(data);
+  let encoding = if src.bool() { ssa::Encoding::Compact } else { ssa::Encoding::Fixed };
+  let mut out = ssa::Builder::with_encoding(encoding);
+  generate::ssa_module(&mut src, &mut out);
+  let code = out.view();
+
+  if let Err(e) = ssa::verify(code) {
+    panic!("{}", e);
+  }
+
+  let args = generate::ssa_args(&mut src, code, 0);
+  let before = interp::run_with_fuel(code, 0, &args, FUEL);
+
+  let mut pm = passes::PassManager::with_level(passes::OptLevel::O2);
+  pm.set_verify(true);
+
+  let code =
+    match pm.run(code) {
+      Ok(code) => code,
+      Err(e) => panic!("{}: {}", e.name, e.error),
+    };
+
+  let after = interp::run_with_fuel(&code, 0, &args, FUEL);
+
+  if let (Ok(x), Ok(y)) = (before, after) {
+    assert!(x.index == y.index && x.values == y.values);
+  }
+});
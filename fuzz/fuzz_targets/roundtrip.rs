@@ -0,0 +1,246 @@
+#![no_main]
+
+// Random instruction sequences built with `Builder`, in both encodings, must
+// decode back to the same instructions. This is checked by re-emitting what
+// was read, which must reproduce the original bytes in the same encoding and
+// the other encoding's bytes when converted.
+//
+// The sequences aren't well-typed, but operands always refer to earlier
+// values, as the compact encoding requires.
+
+use libfuzzer_sys::fuzz_target;
+use lilac::generate::Source;
+use lilac::ssa;
+use lilac::ssa::Builder;
+use lilac::ssa::Encoding;
+use lilac::ssa::Instruction;
+use lilac::ssa::Label;
+use lilac::ssa::MemFlags;
+use lilac::ssa::MemType;
+use lilac::ssa::Op1;
+use lilac::ssa::Op2;
+use lilac::ssa::Type;
+use lilac::ssa::Value;
+use lilac::ssa::Variable;
+
+const TYPES: [Type; 5] = [Type::BOOL, Type::I32, Type::I64, Type::F32, Type::F64];
+
+fn generate(src: &mut Source<'_>, encoding: Encoding) -> (Vec<u8>, usize) {
+  let mut b = Builder::with_encoding(encoding);
+  let mut count = 0;
+
+  for _ in 0 .. src.below(3) {
+    if src.bool() {
+      let n = src.below(4);
+      let _ = b.emit_struct(n);
+      for _ in 0 .. n {
+        let _ = b.emit_type(Type(src.u8()));
+      }
+    } else {
+      let _ = b.emit_array(Type(src.u8()), src.u32());
+    }
+    count += 1;
+  }
+
+  for _ in 0 .. 1 + src.below(3) {
+    let nkonts = src.below(3);
+    let nargs = src.below(4);
+    let mut nvalues = nargs;
+    let mut nvariables = 0;
+
+    b.emit_function(nkonts, nargs);
+    for _ in 0 .. nargs {
+      let _ = b.emit_param(src.choose(&TYPES));
+    }
+    for _ in 0 .. nkonts {
+      let nrets = src.below(3);
+      b.emit_results(nrets);
+      for _ in 0 .. nrets {
+        let _ = b.emit_type(src.choose(&TYPES));
+      }
+    }
+    count += 1;
+
+    for _ in 0 .. src.below(64) {
+      let value = move |src: &mut Source<'_>| Value(src.below(nvalues));
+      let label = move |src: &mut Source<'_>| Label(src.below(8));
+
+      match src.below(26) {
+        0 => { let _ = b.emit_case(); }
+        1 | 2 => {
+          let n = src.below(3);
+          let _ = if src.bool() { b.emit_join(n) } else { b.emit_kont(n) };
+          for _ in 0 .. n {
+            let _ = b.emit_param(src.choose(&TYPES));
+          }
+          nvalues += n;
+        }
+        3 => { let _ = b.emit_const_bool(src.bool()); nvalues += 1; }
+        4 => { let _ = b.emit_const_i32(src.u32()); nvalues += 1; }
+        5 => { let _ = b.emit_const_i64(src.u64()); nvalues += 1; }
+        6 => { let _ = b.emit_const_f32(f32::from_bits(src.u32())); nvalues += 1; }
+        7 => { let _ = b.emit_const_f64(f64::from_bits(src.u64())); nvalues += 1; }
+        23 => { let _ = b.emit_const_i5(src.below(1 << 5) as u8); nvalues += 1; }
+        24 => { let _ = b.emit_const_i6(src.below(1 << 6) as u8); nvalues += 1; }
+        25 => { let _ = b.emit_const_i7(src.below(1 << 7) as u8); nvalues += 1; }
+        _ if nvalues == 0 => { continue; }
+        8 => { let _ = b.emit_op1(Op1(src.u8()), value(src)); nvalues += 1; }
+        9 => { let _ = b.emit_op2(Op2(src.u8()), value(src), value(src)); nvalues += 1; }
+        10 => { let _ = b.emit_select(value(src), value(src), value(src)); nvalues += 1; }
+        11 => { let _ = b.emit_let_variable(value(src)); nvariables += 1; }
+        18 => { let _ = b.emit_alloca(src.u32(), 1 << src.below(5)); nvalues += 1; }
+        20 => {
+          let xs: Vec<_> = (0 .. src.below(4)).map(|_| value(src)).collect();
+          let _ = b.emit_build(Type(src.u8()), &xs);
+          nvalues += 1;
+        }
+        21 => { let _ = b.emit_extract(value(src), src.u32()); nvalues += 1; }
+        22 => { let _ = b.emit_insert(value(src), src.u32(), value(src)); nvalues += 1; }
+        19 => {
+          let t = MemType(src.u8());
+          let flags = MemFlags(src.u8());
+          if src.bool() {
+            let _ = b.emit_load(t, flags, value(src), src.u32());
+            nvalues += 1;
+          } else {
+            b.emit_store(t, flags, value(src), src.u32(), value(src));
+          }
+        }
+        12 if nvariables != 0 => { let _ = b.emit_get_variable(Variable(src.below(nvariables))); nvalues += 1; }
+        13 if nvariables != 0 => { b.emit_set_variable(Variable(src.below(nvariables)), value(src)); }
+        14 => { let _ = b.emit_if(value(src), label(src), label(src)); }
+        15 | 16 | 17 => {
+          let n = src.below(4);
+          match src.below(4) {
+            0 => { let _ = b.emit_goto(label(src), n); }
+            1 => { b.emit_return(src.below(3), n); }
+            2 => { b.emit_tailcall(src.below(3), n); }
+            _ => {
+              let nkonts = src.below(3);
+              b.emit_call(src.below(3), n, nkonts);
+              for _ in 0 .. n {
+                b.emit_value(value(src));
+              }
+              for _ in 0 .. nkonts {
+                let _ = b.emit_label(label(src));
+              }
+              count += 1;
+              continue;
+            }
+          }
+          for _ in 0 .. n {
+            b.emit_value(value(src));
+          }
+        }
+        _ => { continue; }
+      }
+
+      count += 1;
+    }
+  }
+
+  (b.view().to_vec(), count)
+}
+
+// Re-emits every instruction of `code`, which must decode without error.
+
+fn copy(code: &[u8], encoding: Encoding) -> (Vec<u8>, usize) {
+  let mut b = Builder::with_encoding(encoding);
+  let mut count = 0;
+
+  for inst in ssa::Reader::new(code) {
+    let Ok((_, inst)) = inst else { panic!() };
+    count += 1;
+    match inst {
+      Instruction::TypeStruct(fields) => {
+        let _ = b.emit_struct(fields.iter().count() as u32);
+        for t in fields.iter() {
+          let _ = b.emit_type(t);
+        }
+      }
+      Instruction::TypeArray(t, n) => { let _ = b.emit_array(t, n); }
+      Instruction::Function(konts, args) => {
+        b.emit_function(konts.len(), args.iter().count() as u32);
+        for t in args.iter() {
+          let _ = b.emit_param(t);
+        }
+        for rets in konts.iter() {
+          b.emit_results(rets.iter().count() as u32);
+          for t in rets.iter() {
+            let _ = b.emit_type(t);
+          }
+        }
+      }
+      Instruction::Case() => { let _ = b.emit_case(); }
+      Instruction::Join(args) => {
+        let _ = b.emit_join(args.iter().count() as u32);
+        for t in args.iter() {
+          let _ = b.emit_param(t);
+        }
+      }
+      Instruction::Kont(args) => {
+        let _ = b.emit_kont(args.iter().count() as u32);
+        for t in args.iter() {
+          let _ = b.emit_param(t);
+        }
+      }
+      Instruction::ConstBool(p) => { let _ = b.emit_const_bool(p); }
+      Instruction::ConstI32(c) => { let _ = b.emit_const_i32(c); }
+      Instruction::ConstI64(c) => { let _ = b.emit_const_i64(c); }
+      Instruction::ConstI5(c) => { let _ = b.emit_const_i5(c); }
+      Instruction::ConstI6(c) => { let _ = b.emit_const_i6(c); }
+      Instruction::ConstI7(c) => { let _ = b.emit_const_i7(c); }
+      Instruction::ConstF32(c) => { let _ = b.emit_const_f32(c); }
+      Instruction::ConstF64(c) => { let _ = b.emit_const_f64(c); }
+      Instruction::Op1(t, x) => { let _ = b.emit_op1(t, x); }
+      Instruction::Op2(t, x, y) => { let _ = b.emit_op2(t, x, y); }
+      Instruction::Select(p, x, y) => { let _ = b.emit_select(p, x, y); }
+      Instruction::LetVariable(x) => { let _ = b.emit_let_variable(x); }
+      Instruction::GetVariable(v) => { let _ = b.emit_get_variable(v); }
+      Instruction::SetVariable(v, x) => { b.emit_set_variable(v, x); }
+      Instruction::Alloca(size, align) => { let _ = b.emit_alloca(size, 1 << align); }
+      Instruction::Load(t, flags, x, offset) => { let _ = b.emit_load(t, flags, x, offset); }
+      Instruction::Store(t, flags, x, offset, y) => { b.emit_store(t, flags, x, offset, y); }
+      Instruction::Build(t, args) => {
+        let xs: Vec<_> = args.iter().collect();
+        let _ = b.emit_build(t, &xs);
+      }
+      Instruction::Extract(x, i) => { let _ = b.emit_extract(x, i); }
+      Instruction::Insert(x, i, y) => { let _ = b.emit_insert(x, i, y); }
+      Instruction::If(p, x, y) => { let _ = b.emit_if(p, x, y); }
+      Instruction::Goto(a, args) => {
+        let _ = b.emit_goto(a, args.iter().count() as u32);
+        args.iter().for_each(|x| b.emit_value(x));
+      }
+      Instruction::Return(k, args) => {
+        b.emit_return(k, args.iter().count() as u32);
+        args.iter().for_each(|x| b.emit_value(x));
+      }
+      Instruction::TailCall(f, args) => {
+        b.emit_tailcall(f, args.iter().count() as u32);
+        args.iter().for_each(|x| b.emit_value(x));
+      }
+      Instruction::Call(f, args, konts) => {
+        b.emit_call(f, args.iter().count() as u32, konts.iter().count() as u32);
+        args.iter().for_each(|x| b.emit_value(x));
+        konts.iter().for_each(|a| { let _ = b.emit_label(a); });
+      }
+    }
+  }
+
+  (b.view().to_vec(), count)
+}
+
+fuzz_target!(|data: &[u8]| {
+  let (fixed, n) = generate(&mut Source::new(data), Encoding::Fixed);
+  let (compact, m) = generate(&mut Source::new(data), Encoding::Compact);
+  assert!(n == m);
+
+  assert!(copy(&fixed, Encoding::Fixed) == (fixed.clone(), n));
+  assert!(copy(&compact, Encoding::Compact) == (compact.clone(), n));
+  assert!(copy(&fixed, Encoding::Compact) == (compact.clone(), n));
+  assert!(copy(&compact, Encoding::Fixed) == (fixed.clone(), n));
+
+  let _ = ssa::verify(&fixed);
+  let _ = ssa::verify(&compact);
+});
@@ -0,0 +1,109 @@
+//! arena allocation
+//!
+//! An `Arena` owns the nodes of mir trees, which borrow from it with its
+//! lifetime, so that a front end can build mir at runtime and free all of it
+//! at once. Nodes are `Copy` and are never dropped individually.
+//!
+//! Allocations are bumped out of chunks of a fixed size, and anything larger
+//! than a chunk gets one of its own.
+
+use core::cell::Cell;
+use core::cell::RefCell;
+
+const CHUNK_WORDS: usize = 512;
+
+pub struct Arena {
+  chunks: RefCell<Vec<Vec<u64>>>,
+  // The unused part of the current chunk.
+  next: Cell<*mut u64>,
+  free: Cell<usize>,
+}
+
+impl Arena {
+  pub fn new() -> Self {
+    Self {
+      chunks: RefCell::new(Vec::new()),
+      next: Cell::new(core::ptr::null_mut()),
+      free: Cell::new(0),
+    }
+  }
+
+  // Allocates `n` uninitialized words. Zero words needn't come from a chunk,
+  // but the pointer must still be non-null and aligned.
+
+  fn words(&self, n: usize) -> *mut u64 {
+    if n == 0 {
+      return core::ptr::NonNull::dangling().as_ptr();
+    }
+
+    if n <= self.free.get() {
+      let p = self.next.get();
+      // SAFETY: `p + n` is within the current chunk.
+      self.next.set(unsafe { p.add(n) });
+      self.free.set(self.free.get() - n);
+      return p;
+    }
+
+    let mut chunk = vec![0u64; n.max(CHUNK_WORDS)];
+    let p = chunk.as_mut_ptr();
+    if n < CHUNK_WORDS {
+      // SAFETY: `p + n` is within the new chunk.
+      self.next.set(unsafe { p.add(n) });
+      self.free.set(CHUNK_WORDS - n);
+    }
+    // The chunk's heap allocation doesn't move when the chunk does.
+    self.chunks.borrow_mut().push(chunk);
+    p
+  }
+
+  pub fn alloc<T: Copy>(&self, x: T) -> &T {
+    &self.alloc_slice(core::slice::from_ref(&x))[0]
+  }
+
+  pub fn alloc_slice<T: Copy>(&self, xs: &[T]) -> &[T] {
+    assert!(align_of::<T>() <= align_of::<u64>());
+    let p = self.words(size_of_val(xs).div_ceil(8)) as *mut T;
+    // SAFETY: the words are large enough and suitably aligned for `xs`, and
+    // aren't freed until the arena is dropped. `T: Copy`, so nothing needs
+    // to be dropped.
+    unsafe {
+      core::ptr::copy_nonoverlapping(xs.as_ptr(), p, xs.len());
+      core::slice::from_raw_parts(p, xs.len())
+    }
+  }
+
+  pub fn alloc_bytes(&self, xs: &[u8]) -> &[u8] {
+    self.alloc_slice(xs)
+  }
+}
+
+impl Default for Arena {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn empty_slices() {
+    let arena = Arena::new();
+    assert!(arena.alloc_slice::<u64>(&[]).is_empty());
+    assert!(arena.alloc_bytes(b"").is_empty());
+    assert!(arena.alloc_slice(&[(); 3]).len() == 3);
+    assert!(*arena.alloc(7u32) == 7);
+    assert!(arena.alloc_slice::<u64>(&[]).is_empty());
+  }
+
+  #[test]
+  fn large_and_small() {
+    let arena = Arena::new();
+    let xs: Vec<u64> = (0 .. 2 * CHUNK_WORDS as u64).collect();
+    let a = arena.alloc_slice(&xs[.. 3]);
+    let b = arena.alloc_slice(&xs);
+    let c = arena.alloc_bytes(b"abc");
+    assert!(a == &xs[.. 3] && b == &xs[..] && c == b"abc");
+  }
+}
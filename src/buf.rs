@@ -8,8 +8,8 @@ pub struct Buf {
 
 #[inline(never)]
 #[cold]
-unsafe fn grow(_ptr: ptr, cap: usize, _len: usize, more: usize) -> (ptr, usize) {
-  assert!(more <= isize::MAX as usize);
+unsafe fn grow(ptr: ptr, cap: usize, len: usize, more: usize) -> (ptr, usize) {
+  assert!(more <= isize::MAX as usize - len);
 
   if cap == 0 {
     let size = usize::max(more, 1024);
@@ -19,7 +19,14 @@ unsafe fn grow(_ptr: ptr, cap: usize, _len: usize, more: usize) -> (ptr, usize)
     assert!(! p.is_null());
     return (p, size);
   } else {
-    todo!()
+    let size = usize::min(usize::max(len + more, 2 * cap), isize::MAX as usize);
+    let layout = core::alloc::Layout::from_size_align(cap, 1).unwrap();
+    let p = unsafe { ptr.as_slice_mut_ref(cap) }.as_mut_ptr();
+    let p = unsafe { alloc::alloc::realloc(p, layout, size) };
+    let p = ptr::from(p);
+    assert!(! p.is_null());
+    unsafe { (p + cap).as_slice_mut_ref(size - cap) }.fill(0);
+    return (p, size);
   }
 }
 
@@ -65,3 +72,32 @@ impl Buf {
     unsafe { self.ptr.as_slice_ref(self.len) }
   }
 }
+
+impl Drop for Buf {
+  fn drop(&mut self) {
+    if self.cap != 0 {
+      let layout = core::alloc::Layout::from_size_align(self.cap, 1).unwrap();
+      let p = unsafe { self.ptr.as_slice_mut_ref(self.cap) }.as_mut_ptr();
+      unsafe { alloc::alloc::dealloc(p, layout) };
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn grow_keeps_contents() {
+    let mut b = Buf::new();
+    for i in 0 .. 5000 {
+      let x = b.append(1);
+      assert!(x[0] == 0);
+      x[0] = i as u8;
+    }
+    let x = b.append(10000);
+    assert!(x.iter().all(|&y| y == 0));
+    assert!(b.len() == 15000);
+    assert!(b.view()[.. 5000].iter().enumerate().all(|(i, &y)| y == i as u8));
+  }
+}
@@ -196,3 +196,370 @@ impl<'a, const K: usize> Iterator for IterChunks<'a, K> {
     }
   }
 }
+
+// A cursor for reading from a byte slice. Unlike the `pop_*` methods above,
+// reads past the end fail without consuming anything.
+
+#[derive(Clone, Copy)]
+pub struct ByteReader<'a> {
+  buf: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+  #[inline(always)]
+  pub fn new(buf: &'a [u8]) -> Self {
+    Self { buf, pos: 0 }
+  }
+
+  #[inline(always)]
+  pub fn position(&self) -> usize {
+    self.pos
+  }
+
+  #[inline(always)]
+  pub fn remaining(&self) -> usize {
+    self.buf.len() - self.pos
+  }
+
+  #[inline(always)]
+  pub fn is_empty(&self) -> bool {
+    self.remaining() == 0
+  }
+
+  // The bytes that haven't been read yet.
+
+  #[inline(always)]
+  pub fn rest(&self) -> &'a [u8] {
+    unsafe { (self.buf.ptr() + self.pos).as_slice_ref(self.remaining()) }
+  }
+
+  #[inline(always)]
+  pub fn try_pop_chunk<const K: usize>(&mut self) -> Option<&'a [u8; K]> {
+    if K > self.remaining() { return None; }
+    let p = self.buf.ptr() + self.pos;
+    self.pos += K;
+    Some(unsafe { p.as_ref() })
+  }
+
+  #[inline(always)]
+  pub fn try_pop_slice(&mut self, k: usize) -> Option<&'a [u8]> {
+    if k > self.remaining() { return None; }
+    let p = self.buf.ptr() + self.pos;
+    self.pos += k;
+    Some(unsafe { p.as_slice_ref(k) })
+  }
+
+  #[inline(always)]
+  pub fn pop_all(&mut self) -> &'a [u8] {
+    let rest = self.rest();
+    self.pos = self.buf.len();
+    rest
+  }
+
+  #[inline(always)]
+  pub fn try_pop_u8(&mut self) -> Option<u8> {
+    Some(u8::from_le_bytes(*self.try_pop_chunk()?))
+  }
+
+  #[inline(always)]
+  pub fn try_pop_u16(&mut self) -> Option<u16> {
+    Some(u16::from_le_bytes(*self.try_pop_chunk()?))
+  }
+
+  #[inline(always)]
+  pub fn try_pop_u32(&mut self) -> Option<u32> {
+    Some(u32::from_le_bytes(*self.try_pop_chunk()?))
+  }
+
+  #[inline(always)]
+  pub fn try_pop_u64(&mut self) -> Option<u64> {
+    Some(u64::from_le_bytes(*self.try_pop_chunk()?))
+  }
+
+  #[inline(always)]
+  pub fn try_pop_f32(&mut self) -> Option<f32> {
+    Some(f32::from_le_bytes(*self.try_pop_chunk()?))
+  }
+
+  #[inline(always)]
+  pub fn try_pop_f64(&mut self) -> Option<f64> {
+    Some(f64::from_le_bytes(*self.try_pop_chunk()?))
+  }
+
+  #[inline(always)]
+  pub fn try_pop_u16_be(&mut self) -> Option<u16> {
+    Some(u16::from_be_bytes(*self.try_pop_chunk()?))
+  }
+
+  #[inline(always)]
+  pub fn try_pop_u32_be(&mut self) -> Option<u32> {
+    Some(u32::from_be_bytes(*self.try_pop_chunk()?))
+  }
+
+  #[inline(always)]
+  pub fn try_pop_u64_be(&mut self) -> Option<u64> {
+    Some(u64::from_be_bytes(*self.try_pop_chunk()?))
+  }
+
+  // Fails on truncated input, and on encodings longer than ten bytes or with
+  // bits beyond the 64th set.
+
+  pub fn try_pop_uleb128(&mut self) -> Option<u64> {
+    let mut r = *self;
+    let mut x = 0u64;
+    let mut shift = 0;
+    loop {
+      let b = r.try_pop_u8()?;
+      if shift == 63 && b > 1 { return None; }
+      x |= ((b & 0x7f) as u64) << shift;
+      if b & 0x80 == 0 { break; }
+      shift += 7;
+    }
+    *self = r;
+    Some(x)
+  }
+
+  pub fn try_pop_sleb128(&mut self) -> Option<i64> {
+    let mut r = *self;
+    let mut x = 0i64;
+    let mut shift = 0;
+    loop {
+      let b = r.try_pop_u8()?;
+      if shift == 63 && b != 0x00 && b != 0x7f { return None; }
+      x |= ((b & 0x7f) as i64) << shift;
+      shift += 7;
+      if b & 0x80 == 0 {
+        if shift < 64 && b & 0x40 != 0 { x |= -1i64 << shift; }
+        break;
+      }
+    }
+    *self = r;
+    Some(x)
+  }
+}
+
+// A cursor for writing into a byte slice. Writes past the end fail without
+// writing anything.
+
+pub struct ByteWriter<'a> {
+  buf: &'a mut [u8],
+  pos: usize,
+}
+
+impl<'a> ByteWriter<'a> {
+  #[inline(always)]
+  pub fn new(buf: &'a mut [u8]) -> Self {
+    Self { buf, pos: 0 }
+  }
+
+  #[inline(always)]
+  pub fn position(&self) -> usize {
+    self.pos
+  }
+
+  #[inline(always)]
+  pub fn remaining(&self) -> usize {
+    self.buf.len() - self.pos
+  }
+
+  #[inline(always)]
+  pub fn try_put_chunk<const K: usize>(&mut self, value: [u8; K]) -> Option<()> {
+    if K > self.remaining() { return None; }
+    let p = self.buf.ptr() + self.pos;
+    self.pos += K;
+    let chunk: &mut [u8; K] = unsafe { p.as_mut_ref() };
+    *chunk = value;
+    Some(())
+  }
+
+  #[inline(always)]
+  pub fn try_put_slice(&mut self, value: &[u8]) -> Option<()> {
+    let k = value.len();
+    if k > self.remaining() { return None; }
+    let p = self.buf.ptr() + self.pos;
+    self.pos += k;
+    unsafe { p.as_slice_mut_ref(k) }.copy_from_slice(value);
+    Some(())
+  }
+
+  #[inline(always)]
+  pub fn try_put_u8(&mut self, value: u8) -> Option<()> {
+    self.try_put_chunk(value.to_le_bytes())
+  }
+
+  #[inline(always)]
+  pub fn try_put_u16(&mut self, value: u16) -> Option<()> {
+    self.try_put_chunk(value.to_le_bytes())
+  }
+
+  #[inline(always)]
+  pub fn try_put_u32(&mut self, value: u32) -> Option<()> {
+    self.try_put_chunk(value.to_le_bytes())
+  }
+
+  #[inline(always)]
+  pub fn try_put_u64(&mut self, value: u64) -> Option<()> {
+    self.try_put_chunk(value.to_le_bytes())
+  }
+
+  #[inline(always)]
+  pub fn try_put_f32(&mut self, value: f32) -> Option<()> {
+    self.try_put_chunk(value.to_le_bytes())
+  }
+
+  #[inline(always)]
+  pub fn try_put_f64(&mut self, value: f64) -> Option<()> {
+    self.try_put_chunk(value.to_le_bytes())
+  }
+
+  #[inline(always)]
+  pub fn try_put_u16_be(&mut self, value: u16) -> Option<()> {
+    self.try_put_chunk(value.to_be_bytes())
+  }
+
+  #[inline(always)]
+  pub fn try_put_u32_be(&mut self, value: u32) -> Option<()> {
+    self.try_put_chunk(value.to_be_bytes())
+  }
+
+  #[inline(always)]
+  pub fn try_put_u64_be(&mut self, value: u64) -> Option<()> {
+    self.try_put_chunk(value.to_be_bytes())
+  }
+
+  pub fn try_put_uleb128(&mut self, value: u64) -> Option<()> {
+    let mut a = [0u8; 10];
+    let n = uleb128(&mut a, value);
+    self.try_put_slice(&a[.. n])
+  }
+
+  pub fn try_put_sleb128(&mut self, value: i64) -> Option<()> {
+    let mut a = [0u8; 10];
+    let n = sleb128(&mut a, value);
+    self.try_put_slice(&a[.. n])
+  }
+}
+
+fn uleb128(out: &mut [u8; 10], value: u64) -> usize {
+  let mut x = value;
+  let mut n = 0;
+  loop {
+    let b = (x & 0x7f) as u8;
+    x >>= 7;
+    if x == 0 {
+      out[n] = b;
+      return n + 1;
+    }
+    out[n] = b | 0x80;
+    n += 1;
+  }
+}
+
+fn sleb128(out: &mut [u8; 10], value: i64) -> usize {
+  let mut x = value;
+  let mut n = 0;
+  loop {
+    let b = (x & 0x7f) as u8;
+    x >>= 7;
+    if (x == 0 && b & 0x40 == 0) || (x == -1 && b & 0x40 != 0) {
+      out[n] = b;
+      return n + 1;
+    }
+    out[n] = b | 0x80;
+    n += 1;
+  }
+}
+
+// The number of bytes in the LEB128 encodings of `value`.
+
+pub fn uleb128_len(value: u64) -> usize {
+  uleb128(&mut [0u8; 10], value)
+}
+
+pub fn sleb128_len(value: i64) -> usize {
+  sleb128(&mut [0u8; 10], value)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const U64S: [u64; 9] = [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, u32::MAX as u64, 1 << 63, u64::MAX];
+
+  const I64S: [i64; 10] = [0, 1, -1, 0x3f, 0x40, -0x40, -0x41, i32::MIN as i64, i64::MAX, i64::MIN];
+
+  #[test]
+  fn leb128_round_trips() {
+    let mut buf = [0u8; 256];
+    let mut w = ByteWriter::new(&mut buf);
+    for x in U64S {
+      let n = w.position();
+      w.try_put_uleb128(x).unwrap();
+      assert!(w.position() - n == uleb128_len(x));
+    }
+    for x in I64S {
+      let n = w.position();
+      w.try_put_sleb128(x).unwrap();
+      assert!(w.position() - n == sleb128_len(x));
+    }
+    let n = w.position();
+
+    let mut r = ByteReader::new(&buf[.. n]);
+    for x in U64S {
+      assert!(r.try_pop_uleb128() == Some(x));
+    }
+    for x in I64S {
+      assert!(r.try_pop_sleb128() == Some(x));
+    }
+    assert!(r.is_empty());
+    assert!(uleb128_len(u64::MAX) == 10 && sleb128_len(i64::MIN) == 10);
+  }
+
+  #[test]
+  fn leb128_truncated() {
+    let mut r = ByteReader::new(&[0x80, 0x80]);
+    assert!(r.try_pop_uleb128().is_none());
+    assert!(r.try_pop_sleb128().is_none());
+    assert!(r.position() == 0);
+
+    let mut buf = [0u8; 1];
+    let mut w = ByteWriter::new(&mut buf);
+    assert!(w.try_put_uleb128(0x80).is_none());
+    assert!(w.position() == 0);
+  }
+
+  #[test]
+  fn leb128_overlong() {
+    // Padding with continuation bytes is fine up to ten bytes.
+    assert!(ByteReader::new(&[0x80, 0x00]).try_pop_uleb128() == Some(0));
+    assert!(ByteReader::new(&[0xff, 0x7f]).try_pop_sleb128() == Some(-1));
+    let mut a = [0x80u8; 11];
+    a[9] = 0x00;
+    assert!(ByteReader::new(&a[.. 10]).try_pop_uleb128() == Some(0));
+    // Eleven bytes are too many.
+    a[9] = 0x80;
+    a[10] = 0x00;
+    assert!(ByteReader::new(&a).try_pop_uleb128().is_none());
+    assert!(ByteReader::new(&a).try_pop_sleb128().is_none());
+    // So are bits beyond the 64th.
+    let mut b = [0xffu8; 10];
+    b[9] = 0x01;
+    assert!(ByteReader::new(&b).try_pop_uleb128() == Some(u64::MAX));
+    b[9] = 0x03;
+    assert!(ByteReader::new(&b).try_pop_uleb128().is_none());
+    b[9] = 0x7f;
+    assert!(ByteReader::new(&b).try_pop_sleb128() == Some(-1));
+    b[9] = 0x01;
+    assert!(ByteReader::new(&b).try_pop_sleb128().is_none());
+  }
+
+  #[test]
+  fn reads_past_the_end() {
+    let mut r = ByteReader::new(&[1, 2, 3]);
+    assert!(r.try_pop_u32().is_none());
+    assert!(r.try_pop_u16() == Some(0x0201));
+    assert!(r.try_pop_slice(2).is_none());
+    assert!(r.rest() == [3]);
+  }
+}
@@ -2,113 +2,779 @@ use crate::prelude::*;
 use crate::ssa;
 use crate::mir;
 use crate::mir::Expression;
+use crate::mir::Statement;
 use crate::mir::Symbol;
+use crate::mir::typecheck::OwnedType;
 use crate::ssa::Label;
 use crate::ssa::Value;
 
 pub struct Env {
   out: ssa::Builder,
+  select_threshold: u32,
+  // The symbols bound in scope, innermost last, and the values bound to each
+  // symbol, indexed by its id, also innermost last.
+  scope: Vec<Symbol>,
+  values: Vec<Vec<Val>>,
+  rets: Vec<Vec<ssa::Type>>,
+  functions: Vec<(Vec<ssa::Type>, Vec<Vec<OwnedType>>)>,
+  // The index of the function named by each symbol, if any.
+  callees: Vec<Option<u32>>,
+  loops: Vec<(Symbol, Label, Vec<ssa::Type>)>,
+  types: mir::typecheck::Types,
 }
 
+// A mir value, scalarized. A tuple is compiled to one ssa value per scalar
+// component, kept in the structure of its type so that fields can be picked
+// out at compile time.
+
+#[derive(Clone)]
+enum Val {
+  Scalar(Value, ssa::Type),
+  Tuple(Vec<Val>),
+}
+
+fn flatten(xs: &[Val], out: &mut Vec<(Value, ssa::Type)>) {
+  for x in xs.iter() {
+    match x {
+      &Val::Scalar(x, t) => out.push((x, t)),
+      Val::Tuple(ys) => flatten(ys, out),
+    }
+  }
+}
+
+fn flat(xs: &[Val]) -> Vec<(Value, ssa::Type)> {
+  let mut out = Vec::with_capacity(xs.len());
+  flatten(xs, &mut out);
+  out
+}
+
+// Rebuilds values with the structure of `xs` from the scalars `ys`.
+
+fn rebuild(xs: &[Val], ys: &mut impl Iterator<Item = (Value, ssa::Type)>) -> Vec<Val> {
+  xs.iter()
+    .map(|x| {
+      match x {
+        Val::Scalar(..) => { let (y, t) = ys.next().unwrap(); Val::Scalar(y, t) }
+        Val::Tuple(xs) => Val::Tuple(rebuild(xs, ys)),
+      }
+    })
+    .collect()
+}
+
+fn scalar(x: Val) -> (Value, ssa::Type) {
+  match x {
+    Val::Scalar(x, t) => (x, t),
+    Val::Tuple(_) => panic!(),
+  }
+}
+
+// Receives a value of type `t` as block or function parameters.
+
+fn receive(env: &mut Env, t: &OwnedType) -> Val {
+  match t {
+    &OwnedType::Scalar(t) => {
+      let t = compile_scalar_type(t);
+      Val::Scalar(env.out.emit_param(t), t)
+    }
+    OwnedType::Tuple(ts) => {
+      Val::Tuple(ts.iter().map(|t| receive(env, t)).collect())
+    }
+  }
+}
+
+// Receives values with the same structure and types as `xs`.
+
+fn receive_like(env: &mut Env, xs: &[Val]) -> Vec<Val> {
+  let ts = flat(xs);
+  let ys: Vec<_> = ts.iter().map(|&(_, t)| (env.out.emit_param(t), t)).collect();
+  rebuild(xs, &mut ys.into_iter())
+}
+
+// The default budget for if-to-select conversion. Both arms of a converted
+// `if` are evaluated unconditionally, so this bounds the amount of work that
+// may be wasted on the arm that isn't taken.
+
+pub const DEFAULT_SELECT_THRESHOLD: u32 = 4;
+
 impl Env {
   pub fn new() -> Self {
+    Self::with_select_threshold(DEFAULT_SELECT_THRESHOLD)
+  }
+
+  // An `if` is lowered to a `select` when the combined cost of its two arms
+  // is at most `threshold`. See `select_cost`.
+
+  pub fn with_select_threshold(threshold: u32) -> Self {
+    Self::with_options(threshold, ssa::Encoding::Fixed)
+  }
+
+  pub fn with_encoding(encoding: ssa::Encoding) -> Self {
+    Self::with_options(DEFAULT_SELECT_THRESHOLD, encoding)
+  }
+
+  pub fn with_options(threshold: u32, encoding: ssa::Encoding) -> Self {
     Self {
-      out: ssa::Builder::new(),
+      out: ssa::Builder::with_encoding(encoding),
+      select_threshold: threshold,
+      scope: Vec::new(),
+      values: Vec::new(),
+      rets: Vec::new(),
+      functions: Vec::new(),
+      callees: Vec::new(),
+      loops: Vec::new(),
+      types: mir::typecheck::Types::new(),
     }
   }
+
+  pub fn view(&self) -> &[u8] {
+    self.out.view()
+  }
 }
 
-pub fn compile(fun: &mir::Function<'_>) {
+pub fn compile(fun: &mir::Function<'_>, names: &mir::Interner<'_>) {
   let mut env = Env::new();
+  env.types = typecheck(core::slice::from_ref(fun), names);
+  compile_function(&mut env, fun);
+  ssa::display(env.view());
+}
+
+// Compiles a sequence of functions that may call each other by name. The
+// `i`th function in the module is the `i`th in `funs`. The functions' names
+// are looked up in `names`, which must be the interner they were built with.
+
+pub fn compile_module(funs: &[mir::Function<'_>], names: &mir::Interner<'_>) -> ssa::Module {
+  compile_module_with(Env::new(), funs, names)
+}
+
+// As `compile_module`, but with the options of a fresh `env`.
+
+pub fn compile_module_with(mut env: Env, funs: &[mir::Function<'_>], names: &mir::Interner<'_>) -> ssa::Module {
+  env.types = typecheck(funs, names);
+  env.callees = vec![None; funs.iter().map(|f| f.name.0 as usize + 1).max().unwrap_or(0)];
+
+  for (i, fun) in funs.iter().enumerate() {
+    let mut params = Vec::new();
+    for &(_, t) in fun.params.iter() {
+      compile_type(t, &mut params);
+    }
+    let rets = fun.rets.iter().map(|&rets| rets.iter().map(|&t| t.into()).collect()).collect();
+    env.functions.push((params, rets));
+    // The first of several functions with the same name is the one called,
+    // as in the type checker.
+    let callee = &mut env.callees[fun.name.0 as usize];
+    if callee.is_none() {
+      *callee = Some(i as u32);
+    }
+  }
+
+  let mut offsets = Vec::with_capacity(funs.len() + 1);
+
+  for fun in funs.iter() {
+    offsets.push(env.view().len());
+    compile_function(&mut env, fun);
+  }
+
+  offsets.push(env.view().len());
+
+  let mut module = ssa::Module::new();
+
+  for (i, fun) in funs.iter().enumerate() {
+    let _ = module.add_function(names.name(fun.name), &env.view()[offsets[i] .. offsets[i + 1]]);
+  }
 
-  env.out.emit_function(1, fun.params.len() as u32);
+  module
+}
+
+// Ill-typed code is a bug in whatever produced it, so this panics with the
+// type error rather than returning it.
+
+fn typecheck(funs: &[mir::Function<'_>], names: &mir::Interner<'_>) -> mir::typecheck::Types {
+  match mir::typecheck(funs, names) {
+    Ok(types) => types,
+    Err(e) => panic!("{}", e),
+  }
+}
+
+fn bind(env: &mut Env, name: Symbol, x: Val) {
+  let i = name.0 as usize;
+  if i >= env.values.len() {
+    env.values.resize_with(i + 1, Vec::new);
+  }
+  env.values[i].push(x);
+  env.scope.push(name);
+}
+
+// Unbinds the innermost symbols until `depth` remain in scope.
 
+fn unbind(env: &mut Env, depth: usize) {
+  while env.scope.len() > depth {
+    let Some(x) = env.scope.pop() else { panic!() };
+    let _ = env.values[x.0 as usize].pop();
+  }
+}
+
+fn lookup(env: &Env, name: Symbol) -> Val {
+  match env.values.get(name.0 as usize).and_then(|xs| xs.last()) {
+    Some(x) => x.clone(),
+    None => panic!(),
+  }
+}
+
+// If the body of a function returns to its own continuation, its values are
+// returned to the function's first continuation.
+
+pub fn compile_function(env: &mut Env, fun: &mir::Function<'_>) {
+  let mut params = Vec::new();
   for &(_, t) in fun.params.iter() {
-    match t {
-      mir::Type::I64 => {
-        let _ = env.out.emit_param(ssa::Type::I64);
-      }
+    compile_type(t, &mut params);
+  }
+
+  env.out.emit_function(fun.rets.len() as u32, params.len() as u32);
+  unbind(env, 0);
+  env.rets.clear();
+  env.loops.clear();
+
+  for &(name, t) in fun.params.iter() {
+    let x = receive(env, &t.into());
+    bind(env, name, x);
+  }
+
+  for &rets in fun.rets.iter() {
+    let mut types = Vec::with_capacity(rets.len());
+    for &t in rets.iter() {
+      compile_type(t, &mut types);
+    }
+    env.out.emit_results(types.len() as u32);
+    for &t in types.iter() {
+      let _ = env.out.emit_type(t);
     }
+    env.rets.push(types);
+  }
+
+  if let Some(values) = compile_values(env, fun.body) {
+    compile_return(env, 0, &flat(&values));
   }
+}
 
-  match compile_expression(&mut env, fun.body) {
-    None => {}
-    Some((value, _)) => {
-      env.out.emit_return(0, 1);
-      env.out.emit_value(value);
+fn compile_return(env: &mut Env, index: u32, values: &[(Value, ssa::Type)]) {
+  let rets = &env.rets[index as usize];
+  assert!(values.len() == rets.len());
+  assert!(values.iter().zip(rets.iter()).all(|(&(_, t), &u)| t == u));
+  env.out.emit_return(index, values.len() as u32);
+  for &(x, _) in values.iter() {
+    env.out.emit_value(x);
+  }
+}
+
+// A call to another function in the module. An expression has a single
+// continuation, which receives the results of the callee's first in a kont
+// block. Each of the callee's other continuations is routed to the caller's
+// continuation with the same index, by a kont block that returns its values
+// there. A callee without continuations never returns.
+
+fn compile_call(env: &mut Env, name: Symbol, args: &[Expression<'_>]) -> Option<Vec<Val>> {
+  let Some(&Some(index)) = env.callees.get(name.0 as usize) else { panic!() };
+  let index = index as usize;
+
+  let mut xs = Vec::new();
+  for &arg in args.iter() {
+    flatten(&compile_values(env, arg)?, &mut xs);
+  }
+
+  let (params, rets) = &env.functions[index];
+  assert!(xs.len() == params.len());
+  assert!(xs.iter().zip(params.iter()).all(|(&(_, t), &u)| t == u));
+  let rets = rets.clone();
+
+  env.out.emit_call(index as u32, xs.len() as u32, rets.len() as u32);
+  for &(x, _) in xs.iter() {
+    env.out.emit_value(x);
+  }
+
+  let points: Vec<_> = rets.iter().map(|_| env.out.emit_label(Label(0))).collect();
+
+  // The first continuation's block is last, so that code continues there.
+  for k in (0 .. rets.len()).rev() {
+    let mut n = Vec::new();
+    for t in rets[k].iter() {
+      compile_owned_type(t, &mut n);
     }
+    let label = env.out.emit_kont(n.len() as u32);
+    env.out.patch_label(points[k], label);
+    let ys: Vec<_> = rets[k].iter().map(|t| receive(env, t)).collect();
+    if k == 0 {
+      return Some(ys);
+    }
+    compile_return(env, k as u32, &flat(&ys));
+  }
+
+  None
+}
+
+pub fn compile_scalar_type(t: mir::ScalarType) -> ssa::Type {
+  match t {
+    mir::ScalarType::Bool => ssa::Type::BOOL,
+    mir::ScalarType::I5 => ssa::Type::I5,
+    mir::ScalarType::I6 => ssa::Type::I6,
+    mir::ScalarType::I32 => ssa::Type::I32,
+    mir::ScalarType::I64 => ssa::Type::I64,
   }
+}
+
+// Appends the ssa types of the scalar components of `t` to `out`.
 
-  ssa::display(env.out.view());
+pub fn compile_type(t: mir::Type<'_>, out: &mut Vec<ssa::Type>) {
+  match t {
+    mir::Type::Scalar(t) => out.push(compile_scalar_type(t)),
+    mir::Type::Tuple(ts) => for &t in ts.iter() { compile_type(t, out); },
+  }
 }
 
+fn compile_owned_type(t: &OwnedType, out: &mut Vec<ssa::Type>) {
+  match t {
+    &OwnedType::Scalar(t) => out.push(compile_scalar_type(t)),
+    OwnedType::Tuple(ts) => for t in ts.iter() { compile_owned_type(t, out); },
+  }
+}
 
-// For now, an expression either evaluates to a single typed ssa value, or
-// doesn't return to its continuation at all.
-//
-// Later we will want to extend this to
-// - aggregate types
-// - zero or multiple return values
-// - two or more continuations
+// An estimate of the number of instructions needed to evaluate an
+// expression, or `None` if the expression isn't cheap and side-effect free,
+// in which case it must stay under a branch.
 
-pub fn compile_expression(env: &mut Env, exp: Expression<'_>) -> Option<(ssa::Value, ssa::Type)> {
+fn select_cost(exp: Expression<'_>) -> Option<u32> {
+  match exp {
+    Expression::ConstBool(_) | Expression::ConstI32(_) | Expression::ConstI64(_) => {
+      Some(1)
+    }
+    Expression::Variable(_) => {
+      Some(0)
+    }
+    Expression::Call(&mir::Call { function, args }) => {
+      let _ = function.primitive()?;
+      let mut cost = 1;
+      for &x in args.iter() {
+        cost += select_cost(x)?;
+      }
+      Some(cost)
+    }
+    Expression::If(&mir::If { condition, if_true, if_false }) => {
+      Some(select_cost(condition)? + select_cost(if_true)? + select_cost(if_false)? + 1)
+    }
+    Expression::And(&mir::Logical { left, right }) | Expression::Or(&mir::Logical { left, right }) => {
+      Some(select_cost(left)? + select_cost(right)? + 1)
+    }
+    Expression::Tuple(xs) => {
+      let mut cost = 0;
+      for &x in xs.iter() {
+        cost += select_cost(x)?;
+      }
+      Some(cost)
+    }
+    Expression::Field(&mir::Field { tuple, .. }) => {
+      select_cost(tuple)
+    }
+    _ => {
+      None
+    }
+  }
+}
+
+fn is_select_candidate(env: &Env, if_true: Expression<'_>, if_false: Expression<'_>) -> bool {
+  match (select_cost(if_true), select_cost(if_false)) {
+    (Some(a), Some(b)) => a + b <= env.select_threshold,
+    _ => false,
+  }
+}
+
+
+// An expression either evaluates to a list of typed ssa values, or doesn't
+// return to its continuation at all. Tuples are flattened into their scalar
+// components.
+
+pub fn compile_expression(env: &mut Env, exp: Expression<'_>) -> Option<Vec<(Value, ssa::Type)>> {
+  compile_values(env, exp).map(|xs| flat(&xs))
+}
+
+// As `compile_expression`, but keeping the structure of tuples.
+
+fn compile_values(env: &mut Env, exp: Expression<'_>) -> Option<Vec<Val>> {
   match exp {
     Expression::ConstBool(p) => {
-      Some((env.out.emit_const_bool(p), ssa::Type::BOOL))
+      Some(vec![Val::Scalar(env.out.emit_const_bool(p), ssa::Type::BOOL)])
+    }
+    Expression::ConstI32(n) => {
+      Some(vec![Val::Scalar(env.out.emit_const_i32(n), ssa::Type::I32)])
     }
     Expression::ConstI64(n) => {
-      Some((env.out.emit_const_i64(n), ssa::Type::I64))
+      Some(vec![Val::Scalar(env.out.emit_const_i64(n), ssa::Type::I64)])
     }
-    Expression::Call(&mir::Call { function: Symbol(b"add.i64"), args: &[x, y] }) => {
-      let (x, t) = compile_expression(env, x)?;
-      assert!(t == ssa::Type::I64);
-      let (y, t) = compile_expression(env, y)?;
-      assert!(t == ssa::Type::I64);
-      Some((env.out.emit_op2(ssa::Op2::ADD_I64, x, y), ssa::Type::I64))
+    Expression::Variable(name) => {
+      Some(vec![lookup(env, name)])
+    }
+    Expression::Call(&mir::Call { function, args }) => {
+      match (function.primitive(), args) {
+        (Some(mir::Primitive::Op1(op)), &[x]) => {
+          let (x, t) = compile_scalar(env, x)?;
+          assert!(t == op.arg_type());
+          Some(vec![Val::Scalar(env.out.emit_op1(op, x), op.ret_type())])
+        }
+        (Some(mir::Primitive::Op2(op)), &[x, y]) => {
+          let (a, b) = op.arg_types();
+          let (x, t) = compile_scalar(env, x)?;
+          assert!(t == a);
+          let (y, t) = compile_scalar(env, y)?;
+          assert!(t == b);
+          Some(vec![Val::Scalar(env.out.emit_op2(op, x, y), op.ret_type())])
+        }
+        (None, args) => {
+          compile_call(env, function, args)
+        }
+        _ => {
+          panic!()
+        }
+      }
+    }
+    Expression::Do(stmts) => {
+      let depth = env.scope.len();
+      for &stmt in stmts.iter() {
+        if compile_statement(env, stmt).is_none() {
+          unbind(env, depth);
+          return None;
+        }
+      }
+      unbind(env, depth);
+      Some(Vec::new())
     }
     Expression::If(&mir::If { condition, if_true, if_false }) => {
-      let (p, t) = compile_expression(env, condition)?;
+      let (p, t) = compile_scalar(env, condition)?;
       assert!(t == ssa::Type::BOOL);
+
+      if is_select_candidate(env, if_true, if_false) {
+        let xs = compile_values(env, if_true)?;
+        let ys = compile_values(env, if_false)?;
+        let (xs0, ys0) = (flat(&xs), flat(&ys));
+        assert!(xs0.len() == ys0.len());
+        let mut zs = Vec::with_capacity(xs0.len());
+        for (&(x, t0), &(y, t1)) in xs0.iter().zip(ys0.iter()) {
+          assert!(t0 == t1);
+          zs.push((env.out.emit_select(p, x, y), t0));
+        }
+        return Some(rebuild(&xs, &mut zs.into_iter()));
+      }
+
       let (a, b) = env.out.emit_if(p, Label(0), Label(0));
 
       let case0 = 'arm: {
         let label = env.out.emit_case();
         env.out.patch_label(b, label);
-        let Some((x, t)) = compile_expression(env, if_false) else { break 'arm None; };
-        let point = env.out.emit_goto(Label(0), 1);
-        env.out.emit_value(x);
-        Some((t, point))
+        let Some(xs) = compile_values(env, if_false) else { break 'arm None; };
+        let point = compile_goto(env, Label(0), &flat(&xs));
+        Some((xs, point))
       };
 
       let case1 = 'arm: {
         let label = env.out.emit_case();
         env.out.patch_label(a, label);
-        let Some((x, t)) = compile_expression(env, if_true) else { break 'arm None; };
-        let point = env.out.emit_goto(Label(0), 1);
-        env.out.emit_value(x);
-        Some((t, point))
+        let Some(xs) = compile_values(env, if_true) else { break 'arm None; };
+        let point = compile_goto(env, Label(0), &flat(&xs));
+        Some((xs, point))
       };
 
-      match [case0, case1] {
-        [None, None] => {
-          None
-        }
-        [Some((t, point)), None] | [None, Some((t, point))] => {
-          let label = env.out.emit_join(1);
-          env.out.patch_label(point, label);
-          Some((env.out.emit_param(t), t))
-        }
-        [Some((t0, point0)), Some((t1, point1))] => {
-          assert!(t0 == t1);
-          let label = env.out.emit_join(1);
-          env.out.patch_label(point0, label);
-          env.out.patch_label(point1, label);
-          Some((env.out.emit_param(t0), t0))
-        }
+      let (xs, points) =
+        match (case0, case1) {
+          (None, None) => {
+            return None;
+          }
+          (Some((xs, point)), None) | (None, Some((xs, point))) => {
+            (xs, [Some(point), None])
+          }
+          (Some((xs, point0)), Some((ys, point1))) => {
+            let (xs0, ys0) = (flat(&xs), flat(&ys));
+            assert!(xs0.len() == ys0.len());
+            assert!(xs0.iter().zip(ys0.iter()).all(|(&(_, t0), &(_, t1))| t0 == t1));
+            (xs, [Some(point0), Some(point1)])
+          }
+        };
+
+      let n = flat(&xs).len();
+      let label = env.out.emit_join(n as u32);
+      for point in points.into_iter().flatten() {
+        env.out.patch_label(point, label);
+      }
+
+      // The join's parameters have the types the checker inferred for the
+      // whole `if`.
+      match env.types.get(exp).map(|ts| ts.to_vec()) {
+        Some(ts) => Some(ts.iter().map(|t| receive(env, t)).collect()),
+        None => Some(receive_like(env, &xs)),
       }
     }
+    Expression::And(&mir::Logical { left, right }) | Expression::Or(&mir::Logical { left, right }) => {
+      let is_and = matches!(exp, Expression::And(_));
+      let (p, t) = compile_scalar(env, left)?;
+      assert!(t == ssa::Type::BOOL);
+
+      // A cheap `right` is evaluated either way, like the arms of a select.
+      if select_cost(right).is_some_and(|cost| cost <= env.select_threshold) {
+        let (q, t) = compile_scalar(env, right)?;
+        assert!(t == ssa::Type::BOOL);
+        let op = if is_and { ssa::Op2::AND_BOOL } else { ssa::Op2::OR_BOOL };
+        return Some(vec![Val::Scalar(env.out.emit_op2(op, p, q), ssa::Type::BOOL)]);
+      }
+
+      let (a, b) = env.out.emit_if(p, Label(0), Label(0));
+      let (short, long) = if is_and { (b, a) } else { (a, b) };
+
+      // Where `left` decides the result, it is the result.
+      let label = env.out.emit_case();
+      env.out.patch_label(short, label);
+      let point0 = compile_goto(env, Label(0), &[(p, ssa::Type::BOOL)]);
+
+      let label = env.out.emit_case();
+      env.out.patch_label(long, label);
+      let point1 =
+        compile_scalar(env, right).map(|(q, t)| {
+          assert!(t == ssa::Type::BOOL);
+          compile_goto(env, Label(0), &[(q, t)])
+        });
+
+      let label = env.out.emit_join(1);
+      for point in [Some(point0), point1].into_iter().flatten() {
+        env.out.patch_label(point, label);
+      }
+      Some(vec![receive(env, &OwnedType::BOOL)])
+    }
+    Expression::Loop(&mir::Loop { name, bindings, body }) => {
+      // The loop header is a join block, which is entered with the initial
+      // values of the bindings and again by every `goto` to the loop.
+      let mut xs = Vec::with_capacity(bindings.len());
+      for &(_, exp) in bindings.iter() {
+        xs.push(compile_value(env, exp)?);
+      }
+      let xs0 = flat(&xs);
+      let point = compile_goto(env, Label(0), &xs0);
+      let label = env.out.emit_join(xs0.len() as u32);
+      env.out.patch_label(point, label);
+
+      let depth = env.scope.len();
+      let ys = receive_like(env, &xs);
+      for (&(x, _), y) in bindings.iter().zip(ys.into_iter()) {
+        bind(env, x, y);
+      }
+
+      env.loops.push((name, label, xs0.iter().map(|&(_, t)| t).collect()));
+      let ys = compile_values(env, body);
+      let _ = env.loops.pop();
+      unbind(env, depth);
+      ys
+    }
+    Expression::Tuple(exps) => {
+      let mut xs = Vec::with_capacity(exps.len());
+      for &exp in exps.iter() {
+        xs.push(compile_value(env, exp)?);
+      }
+      Some(vec![Val::Tuple(xs)])
+    }
+    Expression::Field(&mir::Field { tuple, index }) => {
+      match compile_value(env, tuple)? {
+        Val::Tuple(mut xs) => Some(vec![xs.swap_remove(index as usize)]),
+        Val::Scalar(..) => panic!(),
+      }
+    }
+  }
+}
+
+fn compile_goto(env: &mut Env, label: Label, values: &[(Value, ssa::Type)]) -> ssa::PatchPoint {
+  let point = env.out.emit_goto(label, values.len() as u32);
+  for &(x, _) in values.iter() {
+    env.out.emit_value(x);
+  }
+  point
+}
+
+// Compiles an expression that evaluates to exactly one value.
+
+fn compile_value(env: &mut Env, exp: Expression<'_>) -> Option<Val> {
+  let mut values = compile_values(env, exp)?;
+  assert!(values.len() == 1);
+  values.pop()
+}
+
+// Compiles an expression that evaluates to exactly one scalar.
+
+fn compile_scalar(env: &mut Env, exp: Expression<'_>) -> Option<(Value, ssa::Type)> {
+  Some(scalar(compile_value(env, exp)?))
+}
+
+// Returns `None` if the statement doesn't return to its continuation.
+
+fn compile_statement(env: &mut Env, stmt: Statement<'_>) -> Option<()> {
+  match stmt {
+    Statement::Let(name, exp) => {
+      let x = compile_value(env, exp)?;
+      bind(env, name, x);
+      Some(())
+    }
+    Statement::Return(index, exps) => {
+      let mut values = Vec::new();
+      for &exp in exps.iter() {
+        flatten(&compile_values(env, exp)?, &mut values);
+      }
+      compile_return(env, index, &values);
+      None
+    }
+    Statement::Goto(name, exps) => {
+      let mut values = Vec::new();
+      for &exp in exps.iter() {
+        flatten(&compile_values(env, exp)?, &mut values);
+      }
+      let Some((_, label, types)) = env.loops.iter().rev().find(|&&(x, _, _)| x == name) else { panic!() };
+      assert!(values.len() == types.len());
+      assert!(values.iter().zip(types.iter()).all(|(&(_, t), &u)| t == u));
+      let label = *label;
+      let _ = compile_goto(env, label, &values);
+      None
+    }
     _ => {
       panic!()
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::arena::Arena;
+  use crate::interp;
+
+  // The number of selects and of branches in compiled code.
+
+  fn shape(code: &[u8]) -> (usize, usize) {
+    let mut n = (0, 0);
+    for inst in ssa::Reader::new(code) {
+      match inst {
+        Ok((_, ssa::Instruction::Select(..))) => { n.0 += 1; }
+        Ok((_, ssa::Instruction::If(..))) => { n.1 += 1; }
+        _ => {}
+      }
+    }
+    n
+  }
+
+  // $f returns $n + 1 if $n < $m and $m otherwise, in an env with the given
+  // select threshold.
+
+  fn min_succ(threshold: u32) -> ssa::Module {
+    let arena = Arena::new();
+    let names = mir::Interner::new(&arena);
+    let b = mir::Builder::new(&names);
+    let (f, n, m) = (b.symbol("f"), b.symbol("n"), b.symbol("m"));
+    let is_lt = Symbol::op2(ssa::Op2::IS_LT_S_I64);
+    let add = Symbol::op2(ssa::Op2::ADD_I64);
+    let params = [(n, mir::Type::I64), (m, mir::Type::I64)];
+    let rets: [&[mir::Type<'_>]; 1] = [&[mir::Type::I64]];
+    let body = b.if_(b.call(is_lt, &[b.variable(n), b.variable(m)]), b.call(add, &[b.variable(n), Expression::ConstI64(1)]), b.variable(m));
+    let funs = [b.function(f, &params, &rets, mir::EffectType::PURE, body)];
+    compile_module_with(Env::with_select_threshold(threshold), &funs, &names)
+  }
+
+  #[test]
+  fn cheap_ifs_are_selects() {
+    // The arms cost 2: an add and its constant.
+    for (threshold, shape) in [(2, (1, 0)), (1, (0, 1))] {
+      let module = min_succ(threshold);
+      assert!(ssa::verify(module.code()).is_ok());
+      assert!(self::shape(module.code()) == shape);
+      assert!(matches!(interp::run(module.code(), 0, &[1, 5]), Ok(interp::Return { values, .. }) if values == [2]));
+      assert!(matches!(interp::run(module.code(), 0, &[7, 5]), Ok(interp::Return { values, .. }) if values == [5]));
+    }
+  }
+
+  #[test]
+  fn select_costs() {
+    let arena = Arena::new();
+    let names = mir::Interner::new(&arena);
+    let b = mir::Builder::new(&names);
+    let (f, n) = (b.symbol("f"), b.symbol("n"));
+    let add = Symbol::op2(ssa::Op2::ADD_I64);
+    assert!(select_cost(b.variable(n)) == Some(0));
+    assert!(select_cost(Expression::ConstI64(1)) == Some(1));
+    assert!(select_cost(b.call(add, &[b.variable(n), Expression::ConstI64(1)])) == Some(2));
+    assert!(select_cost(b.if_(Expression::ConstBool(true), b.variable(n), Expression::ConstI64(1))) == Some(3));
+    // Calls to functions may have effects, and returns leave the function.
+    assert!(select_cost(b.call(f, &[b.variable(n)])).is_none());
+    assert!(select_cost(b.do_(&[b.return_(0, &[b.variable(n)])])).is_none());
+  }
+
+  // $f returns #1 if $n = 0 and/or `right` holds, and #2 otherwise, where
+  // `right` is a call to $g, which returns $n to its second continuation,
+  // or, if it's cheap, whether $n = 1.
+
+  fn logical(and: bool, expensive: bool) -> ssa::Module {
+    let arena = Arena::new();
+    let names = mir::Interner::new(&arena);
+    let b = mir::Builder::new(&names);
+    let (f, g, n) = (b.symbol("f"), b.symbol("g"), b.symbol("n"));
+    let is_eq = Symbol::op2(ssa::Op2::IS_EQ_I64);
+    let params = [(n, mir::Type::I64)];
+    let zero = b.call(is_eq, &[b.variable(n), Expression::ConstI64(0)]);
+    let right = if expensive { b.call(g, &[b.variable(n)]) } else { b.call(is_eq, &[b.variable(n), Expression::ConstI64(1)]) };
+    let body = b.if_(if and { b.and(zero, right) } else { b.or(zero, right) }, Expression::ConstI64(1), Expression::ConstI64(2));
+    let funs = [
+      b.function(f, &params, &[&[mir::Type::I64], &[mir::Type::I64]], mir::EffectType::PURE, body),
+      b.function(g, &params, &[&[mir::Type::BOOL], &[mir::Type::I64]], mir::EffectType::PURE, b.do_(&[b.return_(1, &[b.variable(n)])])),
+    ];
+    compile_module(&funs, &names)
+  }
+
+  #[test]
+  fn and_or_short_circuit() {
+    let and = logical(true, true);
+    let or = logical(false, true);
+    for module in [&and, &or] {
+      assert!(ssa::verify(module.code()).is_ok());
+      assert!(shape(module.code()) == (1, 1));
+    }
+    // $g is only called where $n = 0 doesn't decide the result.
+    assert!(matches!(interp::run(and.code(), 0, &[5]), Ok(interp::Return { index: 0, values }) if values == [2]));
+    assert!(matches!(interp::run(and.code(), 0, &[0]), Ok(interp::Return { index: 1, values }) if values == [0]));
+    assert!(matches!(interp::run(or.code(), 0, &[0]), Ok(interp::Return { index: 0, values }) if values == [1]));
+    assert!(matches!(interp::run(or.code(), 0, &[5]), Ok(interp::Return { index: 1, values }) if values == [5]));
+  }
+
+  #[test]
+  fn cheap_and_or_are_bool_ops() {
+    for (and, results) in [(true, [2, 2, 2]), (false, [1, 1, 2])] {
+      let module = logical(and, false);
+      assert!(ssa::verify(module.code()).is_ok());
+      assert!(shape(module.code()) == (1, 0));
+      for (n, x) in [0, 1, 2].into_iter().zip(results) {
+        assert!(matches!(interp::run(module.code(), 0, &[n]), Ok(interp::Return { index: 0, values }) if values == [x]));
+      }
+    }
+  }
+
+  // $f returns #1 to its second continuation when $n is zero, and $n to its
+  // first otherwise, and $g adds one to what $f returns to the first.
+
+  #[test]
+  fn calls_route_other_continuations() {
+    let arena = Arena::new();
+    let names = mir::Interner::new(&arena);
+    let b = mir::Builder::new(&names);
+    let (f, g, n) = (b.symbol("f"), b.symbol("g"), b.symbol("n"));
+    let is_eq = Symbol::op2(ssa::Op2::IS_EQ_I64);
+    let add = Symbol::op2(ssa::Op2::ADD_I64);
+    let params = [(n, mir::Type::I64)];
+    let rets: [&[mir::Type<'_>]; 2] = [&[mir::Type::I64], &[mir::Type::I64]];
+    let zero = b.call(is_eq, &[b.variable(n), Expression::ConstI64(0)]);
+    let funs = [
+      b.function(g, &params, &rets, mir::EffectType::PURE, b.call(add, &[b.call(f, &[b.variable(n)]), Expression::ConstI64(1)])),
+      b.function(f, &params, &rets, mir::EffectType::PURE, b.if_(zero, b.do_(&[b.return_(1, &[Expression::ConstI64(1)])]), b.variable(n))),
+    ];
+    let module = compile_module(&funs, &names);
+    assert!(ssa::verify(module.code()).is_ok());
+    assert!(matches!(interp::run(module.code(), 0, &[0]), Ok(interp::Return { index: 1, values }) if values == [1]));
+    assert!(matches!(interp::run(module.code(), 0, &[5]), Ok(interp::Return { index: 0, values }) if values == [6]));
+  }
+}
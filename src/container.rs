@@ -0,0 +1,407 @@
+//! on-disk format for ssa modules
+//!
+//! A file is a header, a section table, and the sections' contents. All
+//! integers are little-endian u32s.
+//!
+//! ```text
+//! header:   magic [u8; 8], version, number of sections
+//! section:  kind, offset, size, checksum
+//! ```
+//!
+//! Offsets are from the start of the file, and checksums are FNV-1a over a
+//! section's contents. The code, types, names and functions sections are
+//! required. Readers skip sections of kinds they don't know, so that new
+//! kinds can be added without changing the version.
+//!
+//! The debug section is optional, and gives the source span of each function,
+//! so that tools can point back at the source. `read_module` ignores it, and
+//! `read_debug` reads it.
+
+use crate::mir::Span;
+use crate::ssa;
+use crate::ssa::FunctionEntry;
+
+pub const MAGIC: [u8; 8] = *b"\x7flilac\0\0";
+
+pub const VERSION: u32 = 1;
+
+pub const NO_FILE: u32 = u32::MAX;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SectionKind(pub u32);
+
+impl SectionKind {
+  pub const CODE: Self = Self(1);
+  pub const TYPES: Self = Self(2);
+  pub const NAMES: Self = Self(3);
+  // Five u32s per function: name offset, name size, code offset, code size,
+  // and signature offset, as in `ssa::FunctionEntry`.
+  pub const FUNCTIONS: Self = Self(4);
+  // Three u32s per function, in the same order: the span's file, start and
+  // end. A function without a span has a file of `NO_FILE`.
+  pub const DEBUG: Self = Self(5);
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::CODE => "code",
+      Self::TYPES => "types",
+      Self::NAMES => "names",
+      Self::FUNCTIONS => "functions",
+      Self::DEBUG => "debug",
+      _ => "unknown",
+    }
+  }
+}
+
+pub enum Error {
+  Truncated { offset: usize, size: usize },
+  BadMagic,
+  UnsupportedVersion(u32),
+  SectionOutOfBounds(SectionKind),
+  DuplicateSection(SectionKind),
+  MissingSection(SectionKind),
+  BadChecksum { kind: SectionKind, expected: u32, actual: u32 },
+  BadFunction(u32),
+  BadDebugInfo,
+}
+
+impl core::fmt::Display for Error {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::Truncated { offset, size } =>
+        write!(f, "truncated: expected {} bytes at byte {}", size, offset),
+      Self::BadMagic =>
+        write!(f, "not a lilac module"),
+      Self::UnsupportedVersion(v) =>
+        write!(f, "unsupported version {}, expected {}", v, VERSION),
+      Self::SectionOutOfBounds(k) =>
+        write!(f, "{} section extends past the end of the file", k.name()),
+      Self::DuplicateSection(k) =>
+        write!(f, "duplicate {} section", k.name()),
+      Self::MissingSection(k) =>
+        write!(f, "missing {} section", k.name()),
+      Self::BadChecksum { kind, expected, actual } =>
+        write!(f, "{} section checksum is {:#010x}, expected {:#010x}", kind.name(), actual, expected),
+      Self::BadFunction(i) =>
+        write!(f, "function {} is out of bounds", i),
+      Self::BadDebugInfo =>
+        write!(f, "debug section doesn't match the functions section"),
+    }
+  }
+}
+
+pub fn checksum(data: &[u8]) -> u32 {
+  let mut h = 0x811c_9dc5u32;
+  for &b in data.iter() {
+    h ^= b as u32;
+    h = h.wrapping_mul(0x0100_0193);
+  }
+  h
+}
+
+fn put_u32(out: &mut Vec<u8>, x: u32) {
+  out.extend_from_slice(&x.to_le_bytes());
+}
+
+pub fn write_module(module: &ssa::Module) -> Vec<u8> {
+  write(module, None)
+}
+
+// As `write_module`, but with a debug section giving the span of the `i`th
+// function as `spans[i]`, e.g. from `mir::Spans::function`.
+
+pub fn write_module_with_debug(module: &ssa::Module, spans: &[Option<Span>]) -> Vec<u8> {
+  assert!(spans.len() == module.len() as usize);
+  let mut debug = Vec::with_capacity(12 * spans.len());
+
+  for span in spans.iter() {
+    let span = span.unwrap_or(Span { file: NO_FILE, start: 0, end: 0 });
+    put_u32(&mut debug, span.file);
+    put_u32(&mut debug, span.start);
+    put_u32(&mut debug, span.end);
+  }
+
+  write(module, Some(&debug))
+}
+
+fn write(module: &ssa::Module, debug: Option<&[u8]>) -> Vec<u8> {
+  let mut functions = Vec::new();
+
+  for i in 0 .. module.len() {
+    let e = module.entry(i);
+    put_u32(&mut functions, e.name.0);
+    put_u32(&mut functions, e.name.1);
+    put_u32(&mut functions, e.offset);
+    put_u32(&mut functions, e.size);
+    put_u32(&mut functions, e.signature);
+  }
+
+  let mut sections: Vec<(SectionKind, &[u8])> = vec![
+    (SectionKind::CODE, module.code()),
+    (SectionKind::TYPES, module.types()),
+    (SectionKind::NAMES, module.strings()),
+    (SectionKind::FUNCTIONS, &functions),
+  ];
+
+  if let Some(debug) = debug {
+    sections.push((SectionKind::DEBUG, debug));
+  }
+
+  let mut out = Vec::new();
+  out.extend_from_slice(&MAGIC);
+  put_u32(&mut out, VERSION);
+  put_u32(&mut out, sections.len() as u32);
+
+  let mut offset = out.len() + 16 * sections.len();
+
+  for &(kind, data) in sections.iter() {
+    put_u32(&mut out, kind.0);
+    put_u32(&mut out, offset as u32);
+    put_u32(&mut out, data.len() as u32);
+    put_u32(&mut out, checksum(data));
+    offset += data.len();
+  }
+
+  for &(_, data) in sections.iter() {
+    out.extend_from_slice(data);
+  }
+
+  out
+}
+
+fn get(file: &[u8], offset: usize, size: usize) -> Result<&[u8], Error> {
+  match file.get(offset .. offset.saturating_add(size)) {
+    Some(data) => Ok(data),
+    None => Err(Error::Truncated { offset, size }),
+  }
+}
+
+fn get_u32(file: &[u8], offset: usize) -> Result<u32, Error> {
+  let data = get(file, offset, 4)?;
+  Ok(u32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+}
+
+// The contents of each kind of section that readers know.
+
+struct Sections<'a> {
+  code: Option<&'a [u8]>,
+  types: Option<&'a [u8]>,
+  names: Option<&'a [u8]>,
+  functions: Option<&'a [u8]>,
+  debug: Option<&'a [u8]>,
+}
+
+// Checks the header and the section table, and finds the sections.
+
+fn sections(file: &[u8]) -> Result<Sections<'_>, Error> {
+  if get(file, 0, MAGIC.len())? != MAGIC {
+    return Err(Error::BadMagic);
+  }
+
+  let version = get_u32(file, 8)?;
+
+  if version != VERSION {
+    return Err(Error::UnsupportedVersion(version));
+  }
+
+  let nsections = get_u32(file, 12)?;
+
+  let mut s = Sections { code: None, types: None, names: None, functions: None, debug: None };
+
+  for i in 0 .. nsections as usize {
+    let entry = 16 + 16 * i;
+    let kind = SectionKind(get_u32(file, entry)?);
+    let offset = get_u32(file, entry + 4)? as usize;
+    let size = get_u32(file, entry + 8)? as usize;
+    let expected = get_u32(file, entry + 12)?;
+
+    let Ok(data) = get(file, offset, size) else {
+      return Err(Error::SectionOutOfBounds(kind));
+    };
+
+    let actual = checksum(data);
+
+    if actual != expected {
+      return Err(Error::BadChecksum { kind, expected, actual });
+    }
+
+    let slot =
+      match kind {
+        SectionKind::CODE => &mut s.code,
+        SectionKind::TYPES => &mut s.types,
+        SectionKind::NAMES => &mut s.names,
+        SectionKind::FUNCTIONS => &mut s.functions,
+        SectionKind::DEBUG => &mut s.debug,
+        _ => continue,
+      };
+
+    if slot.is_some() {
+      return Err(Error::DuplicateSection(kind));
+    }
+
+    *slot = Some(data);
+  }
+
+  Ok(s)
+}
+
+pub fn read_module(file: &[u8]) -> Result<ssa::Module, Error> {
+  let Sections { code, types, names, functions, .. } = sections(file)?;
+
+  let Some(code) = code else { return Err(Error::MissingSection(SectionKind::CODE)); };
+  let Some(types) = types else { return Err(Error::MissingSection(SectionKind::TYPES)); };
+  let Some(names) = names else { return Err(Error::MissingSection(SectionKind::NAMES)); };
+  let Some(functions) = functions else { return Err(Error::MissingSection(SectionKind::FUNCTIONS)); };
+
+  let mut entries = Vec::with_capacity(functions.len() / 20);
+
+  for (i, e) in functions.chunks(20).enumerate() {
+    let i = i as u32;
+
+    if e.len() != 20 {
+      return Err(Error::BadFunction(i));
+    }
+
+    let e = FunctionEntry {
+      name: (get_u32(e, 0)?, get_u32(e, 4)?),
+      offset: get_u32(e, 8)?,
+      size: get_u32(e, 12)?,
+      signature: get_u32(e, 16)?,
+    };
+
+    let in_bounds =
+      get(names, e.name.0 as usize, e.name.1 as usize).is_ok()
+        && get(code, e.offset as usize, e.size as usize).is_ok()
+        && check_signature(types, e.signature as usize).is_ok();
+
+    if !in_bounds {
+      return Err(Error::BadFunction(i));
+    }
+
+    entries.push(e);
+  }
+
+  Ok(ssa::Module::from_parts(code.to_vec(), entries, types.to_vec(), names.to_vec()))
+}
+
+// The spans in the debug section of a module that `read_module` accepts, or
+// `None` if it has none.
+
+pub fn read_debug(file: &[u8]) -> Result<Option<Vec<Option<Span>>>, Error> {
+  let module = read_module(file)?;
+  let Some(debug) = sections(file)?.debug else { return Ok(None); };
+
+  if debug.len() != 12 * module.len() as usize {
+    return Err(Error::BadDebugInfo);
+  }
+
+  let mut spans = Vec::with_capacity(module.len() as usize);
+
+  for e in debug.chunks(12) {
+    let span = Span { file: get_u32(e, 0)?, start: get_u32(e, 4)?, end: get_u32(e, 8)? };
+    spans.push(if span.file == NO_FILE { None } else { Some(span) });
+  }
+
+  Ok(Some(spans))
+}
+
+// Checks that the signature at `offset` lies within `types`.
+
+fn check_signature(types: &[u8], offset: usize) -> Result<(), Error> {
+  let nparams = get_u32(types, offset)? as usize;
+  let mut offset = offset + 4 + nparams;
+  let nkonts = get_u32(types, offset)?;
+  offset += 4;
+
+  for _ in 0 .. nkonts {
+    let n = get_u32(types, offset)? as usize;
+    offset = offset + 4 + n;
+  }
+
+  let _ = get(types, offset, 0)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::arena::Arena;
+  use crate::compile;
+  use crate::mir;
+
+  fn file() -> Vec<u8> {
+    let arena = Arena::new();
+    let names = mir::Interner::new(&arena);
+    let fib = mir::fib(mir::Builder::new(&names));
+    write_module(&compile::compile_module(&[fib], &names))
+  }
+
+  #[test]
+  fn round_trip() {
+    let arena = Arena::new();
+    let names = mir::Interner::new(&arena);
+    let fib = mir::fib(mir::Builder::new(&names));
+    let m = compile::compile_module(&[fib], &names);
+    let Ok(n) = read_module(&write_module(&m)) else { panic!() };
+    assert!(n.code() == m.code() && n.types() == m.types() && n.strings() == m.strings());
+    assert!(n.len() == 1 && n.find(b"fib") == Some(0));
+    let (a, b) = (m.entry(0), n.entry(0));
+    assert!(a.name == b.name && a.offset == b.offset && a.size == b.size && a.signature == b.signature);
+  }
+
+  #[test]
+  fn truncated() {
+    let f = file();
+    assert!(matches!(read_module(&f[.. 4]), Err(Error::Truncated { offset: 0, size: 8 })));
+    assert!(matches!(read_module(&f[.. 14]), Err(Error::Truncated { offset: 12, size: 4 })));
+    assert!(matches!(read_module(&f[.. 18]), Err(Error::Truncated { offset: 16, size: 4 })));
+    assert!(matches!(read_module(&f[.. f.len() - 1]), Err(Error::SectionOutOfBounds(SectionKind::FUNCTIONS))));
+    for n in 0 .. f.len() {
+      assert!(read_module(&f[.. n]).is_err());
+    }
+  }
+
+  #[test]
+  fn bad_header() {
+    let mut f = file();
+    f[8 .. 12].copy_from_slice(&2u32.to_le_bytes());
+    assert!(matches!(read_module(&f), Err(Error::UnsupportedVersion(2))));
+    f[0] = b'x';
+    assert!(matches!(read_module(&f), Err(Error::BadMagic)));
+  }
+
+  #[test]
+  fn bad_checksum() {
+    let mut f = file();
+    let n = f.len();
+    f[n - 1] ^= 1;
+    let Err(Error::BadChecksum { kind, expected, actual }) = read_module(&f) else { panic!() };
+    assert!(kind == SectionKind::FUNCTIONS && expected != actual && actual == checksum(&f[n - 20 ..]));
+  }
+
+  #[test]
+  fn missing_section() {
+    let mut f = file();
+    // Make the types section one of a kind that readers skip.
+    f[32 .. 36].copy_from_slice(&99u32.to_le_bytes());
+    assert!(matches!(read_module(&f), Err(Error::MissingSection(SectionKind::TYPES))));
+    f[32 .. 36].copy_from_slice(&SectionKind::CODE.0.to_le_bytes());
+    assert!(matches!(read_module(&f), Err(Error::DuplicateSection(SectionKind::CODE))));
+  }
+
+  #[test]
+  fn debug() {
+    let arena = Arena::new();
+    let names = mir::Interner::new(&arena);
+    let b = mir::Builder::new(&names);
+    let funs = [mir::fib(b), mir::fib(b)];
+    let m = compile::compile_module(&funs, &names);
+    let span = Span { file: 1, start: 2, end: 3 };
+    let f = write_module_with_debug(&m, &[Some(span), None]);
+    assert!(read_module(&f).is_ok_and(|n| n.code() == m.code()));
+    assert!(matches!(read_debug(&f), Ok(Some(spans)) if spans == [Some(span), None]));
+    assert!(matches!(read_debug(&write_module(&m)), Ok(None)));
+    // A debug section for one function fewer than there are.
+    assert!(matches!(read_debug(&write(&m, Some(&[0; 12]))), Err(Error::BadDebugInfo)));
+  }
+}
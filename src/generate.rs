@@ -0,0 +1,1098 @@
+//! random program generation for testing
+//!
+//! Generators draw every choice from a `Source` of bytes, so that a program
+//! is determined by its input, and smaller inputs give simpler programs. This
+//! is what `shrink` relies on, and also makes the generators directly usable
+//! as fuzz targets.
+//!
+//! - `mir_module` generates well-typed mir functions, and `eval` evaluates
+//!   them directly, as a reference for compiled code
+//! - `ssa_module` generates ssa functions that verify, with aggregate types
+//!   defined at the start
+//!
+//! Calls only go to later functions and every loop counts down, so every
+//! generated program terminates.
+//!
+//! This module is only built for tests and with the `generate` feature, which
+//! the fuzz targets enable.
+
+use crate::mir;
+use crate::mir::Expression;
+use crate::mir::Interner;
+use crate::mir::Statement;
+use crate::mir::Symbol;
+use crate::ssa;
+use crate::ssa::Builder;
+use crate::ssa::Label;
+use crate::ssa::MemFlags;
+use crate::ssa::MemType;
+use crate::ssa::Op1;
+use crate::ssa::Op2;
+use crate::ssa::Type;
+use crate::ssa::TypeTable;
+use crate::ssa::Value;
+
+// Draws choices from a byte string. Once it runs out every choice is zero,
+// which generators treat as the simplest option.
+
+pub struct Source<'a>(&'a [u8]);
+
+impl<'a> Source<'a> {
+  pub fn new(data: &'a [u8]) -> Self {
+    Self(data)
+  }
+
+  pub fn u8(&mut self) -> u8 {
+    match self.0.split_first() {
+      Some((&b, rest)) => {
+        self.0 = rest;
+        b
+      }
+      None => 0,
+    }
+  }
+
+  pub fn u32(&mut self) -> u32 {
+    u32::from_le_bytes([self.u8(), self.u8(), self.u8(), self.u8()])
+  }
+
+  pub fn u64(&mut self) -> u64 {
+    self.u32() as u64 | (self.u32() as u64) << 32
+  }
+
+  pub fn bool(&mut self) -> bool {
+    self.u8() & 1 != 0
+  }
+
+  // A number in `0 .. n`, which must be positive.
+
+  pub fn below(&mut self, n: u32) -> u32 {
+    if n <= 256 { self.u8() as u32 % n } else { self.u32() % n }
+  }
+
+  pub fn choose<T: Copy>(&mut self, xs: &[T]) -> T {
+    xs[self.below(xs.len() as u32) as usize]
+  }
+
+  // A random value of type `t`, represented as described for `ssa::Op1`.
+
+  pub fn value(&mut self, t: Type) -> u64 {
+    match t {
+      Type::BOOL => self.bool() as u64,
+      Type::I5 => self.below(1 << 5) as u64,
+      Type::I6 => self.below(1 << 6) as u64,
+      Type::I7 => self.below(1 << 7) as u64,
+      Type::I32 | Type::F32 => self.u32() as u64,
+      _ => self.u64(),
+    }
+  }
+}
+
+const MIR_SCALARS: [Type; 5] = [Type::BOOL, Type::I5, Type::I6, Type::I32, Type::I64];
+
+const SCALARS: [Type; 8] =
+  [Type::BOOL, Type::I5, Type::I6, Type::I7, Type::I32, Type::I64, Type::F32, Type::F64];
+
+const MEM_TYPES: [MemType; 8] = [
+  MemType::I8_U,
+  MemType::I8_S,
+  MemType::I16_U,
+  MemType::I16_S,
+  MemType::I32,
+  MemType::I64,
+  MemType::F32,
+  MemType::F64,
+];
+
+fn mir_type(t: Type) -> mir::Type<'static> {
+  match t {
+    Type::BOOL => mir::Type::BOOL,
+    Type::I5 => mir::Type::I5,
+    Type::I6 => mir::Type::I6,
+    Type::I32 => mir::Type::I32,
+    Type::I64 => mir::Type::I64,
+    _ => panic!(),
+  }
+}
+
+fn op1s(types: &[Type]) -> Vec<Op1> {
+  (0 ..= 255)
+    .map(Op1)
+    .filter(|op| op.is_valid() && types.contains(&op.arg_type()) && types.contains(&op.ret_type()))
+    .collect()
+}
+
+fn op2s(types: &[Type]) -> Vec<Op2> {
+  (0 ..= 255)
+    .map(Op2)
+    .filter(|op| {
+      let (a, b) = op.arg_types();
+      op.is_valid() && types.contains(&a) && types.contains(&b) && types.contains(&op.ret_type())
+    })
+    .collect()
+}
+
+struct Signature {
+  params: Vec<Type>,
+  rets: Vec<Vec<Type>>,
+}
+
+struct MirGen<'a, 's, 'r> {
+  mir: mir::Builder<'a>,
+  src: &'r mut Source<'s>,
+  op1s: Vec<Op1>,
+  op2s: Vec<Op2>,
+  sigs: Vec<Signature>,
+  names: Vec<Symbol>,
+  current: usize,
+  // Variables, or the scalar fields of tuple variables.
+  scope: Vec<(Symbol, Option<u32>, Type)>,
+  next_name: u32,
+}
+
+impl<'a, 's, 'r> MirGen<'a, 's, 'r> {
+  fn name(&mut self, prefix: &str) -> Symbol {
+    self.next_name += 1;
+    self.mir.symbol(&format!("{}{}", prefix, self.next_name))
+  }
+
+  fn call(&self, function: Symbol, args: &[Expression<'a>]) -> Expression<'a> {
+    self.mir.call(function, args)
+  }
+
+  fn constant(&mut self, t: Type) -> Expression<'a> {
+    match t {
+      Type::BOOL => Expression::ConstBool(self.src.bool()),
+      // mir has no i5 or i6 constants, so they are cast from i32.
+      Type::I5 => {
+        let c = Expression::ConstI32(self.src.below(1 << 5));
+        self.call(Symbol::op1(Op1::CAST_I32_I5), &[c])
+      }
+      Type::I6 => {
+        let c = Expression::ConstI32(self.src.below(1 << 6));
+        self.call(Symbol::op1(Op1::CAST_I32_I6), &[c])
+      }
+      Type::I32 => Expression::ConstI32(self.src.u32()),
+      _ => Expression::ConstI64(self.src.u64()),
+    }
+  }
+
+  fn values(&mut self, types: &[Type], depth: u32) -> &'a [Expression<'a>] {
+    let xs: Vec<_> = types.iter().map(|&t| self.value(t, depth)).collect();
+    self.mir.arena().alloc_slice(&xs)
+  }
+
+  fn lets(&mut self, depth: u32, stmts: &mut Vec<Statement<'a>>) {
+    for _ in 0 .. self.src.below(3) {
+      if self.src.below(4) == 3 {
+        let types: Vec<_> = (0 .. self.src.below(4)).map(|_| self.src.choose(&MIR_SCALARS)).collect();
+        let exps = self.values(&types, depth);
+        let x = self.name("t");
+        stmts.push(Statement::Let(x, Expression::Tuple(exps)));
+        self.scope.extend(types.iter().enumerate().map(|(i, &t)| (x, Some(i as u32), t)));
+        continue;
+      }
+      let t = self.src.choose(&[Type::I64, Type::BOOL, Type::I32]);
+      let exp = self.value(t, depth);
+      let x = self.name("x");
+      stmts.push(Statement::Let(x, exp));
+      self.scope.push((x, None, t));
+    }
+  }
+
+  fn field(&self, tuple: Expression<'a>, index: u32) -> Expression<'a> {
+    self.mir.field(tuple, index)
+  }
+
+  // An expression that evaluates to one value of type `t`, or returns from
+  // the function.
+
+  fn value(&mut self, t: Type, depth: u32) -> Expression<'a> {
+    let choice = if depth == 0 { self.src.below(2) } else { self.src.below(10) };
+    let depth = depth.saturating_sub(1);
+
+    match choice {
+      1 => {
+        let xs: Vec<_> = self.scope.iter().filter(|&&(_, _, u)| u == t).map(|&(x, i, _)| (x, i)).collect();
+        if xs.is_empty() {
+          return self.constant(t);
+        }
+        match self.src.choose(&xs) {
+          (x, None) => Expression::Variable(x),
+          (x, Some(i)) => self.field(Expression::Variable(x), i),
+        }
+      }
+      2 => {
+        let ops: Vec<_> = self.op2s.iter().copied().filter(|op| op.ret_type() == t).collect();
+        if ops.is_empty() {
+          return self.constant(t);
+        }
+        let op = self.src.choose(&ops);
+        let (a, b) = op.arg_types();
+        let x = self.value(a, depth);
+        let y = self.value(b, depth);
+        self.call(Symbol::op2(op), &[x, y])
+      }
+      3 => {
+        let ops: Vec<_> = self.op1s.iter().copied().filter(|op| op.ret_type() == t).collect();
+        if ops.is_empty() {
+          return self.constant(t);
+        }
+        let op = self.src.choose(&ops);
+        let x = self.value(op.arg_type(), depth);
+        self.call(Symbol::op1(op), &[x])
+      }
+      4 => {
+        let condition = self.value(Type::BOOL, depth);
+        let if_true = self.value(t, depth);
+        let if_false = self.value(t, depth);
+        self.mir.if_(condition, if_true, if_false)
+      }
+      5 => {
+        let callees: Vec<_> =
+          (self.current + 1 .. self.sigs.len())
+            .filter(|&j| self.sigs[j].rets[0] == [t])
+            .collect();
+        if callees.is_empty() {
+          return self.constant(t);
+        }
+        let j = self.src.choose(&callees);
+        let params = self.sigs[j].params.clone();
+        let args = self.values(&params, depth);
+        self.call(self.names[j], args)
+      }
+      6 => {
+        // Returns early, to any of the function's continuations.
+        let k = self.src.below(self.sigs[self.current].rets.len() as u32);
+        let rets = self.sigs[self.current].rets[k as usize].clone();
+        let scope = self.scope.len();
+        let mut stmts = Vec::new();
+        self.lets(depth, &mut stmts);
+        let values = self.values(&rets, depth);
+        stmts.push(Statement::Return(k, values));
+        self.scope.truncate(scope);
+        let exit = self.mir.do_(&stmts);
+        let condition = self.value(Type::BOOL, depth);
+        let other = self.value(t, depth);
+        let (if_true, if_false) = if self.src.bool() { (exit, other) } else { (other, exit) };
+        self.mir.if_(condition, if_true, if_false)
+      }
+      7 => {
+        self.countdown(t, depth)
+      }
+      8 => {
+        let n = 1 + self.src.below(3);
+        let i = self.src.below(n);
+        let mut types: Vec<_> = (0 .. n).map(|_| self.src.choose(&MIR_SCALARS)).collect();
+        types[i as usize] = t;
+        let exps = self.values(&types, depth);
+        self.field(Expression::Tuple(exps), i)
+      }
+      9 if t == Type::BOOL => {
+        let left = self.value(Type::BOOL, depth);
+        let right = self.value(Type::BOOL, depth);
+        if self.src.bool() { self.mir.and(left, right) } else { self.mir.or(left, right) }
+      }
+      _ => {
+        self.constant(t)
+      }
+    }
+  }
+
+  // A loop with a counter `i`, and some other bindings that are updated on
+  // every iteration.
+  //
+  //   (loop $l (($i #n) ($x ...) ...)
+  //     (if (is_eq.i64 $i #0)
+  //       ...
+  //       (do (let ...) ... (goto $l ((sub.i64 $i #1) ...)))))
+
+  fn countdown(&mut self, t: Type, depth: u32) -> Expression<'a> {
+    let name = self.name("l");
+    let i = self.name("i");
+    let types: Vec<_> = (0 .. self.src.below(3)).map(|_| self.src.choose(&[Type::I64, Type::BOOL])).collect();
+
+    let mut bindings = vec![(i, Expression::ConstI64(self.src.below(4) as u64))];
+    for &u in types.iter() {
+      let exp = self.value(u, depth);
+      bindings.push((self.name("x"), exp));
+    }
+
+    let scope = self.scope.len();
+    self.scope.extend(bindings.iter().zip([Type::I64].iter().chain(types.iter())).map(|(&(x, _), &u)| (x, None, u)));
+
+    let counter = Expression::Variable(i);
+    let condition = self.call(Symbol::op2(Op2::IS_EQ_I64), &[counter, Expression::ConstI64(0)]);
+    let exit = self.value(t, depth);
+
+    let mut stmts = Vec::new();
+    self.lets(depth, &mut stmts);
+    let mut args = vec![self.call(Symbol::op2(Op2::SUB_I64), &[counter, Expression::ConstI64(1)])];
+    for &u in types.iter() {
+      args.push(self.value(u, depth));
+    }
+    stmts.push(self.mir.goto(name, &args));
+    self.scope.truncate(scope);
+
+    let step = self.mir.do_(&stmts);
+    let body = self.mir.if_(condition, exit, step);
+    self.mir.loop_(name, &bindings, body)
+  }
+
+  fn function(&mut self, index: usize) -> mir::Function<'a> {
+    self.current = index;
+    self.scope.clear();
+
+    let mut params = Vec::new();
+    for &t in self.sigs[index].params.clone().iter() {
+      let x = self.name("p");
+      params.push((x, mir_type(t)));
+      self.scope.push((x, None, t));
+    }
+
+    let mut stmts = Vec::new();
+    self.lets(3, &mut stmts);
+    let k = self.src.below(self.sigs[index].rets.len() as u32);
+    let rets = self.sigs[index].rets[k as usize].clone();
+    let values = self.values(&rets, 3);
+    stmts.push(Statement::Return(k, values));
+
+    let rets: Vec<Vec<mir::Type<'a>>> =
+      self.sigs[index].rets.iter().map(|rets| rets.iter().map(|&t| mir_type(t)).collect()).collect();
+    let rets: Vec<&[mir::Type<'a>]> = rets.iter().map(|ts| &**ts).collect();
+
+    let body = self.mir.do_(&stmts);
+    self.mir.function(self.names[index], &params, &rets, mir::EffectType::DIVERGE, body)
+  }
+}
+
+// Generates a module of one to four well-typed functions. Only the first may
+// have more than one continuation, as the others are called as expressions.
+
+pub fn mir_module<'a>(names: &'a Interner<'a>, src: &mut Source<'_>) -> Vec<mir::Function<'a>> {
+  let mut g = MirGen {
+    mir: mir::Builder::new(names),
+    src,
+    op1s: op1s(&MIR_SCALARS),
+    op2s: op2s(&MIR_SCALARS),
+    sigs: Vec::new(),
+    names: Vec::new(),
+    current: 0,
+    scope: Vec::new(),
+    next_name: 0,
+  };
+
+  let n = 1 + g.src.below(4) as usize;
+
+  for i in 0 .. n {
+    let params = (0 .. g.src.below(4)).map(|_| g.src.choose(&MIR_SCALARS)).collect();
+    let rets =
+      if i == 0 {
+        (0 .. 1 + g.src.below(2)).map(|_| (0 .. g.src.below(3)).map(|_| g.src.choose(&MIR_SCALARS)).collect()).collect()
+      } else {
+        // Any function may call a later one, so they all share the first
+        // function's continuations after their own first.
+        let mut rets = vec![vec![g.src.choose(&MIR_SCALARS)]];
+        rets.extend(g.sigs[0].rets[1 ..].iter().cloned());
+        rets
+      };
+    g.sigs.push(Signature { params, rets });
+    let name = g.name("f");
+    g.names.push(name);
+  }
+
+  (0 .. n).map(|i| g.function(i)).collect()
+}
+
+// Random arguments for a function, as scalars.
+
+pub fn mir_args(src: &mut Source<'_>, fun: &mir::Function<'_>) -> Vec<u64> {
+  let mut types = Vec::new();
+  for &(_, t) in fun.params.iter() {
+    crate::compile::compile_type(t, &mut types);
+  }
+  types.iter().map(|&t| src.value(t)).collect()
+}
+
+pub enum EvalError {
+  OutOfFuel,
+}
+
+#[derive(Clone)]
+enum Datum {
+  Scalar(u64),
+  Tuple(Vec<Datum>),
+}
+
+impl Datum {
+  fn scalar(&self) -> u64 {
+    match self {
+      &Self::Scalar(x) => x,
+      Self::Tuple(_) => panic!(),
+    }
+  }
+}
+
+fn flatten(xs: &[Datum], out: &mut Vec<u64>) {
+  for x in xs.iter() {
+    match x {
+      &Datum::Scalar(x) => out.push(x),
+      Datum::Tuple(ys) => flatten(ys, out),
+    }
+  }
+}
+
+fn unflatten(t: mir::Type<'_>, xs: &mut impl Iterator<Item = u64>) -> Datum {
+  match t {
+    mir::Type::Scalar(_) => Datum::Scalar(xs.next().unwrap()),
+    mir::Type::Tuple(ts) => Datum::Tuple(ts.iter().map(|&t| unflatten(t, xs)).collect()),
+  }
+}
+
+enum Exit {
+  Return(u32, Vec<Datum>),
+  Goto(Symbol, Vec<Datum>),
+  OutOfFuel,
+}
+
+// Evaluates the `index`th function directly, returning the index of the
+// continuation it returned to and the values it passed, or stopping after
+// `fuel` steps. Tuples are passed as their scalar components, in order, as
+// in compiled code.
+
+pub fn eval(funs: &[mir::Function<'_>], index: usize, args: &[u64], fuel: u64) -> Result<(u32, Vec<u64>), EvalError> {
+  let mut fuel = fuel;
+  let mut xs = args.iter().copied();
+  let args: Vec<_> = funs[index].params.iter().map(|&(_, t)| unflatten(t, &mut xs)).collect();
+  let (k, vs) =
+    match eval_function(funs, &funs[index], &args, &mut fuel) {
+      Ok(vs) => (0, vs),
+      Err(Exit::Return(k, vs)) => (k, vs),
+      Err(Exit::Goto(..)) => panic!(),
+      Err(Exit::OutOfFuel) => return Err(EvalError::OutOfFuel),
+    };
+  let mut out = Vec::new();
+  flatten(&vs, &mut out);
+  Ok((k, out))
+}
+
+type Scope = Vec<(Symbol, Datum)>;
+
+fn eval_function<'a>(funs: &[mir::Function<'a>], fun: &mir::Function<'a>, args: &[Datum], fuel: &mut u64) -> Result<Vec<Datum>, Exit> {
+  let mut scope = fun.params.iter().zip(args.iter()).map(|(&(x, _), v)| (x, v.clone())).collect();
+  eval_expression(funs, &mut scope, fun.body, fuel)
+}
+
+fn eval_values<'a>(funs: &[mir::Function<'a>], scope: &mut Scope, exps: &[Expression<'a>], fuel: &mut u64) -> Result<Vec<Datum>, Exit> {
+  let mut xs = Vec::with_capacity(exps.len());
+  for &exp in exps.iter() {
+    xs.extend(eval_expression(funs, scope, exp, fuel)?);
+  }
+  Ok(xs)
+}
+
+fn eval_expression<'a>(funs: &[mir::Function<'a>], scope: &mut Scope, exp: Expression<'a>, fuel: &mut u64) -> Result<Vec<Datum>, Exit> {
+  if *fuel == 0 {
+    return Err(Exit::OutOfFuel);
+  }
+
+  *fuel -= 1;
+
+  match exp {
+    Expression::ConstBool(p) => Ok(vec![Datum::Scalar(p as u64)]),
+    Expression::ConstI32(c) => Ok(vec![Datum::Scalar(c as u64)]),
+    Expression::ConstI64(c) => Ok(vec![Datum::Scalar(c)]),
+    Expression::Variable(x) => {
+      match scope.iter().rev().find(|&&(y, _)| y == x) {
+        Some((_, v)) => Ok(vec![v.clone()]),
+        None => panic!(),
+      }
+    }
+    Expression::Call(&mir::Call { function: f, args }) => {
+      let xs = eval_values(funs, scope, args, fuel)?;
+      match f.primitive() {
+        Some(mir::Primitive::Op1(op)) => return Ok(vec![Datum::Scalar(op.eval(xs[0].scalar()))]),
+        Some(mir::Primitive::Op2(op)) => return Ok(vec![Datum::Scalar(op.eval(xs[0].scalar(), xs[1].scalar()))]),
+        None => {}
+      }
+      let Some(g) = funs.iter().find(|g| g.name == f) else { panic!() };
+      match eval_function(funs, g, &xs, fuel) {
+        Ok(vs) | Err(Exit::Return(0, vs)) => Ok(vs),
+        // The callee's other continuations are the caller's.
+        Err(Exit::Goto(..)) => panic!(),
+        Err(exit) => Err(exit),
+      }
+    }
+    Expression::Do(stmts) => {
+      let depth = scope.len();
+      let r = eval_statements(funs, scope, stmts, fuel);
+      scope.truncate(depth);
+      r.map(|()| Vec::new())
+    }
+    Expression::If(&mir::If { condition, if_true, if_false }) => {
+      let p = eval_expression(funs, scope, condition, fuel)?[0].scalar();
+      eval_expression(funs, scope, if p != 0 { if_true } else { if_false }, fuel)
+    }
+    Expression::And(&mir::Logical { left, right }) => {
+      let p = eval_expression(funs, scope, left, fuel)?[0].scalar();
+      if p == 0 { Ok(vec![Datum::Scalar(0)]) } else { eval_expression(funs, scope, right, fuel) }
+    }
+    Expression::Or(&mir::Logical { left, right }) => {
+      let p = eval_expression(funs, scope, left, fuel)?[0].scalar();
+      if p != 0 { Ok(vec![Datum::Scalar(1)]) } else { eval_expression(funs, scope, right, fuel) }
+    }
+    Expression::Loop(&mir::Loop { name, bindings, body }) => {
+      let mut xs = Vec::with_capacity(bindings.len());
+      for &(_, exp) in bindings.iter() {
+        xs.extend(eval_expression(funs, scope, exp, fuel)?);
+      }
+      let depth = scope.len();
+      loop {
+        scope.extend(bindings.iter().zip(xs.iter()).map(|(&(x, _), v)| (x, v.clone())));
+        let r = eval_expression(funs, scope, body, fuel);
+        scope.truncate(depth);
+        match r {
+          Err(Exit::Goto(x, ys)) if x == name => { xs = ys; }
+          r => { return r; }
+        }
+      }
+    }
+    Expression::Tuple(exps) => {
+      Ok(vec![Datum::Tuple(eval_values(funs, scope, exps, fuel)?)])
+    }
+    Expression::Field(&mir::Field { tuple, index }) => {
+      match eval_expression(funs, scope, tuple, fuel)?.swap_remove(0) {
+        Datum::Tuple(mut xs) => Ok(vec![xs.swap_remove(index as usize)]),
+        Datum::Scalar(_) => panic!(),
+      }
+    }
+  }
+}
+
+fn eval_statements<'a>(funs: &[mir::Function<'a>], scope: &mut Scope, stmts: &[Statement<'a>], fuel: &mut u64) -> Result<(), Exit> {
+  for &stmt in stmts.iter() {
+    match stmt {
+      Statement::Let(x, exp) => {
+        let v = eval_expression(funs, scope, exp, fuel)?.swap_remove(0);
+        scope.push((x, v));
+      }
+      Statement::Return(k, exps) => {
+        return Err(Exit::Return(k, eval_values(funs, scope, exps, fuel)?));
+      }
+      Statement::Goto(x, exps) => {
+        return Err(Exit::Goto(x, eval_values(funs, scope, exps, fuel)?));
+      }
+      Statement::LetVariable(..) | Statement::SetVariable(..) => {
+        panic!()
+      }
+    }
+  }
+  Ok(())
+}
+
+struct SsaGen<'s, 'r, 'b> {
+  src: &'r mut Source<'s>,
+  out: &'b mut Builder,
+  op1s: Vec<Op1>,
+  op2s: Vec<Op2>,
+  sigs: Vec<Signature>,
+  table: TypeTable,
+  aggregates: Vec<Type>,
+  current: usize,
+  scope: Vec<(Value, Type)>,
+}
+
+// A scalar, or sometimes one of the given aggregate types.
+
+fn ssa_type(src: &mut Source<'_>, aggregates: &[Type]) -> Type {
+  if ! aggregates.is_empty() && src.below(4) == 0 {
+    src.choose(aggregates)
+  } else {
+    src.choose(&SCALARS)
+  }
+}
+
+impl<'s, 'r, 'b> SsaGen<'s, 'r, 'b> {
+  fn constant(&mut self, t: Type) -> Value {
+    match t {
+      Type::BOOL => self.out.emit_const_bool(self.src.bool()),
+      Type::I5 => self.out.emit_const_i5(self.src.below(1 << 5) as u8),
+      Type::I6 => self.out.emit_const_i6(self.src.below(1 << 6) as u8),
+      Type::I7 => self.out.emit_const_i7(self.src.below(1 << 7) as u8),
+      Type::I32 => self.out.emit_const_i32(self.src.u32()),
+      Type::I64 => self.out.emit_const_i64(self.src.u64()),
+      Type::F32 => self.out.emit_const_f32(f32::from_bits(self.src.u32())),
+      Type::F64 => self.out.emit_const_f64(f64::from_bits(self.src.u64())),
+      _ => {
+        let fields: Vec<_> = (0 .. self.table.num_fields(t)).map(|i| self.table.field(t, i)).collect();
+        let xs = self.operands(&fields);
+        self.out.emit_build(t, &xs)
+      }
+    }
+  }
+
+  // A value of type `t` from the scope, or a new constant.
+
+  fn operand(&mut self, t: Type) -> Value {
+    let xs: Vec<_> = self.scope.iter().filter(|&&(_, u)| u == t).map(|&(x, _)| x).collect();
+    if xs.is_empty() || self.src.below(4) == 0 {
+      let x = self.constant(t);
+      self.scope.push((x, t));
+      return x;
+    }
+    self.src.choose(&xs)
+  }
+
+  fn operands(&mut self, types: &[Type]) -> Vec<Value> {
+    types.iter().map(|&t| self.operand(t)).collect()
+  }
+
+  fn emit_values(&mut self, xs: &[Value]) {
+    for &x in xs.iter() {
+      self.out.emit_value(x);
+    }
+  }
+
+  fn params(&mut self, types: &[Type]) {
+    for &t in types.iter() {
+      let x = self.out.emit_param(t);
+      self.scope.push((x, t));
+    }
+  }
+
+  fn types(&mut self, n: u32) -> Vec<Type> {
+    (0 .. self.src.below(n)).map(|_| ssa_type(self.src, &self.aggregates)).collect()
+  }
+
+  // Stores values into a fresh stack slot and loads them back, at offsets
+  // that are in bounds and naturally aligned, but not necessarily with the
+  // memory type they were stored with.
+
+  fn memory(&mut self) {
+    let slot = self.out.emit_alloca(16, 8);
+    for _ in 0 .. 1 + self.src.below(4) {
+      let t = self.src.choose(&MEM_TYPES);
+      let offset = t.size() * self.src.below(16 / t.size());
+      let flags = MemFlags::new(t.size(), self.src.bool());
+      if self.src.bool() {
+        let x = self.operand(t.value_type());
+        self.out.emit_store(t, flags, slot, offset, x);
+      } else {
+        let x = self.out.emit_load(t, flags, slot, offset);
+        self.scope.push((x, t.value_type()));
+      }
+    }
+  }
+
+  // Takes a field of an aggregate, or makes a copy with a different field.
+
+  fn aggregate(&mut self) {
+    if self.aggregates.is_empty() {
+      return;
+    }
+    let t = self.src.choose(&self.aggregates);
+    let n = self.table.num_fields(t);
+    if n == 0 {
+      return;
+    }
+    let x = self.operand(t);
+    let i = self.src.below(n);
+    let u = self.table.field(t, i);
+    if self.src.bool() {
+      let y = self.out.emit_extract(x, i);
+      self.scope.push((y, u));
+    } else {
+      let y = self.operand(u);
+      let z = self.out.emit_insert(x, i, y);
+      self.scope.push((z, t));
+    }
+  }
+
+  // Leaves the function. Without continuations, the function can only loop
+  // forever or make a tail call, so it makes a tail call to a later function
+  // without continuations, or else to itself.
+
+  fn exit(&mut self) {
+    if self.sigs[self.current].rets.is_empty() {
+      let f =
+        (self.current + 1 .. self.sigs.len())
+          .find(|&j| self.sigs[j].rets.is_empty())
+          .unwrap_or(self.current);
+      let params = self.sigs[f].params.clone();
+      let xs = self.operands(&params);
+      self.out.emit_tailcall(f as u32, xs.len() as u32);
+      self.emit_values(&xs);
+      return;
+    }
+
+    let k = self.src.below(self.sigs[self.current].rets.len() as u32);
+    let rets = self.sigs[self.current].rets[k as usize].clone();
+    let xs = self.operands(&rets);
+    self.out.emit_return(k, xs.len() as u32);
+    self.emit_values(&xs);
+  }
+
+  // Straight-line code and nested control flow, after which the current
+  // block is still open.
+
+  fn body(&mut self, depth: u32) {
+    for _ in 0 .. self.src.below(6) {
+      let choice = if depth == 0 { self.src.choose(&[0, 1, 2, 3, 7, 8]) } else { self.src.below(9) };
+
+      match choice {
+        1 => {
+          let op = self.src.choose(&self.op1s);
+          let x = self.operand(op.arg_type());
+          let y = self.out.emit_op1(op, x);
+          self.scope.push((y, op.ret_type()));
+        }
+        2 => {
+          let op = self.src.choose(&self.op2s);
+          let (a, b) = op.arg_types();
+          let x = self.operand(a);
+          let y = self.operand(b);
+          let z = self.out.emit_op2(op, x, y);
+          self.scope.push((z, op.ret_type()));
+        }
+        3 => {
+          let t = ssa_type(self.src, &self.aggregates);
+          let p = self.operand(Type::BOOL);
+          let x = self.operand(t);
+          let y = self.operand(t);
+          let z = self.out.emit_select(p, x, y);
+          self.scope.push((z, t));
+        }
+        4 => {
+          self.diamond(depth - 1);
+        }
+        5 => {
+          self.countdown(depth - 1);
+        }
+        6 => {
+          self.call();
+        }
+        7 => {
+          self.memory();
+        }
+        8 => {
+          self.aggregate();
+        }
+        _ => {
+          let t = self.src.choose(&SCALARS);
+          let x = self.constant(t);
+          self.scope.push((x, t));
+        }
+      }
+    }
+  }
+
+  // An `if` whose arms pass values to a join block, though one of them may
+  // leave the function instead.
+
+  fn diamond(&mut self, depth: u32) {
+    let p = self.operand(Type::BOOL);
+    let types = self.types(3);
+    let (a, b) = self.out.emit_if(p, Label(0), Label(0));
+    let scope = self.scope.len();
+    let mut gotos = Vec::new();
+
+    for (i, point) in [a, b].into_iter().enumerate() {
+      let label = self.out.emit_case();
+      self.out.patch_label(point, label);
+      self.body(depth);
+      if i + gotos.len() == 0 && self.src.below(4) == 0 {
+        self.exit();
+      } else {
+        let xs = self.operands(&types);
+        gotos.push(self.out.emit_goto(Label(0), xs.len() as u32));
+        self.emit_values(&xs);
+      }
+      self.scope.truncate(scope);
+    }
+
+    let label = self.out.emit_join(types.len() as u32);
+    for point in gotos {
+      self.out.patch_label(point, label);
+    }
+    self.params(&types);
+  }
+
+  // A loop with a counter, which continues in the block after the counter
+  // reaches zero.
+
+  fn countdown(&mut self, depth: u32) {
+    let mut types = self.types(3);
+    types.insert(0, Type::I64);
+    let n = self.out.emit_const_i64(self.src.below(4) as u64);
+    let mut xs = self.operands(&types[1 ..]);
+    xs.insert(0, n);
+    let point = self.out.emit_goto(Label(0), xs.len() as u32);
+    self.emit_values(&xs);
+    let header = self.out.emit_join(types.len() as u32);
+    self.out.patch_label(point, header);
+
+    let counter = self.out.emit_param(Type::I64);
+    self.scope.push((counter, Type::I64));
+    self.params(&types[1 ..]);
+    let state = self.scope.len();
+
+    let zero = self.out.emit_const_i64(0);
+    let p = self.out.emit_op2(Op2::IS_EQ_I64, counter, zero);
+    let (a, b) = self.out.emit_if(p, Label(0), Label(0));
+
+    let label = self.out.emit_case();
+    self.out.patch_label(b, label);
+    self.body(depth);
+    let one = self.out.emit_const_i64(1);
+    let i = self.out.emit_op2(Op2::SUB_I64, counter, one);
+    let mut ys = self.operands(&types[1 ..]);
+    ys.insert(0, i);
+    let _ = self.out.emit_goto(header, ys.len() as u32);
+    self.emit_values(&ys);
+    self.scope.truncate(state);
+
+    let label = self.out.emit_case();
+    self.out.patch_label(a, label);
+  }
+
+  // A call to a later function, which continues in the block for its first
+  // continuation. Its other continuations leave the function.
+
+  fn call(&mut self) {
+    let callees: Vec<_> =
+      (self.current + 1 .. self.sigs.len())
+        .filter(|&j| ! self.sigs[j].rets.is_empty())
+        .collect();
+
+    if callees.is_empty() {
+      return;
+    }
+
+    let f = self.src.choose(&callees);
+    let params = self.sigs[f].params.clone();
+    let rets = self.sigs[f].rets.clone();
+    let xs = self.operands(&params);
+    self.out.emit_call(f as u32, xs.len() as u32, rets.len() as u32);
+    self.emit_values(&xs);
+    let points: Vec<_> = rets.iter().map(|_| self.out.emit_label(Label(0))).collect();
+
+    let scope = self.scope.len();
+
+    for k in (0 .. rets.len()).rev() {
+      let label = self.out.emit_kont(rets[k].len() as u32);
+      self.out.patch_label(points[k], label);
+      self.params(&rets[k]);
+      if k != 0 {
+        self.exit();
+        self.scope.truncate(scope);
+      }
+    }
+  }
+
+  fn function(&mut self, index: usize) {
+    self.current = index;
+    self.scope.clear();
+
+    let sig = &self.sigs[index];
+    let params = sig.params.clone();
+    let rets = sig.rets.clone();
+    self.out.emit_function(rets.len() as u32, params.len() as u32);
+    self.params(&params);
+    for rets in rets.iter() {
+      self.out.emit_results(rets.len() as u32);
+      for &t in rets.iter() {
+        let _ = self.out.emit_type(t);
+      }
+    }
+
+    self.body(3);
+    self.exit();
+  }
+}
+
+// Generates a sequence of one to four ssa functions that verify, into `out`.
+// Functions without continuations may run forever, so run them with fuel.
+
+pub fn ssa_module(src: &mut Source<'_>, out: &mut Builder) {
+  let mut table = TypeTable::new();
+  let mut aggregates = Vec::new();
+
+  for _ in 0 .. src.below(4) {
+    if src.bool() {
+      let fields: Vec<_> = (0 .. src.below(4)).map(|_| ssa_type(src, &aggregates)).collect();
+      let _ = out.emit_struct(fields.len() as u32);
+      for &t in fields.iter() {
+        let _ = out.emit_type(t);
+      }
+      aggregates.push(table.push(ssa::Aggregate::Struct(fields)));
+    } else {
+      let t = ssa_type(src, &aggregates);
+      let n = src.below(4);
+      let _ = out.emit_array(t, n);
+      aggregates.push(table.push(ssa::Aggregate::Array(t, n)));
+    }
+  }
+
+  let n = 1 + src.below(4) as usize;
+  let mut sigs = Vec::with_capacity(n);
+
+  for _ in 0 .. n {
+    let params = (0 .. src.below(4)).map(|_| ssa_type(src, &aggregates)).collect();
+    let rets = (0 .. src.below(3)).map(|_| (0 .. src.below(3)).map(|_| ssa_type(src, &aggregates)).collect()).collect();
+    sigs.push(Signature { params, rets });
+  }
+
+  let mut g = SsaGen {
+    src,
+    out,
+    op1s: op1s(&SCALARS),
+    op2s: op2s(&SCALARS),
+    sigs,
+    table,
+    aggregates,
+    current: 0,
+    scope: Vec::new(),
+  };
+
+  for i in 0 .. n {
+    g.function(i);
+  }
+}
+
+// Random arguments for the `index`th function of verified code, with
+// aggregates passed as their scalars.
+
+pub fn ssa_args(src: &mut Source<'_>, code: &[u8], index: usize) -> Vec<u64> {
+  let table = TypeTable::read(code);
+  let mut functions = ssa::Reader::new(code).filter_map(|inst| {
+    match inst {
+      Ok((_, ssa::Instruction::Function(_, params))) => Some(params.iter().collect::<Vec<_>>()),
+      _ => None,
+    }
+  });
+  match functions.nth(index) {
+    Some(params) => {
+      let mut flat = Vec::new();
+      for &t in params.iter() {
+        table.flatten(t, &mut flat);
+      }
+      flat.iter().map(|&t| src.value(t)).collect()
+    }
+    None => Vec::new(),
+  }
+}
+
+// Shrinks an input for which `fails` holds to a smaller one for which it
+// still holds, by deleting runs of bytes and then making bytes smaller for
+// as long as that makes progress.
+
+pub fn shrink(data: &[u8], mut fails: impl FnMut(&[u8]) -> bool) -> Vec<u8> {
+  let mut best = data.to_vec();
+
+  loop {
+    let mut progress = false;
+
+    let mut k = best.len().div_ceil(2);
+    while k > 0 {
+      let mut i = 0;
+      while i + k <= best.len() {
+        let mut x = best.clone();
+        let _ = x.drain(i .. i + k);
+        if fails(&x) {
+          best = x;
+          progress = true;
+        } else {
+          i += k;
+        }
+      }
+      k /= 2;
+    }
+
+    for i in 0 .. best.len() {
+      for b in [0, best[i] / 2, best[i].saturating_sub(1)] {
+        if b < best[i] {
+          let mut x = best.clone();
+          x[i] = b;
+          if fails(&x) {
+            best = x;
+            progress = true;
+            break;
+          }
+        }
+      }
+    }
+
+    if ! progress {
+      return best;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::arena::Arena;
+  use crate::compile;
+  use crate::interp;
+  use crate::passes;
+
+  const FUEL: u64 = 1 << 16;
+
+  fn agrees(code: &[u8], args: &[u64], expected: &(u32, Vec<u64>)) -> bool {
+    match interp::run_with_fuel(code, 0, args, FUEL) {
+      Ok(r) => (r.index, r.values) == *expected,
+      Err(interp::Error::OutOfFuel) => true,
+      Err(_) => false,
+    }
+  }
+
+  // Whether the mir module generated from `data` compiles to code that
+  // verifies, and that the interpreter runs to the same results as direct
+  // evaluation, before and after optimization. A panic counts as failure,
+  // so that shrinking can also minimize inputs that crash the compiler.
+
+  fn compiles_correctly(data: &[u8]) -> bool {
+    std::panic::catch_unwind(|| {
+      let arena = Arena::new();
+      let names = Interner::new(&arena);
+      let mut src = Source::new(data);
+      let funs = mir_module(&names, &mut src);
+      let args = mir_args(&mut src, &funs[0]);
+      let Ok(expected) = eval(&funs, 0, &args, FUEL) else { return true; };
+      let module = compile::compile_module(&funs, &names);
+      if ssa::verify(module.code()).is_err() || ! agrees(module.code(), &args, &expected) {
+        return false;
+      }
+      match passes::PassManager::with_level(passes::OptLevel::O2).run(module.code()) {
+        Ok(code) => agrees(&code, &args, &expected),
+        Err(_) => false,
+      }
+    })
+    .unwrap_or(false)
+  }
+
+  #[test]
+  fn compiled_mir_agrees_with_eval() {
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    for _ in 0 .. 500 {
+      let data: Vec<u8> =
+        (0 .. 256)
+          .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+          })
+          .collect();
+      if ! compiles_correctly(&data) {
+        panic!("fails on input {:?}", shrink(&data, |x| ! compiles_correctly(x)));
+      }
+    }
+  }
+
+  #[test]
+  fn shrink_minimizes() {
+    let small = shrink(&[9, 200, 3, 77, 5], |x| x.iter().any(|&b| b >= 50));
+    assert!(small == [50]);
+  }
+}
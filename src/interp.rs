@@ -0,0 +1,520 @@
+//! reference interpreter for ssa
+//!
+//! Values are represented as described for `ssa::Op1` and `ssa::Op2`.
+
+use crate::passes;
+use crate::ssa;
+use crate::ssa::Instruction;
+use crate::ssa::Label;
+
+pub enum Error {
+  Invalid(ssa::VerifyError),
+  NoSuchFunction,
+  WrongArguments,
+  ArgumentOutOfRange,
+  OutOfFuel,
+  StackOverflow,
+  OutOfMemory,
+  OutOfBounds,
+  Misaligned,
+}
+
+impl core::fmt::Display for Error {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::Invalid(e) => write!(f, "invalid code: {}", e),
+      Self::NoSuchFunction => write!(f, "no such function"),
+      Self::WrongArguments => write!(f, "wrong number of arguments"),
+      Self::ArgumentOutOfRange => write!(f, "argument isn't a value of its type"),
+      Self::OutOfFuel => write!(f, "out of fuel"),
+      Self::StackOverflow => write!(f, "stack overflow"),
+      Self::OutOfMemory => write!(f, "out of memory"),
+      Self::OutOfBounds => write!(f, "memory access out of bounds"),
+      Self::Misaligned => write!(f, "misaligned memory access"),
+    }
+  }
+}
+
+// The maximum number of active non-tail calls.
+
+pub const MAX_DEPTH: usize = 1 << 16;
+
+// The maximum number of bytes of memory allocated by active calls.
+//
+// Memory is a single arena of bytes, and a `ref` is an offset into it. Each
+// call's slots are at the end of the arena, and are freed when it returns or
+// makes a tail call. The extent of every live slot is recorded, so an access
+// is out of bounds unless it is within a single slot of some active call,
+// even if it is within the arena, e.g. in the padding between two slots.
+
+pub const MAX_MEMORY: usize = 1 << 24;
+
+// The continuation a function returned to, and the values it passed.
+
+pub struct Return {
+  pub index: u32,
+  pub values: Vec<u64>,
+}
+
+// A decoded function, with the position of every block and the first value
+// defined by every instruction.
+
+struct Function<'a> {
+  insts: Vec<Instruction<'a>>,
+  defs: Vec<u32>,
+  blocks: Vec<usize>,
+  nvalues: usize,
+  nvariables: usize,
+}
+
+fn decode<'a>(code: &'a [u8]) -> Vec<Function<'a>> {
+  let mut r = code;
+  let mut s = ssa::ReadState::new();
+  let mut funs: Vec<Function<'a>> = Vec::new();
+
+  loop {
+    // `code` has been verified or was built by a pass, so it decodes.
+    let inst =
+      match ssa::read_with_state(&mut r, &mut s) {
+        Ok(Some(inst)) => inst,
+        Ok(None) => { break; }
+        Err(e) => { panic!("{}", e); }
+      };
+
+    if let Instruction::Function(..) = inst {
+      funs.push(Function {
+        insts: Vec::new(),
+        defs: Vec::new(),
+        blocks: Vec::new(),
+        nvalues: 0,
+        nvariables: 0,
+      });
+    }
+
+    let Some(f) = funs.last_mut() else { break; };
+
+    let (nvalues, nvariables) =
+      match &inst {
+        Instruction::Function(_, args) | Instruction::Join(args) | Instruction::Kont(args) => (args.iter().count(), 0),
+        Instruction::Case() => (0, 0),
+        Instruction::LetVariable(_) => (0, 1),
+        Instruction::SetVariable(..) | Instruction::Store(..) => (0, 0),
+        Instruction::If(..) | Instruction::Goto(..) | Instruction::Return(..) => (0, 0),
+        Instruction::Call(..) | Instruction::TailCall(..) => (0, 0),
+        _ => (1, 0),
+      };
+
+    match &inst {
+      Instruction::Function(..) | Instruction::Case() | Instruction::Join(_) | Instruction::Kont(_) => {
+        f.blocks.push(f.insts.len());
+      }
+      _ => {}
+    }
+
+    f.defs.push(if nvariables == 0 { f.nvalues } else { f.nvariables } as u32);
+    f.nvalues += nvalues;
+    f.nvariables += nvariables;
+    f.insts.push(inst);
+  }
+
+  funs
+}
+
+// An active call. `konts` are the labels in the caller's frame that the
+// callee's continuations resume at, and `base` is the size of the memory
+// arena when it was called.
+
+struct Frame {
+  fun: usize,
+  pc: usize,
+  values: Vec<u64>,
+  variables: Vec<u64>,
+  konts: Vec<Label>,
+  base: usize,
+}
+
+impl Frame {
+  fn new(funs: &[Function<'_>], fun: usize, args: &[u64], konts: Vec<Label>, base: usize) -> Self {
+    let f = &funs[fun];
+    let mut values = vec![0u64; f.nvalues];
+    values[.. args.len()].copy_from_slice(args);
+    Self {
+      fun,
+      pc: 1,
+      values,
+      variables: vec![0u64; f.nvariables],
+      konts,
+      base,
+    }
+  }
+
+  // Passes `args` to the block at label `a` and continues there.
+
+  fn jump(&mut self, funs: &[Function<'_>], a: Label, args: &[u64]) {
+    let f = &funs[self.fun];
+    let block = f.blocks[a.0 as usize];
+    let def = f.defs[block] as usize;
+    self.values[def .. def + args.len()].copy_from_slice(args);
+    self.pc = block + 1;
+  }
+}
+
+// The position in memory of an access of `size` bytes at `x + offset`.
+// `slots` are the start and end of every live slot, in order.
+
+fn address(slots: &[(usize, usize)], x: u64, offset: u32, size: u32, align: u32) -> Result<usize, Error> {
+  let a = x.wrapping_add(offset as u64);
+  if a % align as u64 != 0 {
+    return Err(Error::Misaligned);
+  }
+  let i = slots.partition_point(|&(start, _)| start as u64 <= a);
+  let Some(&(_, end)) = i.checked_sub(1).map(|i| &slots[i]) else {
+    return Err(Error::OutOfBounds);
+  };
+  if a > end as u64 || end - (a as usize) < size as usize {
+    return Err(Error::OutOfBounds);
+  }
+  Ok(a as usize)
+}
+
+// Frees the slots at or after `base`.
+
+fn free(memory: &mut Vec<u8>, slots: &mut Vec<(usize, usize)>, base: usize) {
+  memory.truncate(base);
+  while slots.last().is_some_and(|&(start, _)| start >= base) {
+    let _ = slots.pop();
+  }
+}
+
+// Runs the `index`th function in `code`.
+
+pub fn run(code: &[u8], index: usize, args: &[u64]) -> Result<Return, Error> {
+  run_with_fuel(code, index, args, u64::MAX)
+}
+
+// Runs the `index`th function in `code`, stopping with `Error::OutOfFuel`
+// after `fuel` instructions.
+//
+// Code with aggregate types is scalarized first, so aggregate arguments and
+// results are passed as the scalars they are made of.
+
+pub fn run_with_fuel(code: &[u8], index: usize, args: &[u64], fuel: u64) -> Result<Return, Error> {
+  ssa::verify(code).map_err(Error::Invalid)?;
+
+  let mut scalar = ssa::Builder::with_encoding(passes::encoding(code));
+  let code =
+    if ssa::TypeTable::read(code).is_empty() {
+      code
+    } else {
+      passes::scalarize(code, &mut scalar);
+      scalar.view()
+    };
+
+  let funs = decode(code);
+  let mut fuel = fuel;
+  let mut scratch = Vec::new();
+  let mut memory = Vec::new();
+  let mut slots = Vec::new();
+
+  let Some(f) = funs.get(index) else {
+    return Err(Error::NoSuchFunction);
+  };
+
+  let Instruction::Function(_, params) = &f.insts[0] else { unreachable!() };
+
+  if params.iter().count() != args.len() {
+    return Err(Error::WrongArguments);
+  }
+
+  if ! params.iter().zip(args.iter()).all(|(t, &x)| t.is_value(x)) {
+    return Err(Error::ArgumentOutOfRange);
+  }
+
+  let mut stack = vec![Frame::new(&funs, index, args, Vec::new(), 0)];
+
+  loop {
+    if fuel == 0 {
+      return Err(Error::OutOfFuel);
+    }
+
+    fuel -= 1;
+
+    let depth = stack.len();
+    let frame = stack.last_mut().unwrap();
+    let f = &funs[frame.fun];
+    let pc = frame.pc;
+    let def = f.defs[pc] as usize;
+    let values = &mut frame.values;
+
+    match f.insts[pc] {
+      Instruction::ConstBool(p) => {
+        values[def] = p as u64;
+      }
+      Instruction::ConstI32(c) => {
+        values[def] = c as u64;
+      }
+      Instruction::ConstI64(c) => {
+        values[def] = c;
+      }
+      Instruction::ConstI5(c) | Instruction::ConstI6(c) | Instruction::ConstI7(c) => {
+        values[def] = c as u64;
+      }
+      Instruction::ConstF32(c) => {
+        values[def] = c.to_bits() as u64;
+      }
+      Instruction::ConstF64(c) => {
+        values[def] = c.to_bits();
+      }
+      Instruction::Op1(t, x) => {
+        values[def] = t.eval(values[x.0 as usize]);
+      }
+      Instruction::Op2(t, x, y) => {
+        values[def] = t.eval(values[x.0 as usize], values[y.0 as usize]);
+      }
+      Instruction::Select(p, x, y) => {
+        values[def] = values[if values[p.0 as usize] != 0 { x } else { y }.0 as usize];
+      }
+      Instruction::LetVariable(x) => {
+        frame.variables[def] = values[x.0 as usize];
+      }
+      Instruction::GetVariable(v) => {
+        values[def] = frame.variables[v.0 as usize];
+      }
+      Instruction::SetVariable(v, x) => {
+        frame.variables[v.0 as usize] = values[x.0 as usize];
+      }
+      Instruction::Alloca(size, align) => {
+        let align = 1usize << align;
+        let start = memory.len().next_multiple_of(align);
+        if start + size as usize > MAX_MEMORY {
+          return Err(Error::OutOfMemory);
+        }
+        memory.resize(start + size as usize, 0);
+        slots.push((start, start + size as usize));
+        values[def] = start as u64;
+      }
+      Instruction::Load(t, flags, x, offset) => {
+        let n = t.size() as usize;
+        let a = address(&slots, values[x.0 as usize], offset, t.size(), flags.align())?;
+        let mut bytes = [0u8; 8];
+        bytes[.. n].copy_from_slice(&memory[a .. a + n]);
+        values[def] = t.extend(u64::from_le_bytes(bytes));
+      }
+      Instruction::Store(t, flags, x, offset, y) => {
+        let n = t.size() as usize;
+        let a = address(&slots, values[x.0 as usize], offset, t.size(), flags.align())?;
+        memory[a .. a + n].copy_from_slice(&values[y.0 as usize].to_le_bytes()[.. n]);
+      }
+      Instruction::If(p, a, b) => {
+        let target = if values[p.0 as usize] != 0 { a } else { b };
+        frame.pc = f.blocks[target.0 as usize] + 1;
+        continue;
+      }
+      Instruction::Goto(a, ref args) => {
+        // The arguments are read before any parameter is written, as a loop
+        // may pass its own parameters in a different order.
+        scratch.clear();
+        scratch.extend(args.iter().map(|x| values[x.0 as usize]));
+        frame.jump(&funs, a, &scratch);
+        continue;
+      }
+      Instruction::Return(index, ref args) => {
+        scratch.clear();
+        scratch.extend(args.iter().map(|x| values[x.0 as usize]));
+        let frame = stack.pop().unwrap();
+        free(&mut memory, &mut slots, frame.base);
+        match stack.last_mut() {
+          None => {
+            return Ok(Return { index, values: scratch });
+          }
+          Some(caller) => {
+            caller.jump(&funs, frame.konts[index as usize], &scratch);
+          }
+        }
+        continue;
+      }
+      Instruction::Call(g, ref args, ref konts) => {
+        if depth == MAX_DEPTH {
+          return Err(Error::StackOverflow);
+        }
+        scratch.clear();
+        scratch.extend(args.iter().map(|x| values[x.0 as usize]));
+        let konts = konts.iter().collect();
+        stack.push(Frame::new(&funs, g as usize, &scratch, konts, memory.len()));
+        continue;
+      }
+      Instruction::TailCall(g, ref args) => {
+        scratch.clear();
+        scratch.extend(args.iter().map(|x| values[x.0 as usize]));
+        let konts = core::mem::take(&mut frame.konts);
+        free(&mut memory, &mut slots, frame.base);
+        *frame = Frame::new(&funs, g as usize, &scratch, konts, frame.base);
+        continue;
+      }
+      _ => {
+        unreachable!()
+      }
+    }
+
+    frame.pc += 1;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // shl.i64.i6 of its two parameters.
+
+  fn shl() -> ssa::Builder {
+    let mut b = ssa::Builder::new();
+    b.emit_function(1, 2);
+    let x = b.emit_param(ssa::Type::I64);
+    let y = b.emit_param(ssa::Type::I6);
+    b.emit_results(1);
+    let _ = b.emit_type(ssa::Type::I64);
+    let z = b.emit_op2(ssa::Op2::SHL_I64_I6, x, y);
+    b.emit_return(0, 1);
+    b.emit_value(z);
+    b
+  }
+
+  // A function of no arguments that allocates an 8-byte slot aligned to 8
+  // after a 1-byte slot, and loads an i32 at `offset` from the second with
+  // the given alignment.
+
+  fn load(size: u32, offset: u32, align: u32) -> ssa::Builder {
+    let mut b = ssa::Builder::new();
+    b.emit_function(1, 0);
+    b.emit_results(1);
+    let _ = b.emit_type(ssa::Type::I32);
+    let _ = b.emit_alloca(1, 1);
+    let x = b.emit_alloca(size, 8);
+    let y = b.emit_load(ssa::MemType::I32, ssa::MemFlags::new(align, false), x, offset);
+    b.emit_return(0, 1);
+    b.emit_value(y);
+    b
+  }
+
+  // The first function calls the second twice, and returns the addresses of
+  // the slots it allocated, after loading from the first if `load`.
+
+  fn twice(load: bool) -> ssa::Builder {
+    let mut b = ssa::Builder::new();
+    b.emit_function(1, 0);
+    b.emit_results(2);
+    let _ = b.emit_type(ssa::Type::Ref);
+    let _ = b.emit_type(ssa::Type::Ref);
+    let mut xs = Vec::new();
+    for _ in 0 .. 2 {
+      b.emit_call(1, 0, 1);
+      let point = b.emit_label(Label(0));
+      let k = b.emit_kont(1);
+      b.patch_label(point, k);
+      xs.push(b.emit_param(ssa::Type::Ref));
+    }
+    if load {
+      let _ = b.emit_load(ssa::MemType::I8_U, ssa::MemFlags::new(1, false), xs[0], 0);
+    }
+    b.emit_return(0, 2);
+    b.emit_value(xs[0]);
+    b.emit_value(xs[1]);
+
+    b.emit_function(1, 0);
+    b.emit_results(1);
+    let _ = b.emit_type(ssa::Type::Ref);
+    let x = b.emit_alloca(4, 4);
+    b.emit_return(0, 1);
+    b.emit_value(x);
+    b
+  }
+
+  #[test]
+  fn memory() {
+    assert!(matches!(run(load(8, 4, 4).view(), 0, &[]), Ok(Return { values, .. }) if values == [0]));
+    assert!(matches!(run(load(8, 8, 4).view(), 0, &[]), Err(Error::OutOfBounds)));
+    assert!(matches!(run(load(8, 2, 4).view(), 0, &[]), Err(Error::Misaligned)));
+    assert!(matches!(run(load(1 << 25, 0, 4).view(), 0, &[]), Err(Error::OutOfMemory)));
+  }
+
+  #[test]
+  fn accesses_stay_within_a_slot() {
+    // The second slot starts at 8, and the padding before it is in no slot.
+    let mut b = ssa::Builder::new();
+    b.emit_function(1, 0);
+    b.emit_results(1);
+    let _ = b.emit_type(ssa::Type::I32);
+    let x = b.emit_alloca(1, 1);
+    let _ = b.emit_alloca(8, 8);
+    let y = b.emit_load(ssa::MemType::I32, ssa::MemFlags::new(4, false), x, 4);
+    b.emit_return(0, 1);
+    b.emit_value(y);
+    assert!(matches!(run(b.view(), 0, &[]), Err(Error::OutOfBounds)));
+  }
+
+  #[test]
+  fn slots_are_freed_on_return() {
+    assert!(matches!(run(twice(false).view(), 0, &[]), Ok(Return { values, .. }) if values[0] == values[1]));
+    assert!(matches!(run(twice(true).view(), 0, &[]), Err(Error::OutOfBounds)));
+  }
+
+  #[test]
+  fn arguments_are_checked() {
+    let b = shl();
+    assert!(matches!(run(b.view(), 0, &[1, 40]), Ok(Return { index: 0, values }) if values == [1 << 40]));
+    assert!(matches!(run(b.view(), 0, &[1, 64]), Err(Error::ArgumentOutOfRange)));
+    assert!(matches!(run(b.view(), 0, &[1]), Err(Error::WrongArguments)));
+    assert!(matches!(run(b.view(), 1, &[1, 1]), Err(Error::NoSuchFunction)));
+  }
+
+  // Counts its parameter down to zero, and returns the number of steps,
+  // recursing with a tail call or with a call.
+
+  fn countdown(tail: bool) -> ssa::Builder {
+    let mut b = ssa::Builder::new();
+    b.emit_function(1, 2);
+    let n = b.emit_param(ssa::Type::I64);
+    let m = b.emit_param(ssa::Type::I64);
+    b.emit_results(1);
+    let _ = b.emit_type(ssa::Type::I64);
+    let zero = b.emit_const_i64(0);
+    let p = b.emit_op2(ssa::Op2::IS_EQ_I64, n, zero);
+    let (a, c) = b.emit_if(p, Label(0), Label(0));
+    let label = b.emit_case();
+    b.patch_label(a, label);
+    b.emit_return(0, 1);
+    b.emit_value(m);
+    let label = b.emit_case();
+    b.patch_label(c, label);
+    let one = b.emit_const_i64(1);
+    let n = b.emit_op2(ssa::Op2::SUB_I64, n, one);
+    let m = b.emit_op2(ssa::Op2::ADD_I64, m, one);
+    if tail {
+      b.emit_tailcall(0, 2);
+      b.emit_value(n);
+      b.emit_value(m);
+    } else {
+      b.emit_call(0, 2, 1);
+      b.emit_value(n);
+      b.emit_value(m);
+      let point = b.emit_label(Label(0));
+      let k = b.emit_kont(1);
+      b.patch_label(point, k);
+      let x = b.emit_param(ssa::Type::I64);
+      b.emit_return(0, 1);
+      b.emit_value(x);
+    }
+    b
+  }
+
+  #[test]
+  fn tail_calls_reuse_their_frame() {
+    let n = MAX_DEPTH as u64 + 1;
+    let (tail, call) = (countdown(true), countdown(false));
+    assert!(ssa::verify(tail.view()).is_ok());
+    assert!(ssa::verify(call.view()).is_ok());
+    assert!(matches!(run(call.view(), 0, &[100, 0]), Ok(Return { index: 0, values }) if values == [100]));
+    assert!(matches!(run(call.view(), 0, &[n, 0]), Err(Error::StackOverflow)));
+    assert!(matches!(run(tail.view(), 0, &[n, 0]), Ok(Return { index: 0, values }) if values == [n]));
+  }
+}
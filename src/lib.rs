@@ -1,8 +1,14 @@
 pub mod buf;
 pub mod byte_slice;
 pub mod prelude;
+pub mod arena;
 pub mod ssa;
 pub mod mir;
 pub mod compile;
+pub mod passes;
+pub mod interp;
+pub mod container;
+#[cfg(any(test, feature = "generate"))]
+pub mod generate;
 
 extern crate alloc;
@@ -1,27 +1,62 @@
 // (function $fib (($n i64)) ((i64))
 //   (loop $continue-loop
-//     (($n $n)
-//      ($x #1)
-//      ($y #0))
-//     (if (i64.is_eq $n #0)
+//     (($n $n) ($x #1) ($y #0))
+//     (if (is_eq.i64 $n #0)
 //       $y
 //       (do
-//         (let ($a) (i64.add $x $y))
-//         (let ($b) (i64.sub $n #1))
+//         (let ($a) (add.i64 $x $y))
+//         (let ($b) (sub.i64 $n #1))
 //         (goto $continue-loop ($y $a $b))))))
 
 use crate::prelude::*;
+use crate::ssa;
 
-#[derive(Clone, Copy)]
-pub struct Symbol<'a>(pub &'a [u8]);
+pub mod builder;
+pub mod intern;
+pub mod print;
+pub mod span;
+pub mod typecheck;
 
-#[derive(Clone, Copy)]
-pub enum Type {
-  I64,
+pub use builder::Builder;
+pub use intern::Interner;
+pub use span::Span;
+pub use span::Spans;
+pub use typecheck::typecheck;
+pub use typecheck::typecheck_with_spans;
+
+// An interned name. Primitives, which are ssa operations, have fixed ids, so
+// a symbol can be resolved to a primitive without its `Interner`.
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(pub u32);
+
+impl Symbol {
+  pub const FIRST_NAME: u32 = 0x200;
+
+  pub const fn op1(op: ssa::Op1) -> Self {
+    Self(op.0 as u32)
+  }
+
+  pub const fn op2(op: ssa::Op2) -> Self {
+    Self(0x100 + op.0 as u32)
+  }
+
+  pub fn primitive(self) -> Option<Primitive> {
+    match self.0 {
+      0x000 ..= 0x0ff if ssa::Op1(self.0 as u8).is_valid() => Some(Primitive::Op1(ssa::Op1(self.0 as u8))),
+      0x100 ..= 0x1ff if ssa::Op2(self.0 as u8).is_valid() => Some(Primitive::Op2(ssa::Op2(self.0 as u8))),
+      _ => None,
+    }
+  }
 }
 
-/*
 #[derive(Clone, Copy)]
+pub enum Primitive {
+  Op1(ssa::Op1),
+  Op2(ssa::Op2),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum ScalarType {
   Bool,
   I5,
@@ -30,19 +65,85 @@ pub enum ScalarType {
   I64,
 }
 
+impl ScalarType {
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Bool => "bool",
+      Self::I5 => "i5",
+      Self::I6 => "i6",
+      Self::I32 => "i32",
+      Self::I64 => "i64",
+    }
+  }
+}
+
+// A tuple is a single value with a fixed number of components, which may
+// themselves be tuples. Tuples are compiled to one ssa value per scalar
+// component, so they never need memory.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Type<'a> {
   Scalar(ScalarType),
   Tuple(&'a [Type<'a>]),
 }
 
-pub enum EffectType {
+impl<'a> Type<'a> {
+  pub const BOOL: Self = Self::Scalar(ScalarType::Bool);
+  pub const I5: Self = Self::Scalar(ScalarType::I5);
+  pub const I6: Self = Self::Scalar(ScalarType::I6);
+  pub const I32: Self = Self::Scalar(ScalarType::I32);
+  pub const I64: Self = Self::Scalar(ScalarType::I64);
+}
+
+impl core::fmt::Display for ScalarType {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+impl<'a> core::fmt::Display for Type<'a> {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      Self::Scalar(t) => write!(f, "{}", t),
+      Self::Tuple(ts) => {
+        write!(f, "(")?;
+        for (i, t) in ts.iter().enumerate() {
+          if i != 0 {
+            write!(f, ", ")?;
+          }
+          write!(f, "{}", t)?;
+        }
+        write!(f, ")")
+      }
+    }
+  }
+}
+
+// The effects that evaluating an expression may have, besides computing its
+// values, as a set. A function declares the effects its body may have.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct EffectType(pub u8);
+
+impl EffectType {
+  pub const PURE: Self = Self(0);
+  // May run forever, by looping or by recursion.
+  pub const DIVERGE: Self = Self(1 << 0);
+
+  pub fn union(self, other: Self) -> Self {
+    Self(self.0 | other.0)
+  }
+
+  pub fn contains(self, other: Self) -> bool {
+    self.0 & other.0 == other.0
+  }
 }
-*/
 
 pub struct Function<'a> {
-  pub name: Symbol<'a>,
-  pub params: &'a [(Symbol<'a>, Type)],
-  // pub rets: &'a [&'a [Type]],
+  pub name: Symbol,
+  pub params: &'a [(Symbol, Type<'a>)],
+  pub rets: &'a [&'a [Type<'a>]],
+  pub effects: EffectType,
   pub body: Expression<'a>,
 }
 
@@ -53,30 +154,55 @@ pub struct Function<'a> {
 
 #[derive(Clone, Copy)]
 pub enum Statement<'a> {
-  Let(Symbol<'a>, Expression<'a>),
-  LetVariable(Symbol<'a>, Expression<'a>),
-  SetVariable(Symbol<'a>, Expression<'a>),
-  Goto(Symbol<'a>, &'a [Expression<'a>]),
-  Return(),
+  Let(Symbol, Expression<'a>),
+  LetVariable(Symbol, Expression<'a>),
+  SetVariable(Symbol, Expression<'a>),
+  Goto(Symbol, &'a [Expression<'a>]),
+  Return(u32, &'a [Expression<'a>]),
 }
 
-// An expr can *potentially* return a single value to a single continuation.
+// An expr can *potentially* return a list of values to a single continuation.
 
 #[derive(Clone, Copy)]
 pub enum Expression<'a> {
   Call(&'a Call<'a>),
   Do(&'a [Statement<'a>]),
   If(&'a If<'a>),
-  Variable(Symbol<'a>),
+  And(&'a Logical<'a>),
+  Or(&'a Logical<'a>),
+  Loop(&'a Loop<'a>),
+  Tuple(&'a [Expression<'a>]),
+  Field(&'a Field<'a>),
+  Variable(Symbol),
   ConstBool(bool),
+  ConstI32(u32),
   ConstI64(u64),
 }
 
 const _: () = assert!(size_of::<Expression<'static>>() <= 24);
 
+impl<'a> Expression<'a> {
+  // The address of the node that a compound expression points to, which
+  // identifies it in side tables such as `typecheck::Types` and `Spans`.
+  // Variables, constants and empty sequences have no node, and no address.
+
+  pub fn address(self) -> Option<*const ()> {
+    match self {
+      Self::Call(x) => Some(x as *const _ as *const ()),
+      Self::If(x) => Some(x as *const _ as *const ()),
+      Self::And(x) | Self::Or(x) => Some(x as *const _ as *const ()),
+      Self::Loop(x) => Some(x as *const _ as *const ()),
+      Self::Field(x) => Some(x as *const _ as *const ()),
+      Self::Do(x) if ! x.is_empty() => Some(x.as_ptr() as *const ()),
+      Self::Tuple(x) if ! x.is_empty() => Some(x.as_ptr() as *const ()),
+      _ => None,
+    }
+  }
+}
+
 #[derive(Clone, Copy)]
 pub struct Call<'a> {
-  pub function: Symbol<'a>,
+  pub function: Symbol,
   pub args: &'a [Expression<'a>],
 }
 
@@ -87,31 +213,44 @@ pub struct If<'a> {
   pub if_false: Expression<'a>,
 }
 
+// Short-circuit `and` and `or` of two bools, which only evaluate `right` if
+// `left` doesn't already decide the result.
+
+#[derive(Clone, Copy)]
+pub struct Logical<'a> {
+  pub left: Expression<'a>,
+  pub right: Expression<'a>,
+}
+
+// The `index`th component of a tuple.
+
+#[derive(Clone, Copy)]
+pub struct Field<'a> {
+  pub tuple: Expression<'a>,
+  pub index: u32,
+}
+
+// The body is evaluated with the names bound to the values of the bindings,
+// and is evaluated again with new values whenever it does a `goto` to the
+// loop's name. The loop evaluates to whatever the body does otherwise.
+
 #[derive(Clone, Copy)]
 pub struct Loop<'a> {
-  pub name: Symbol<'a>,
-  pub bindings: &'a [(Symbol<'a>, Expression<'a>)],
+  pub name: Symbol,
+  pub bindings: &'a [(Symbol, Expression<'a>)],
   pub body: Expression<'a>,
 }
 
-pub static FIB: Function<'static> = Function {
-  name: Symbol(b"fib"),
-  params: &[(Symbol(b"n"), Type::I64)],
-  //body: Expression::ConstI64(13),
-  body:
-    Expression::Call(&Call {
-      function: Symbol(b"add.i64"),
-      args: &[
-        Expression::If(&If {
-          condition: Expression::ConstBool(false),
-          if_true: Expression::ConstI64(1),
-          if_false: Expression::ConstI64(2)
-        }),
-        Expression::If(&If {
-          condition: Expression::ConstBool(true),
-          if_true: Expression::ConstI64(3),
-          if_false: Expression::ConstI64(4)
-        })
-      ]
-    })
-};
+pub fn fib<'a>(b: Builder<'a>) -> Function<'a> {
+  let add = Symbol::op2(ssa::Op2::ADD_I64);
+  b.function(
+    b.symbol("fib"),
+    &[(b.symbol("n"), Type::I64)],
+    &[&[Type::I64]],
+    EffectType::PURE,
+    b.call(add, &[
+      b.if_(Expression::ConstBool(false), Expression::ConstI64(1), Expression::ConstI64(2)),
+      b.if_(Expression::ConstBool(true), Expression::ConstI64(3), Expression::ConstI64(4)),
+    ])
+  )
+}
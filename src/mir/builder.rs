@@ -0,0 +1,156 @@
+//! building mir at runtime
+//!
+//! A `Builder` allocates mir nodes in an `Arena`, so that a front end can
+//! construct functions that live as long as the arena, and interns their
+//! names in an `Interner` that uses the same arena.
+//!
+//! ```text
+//! let arena = Arena::new();
+//! let names = Interner::new(&arena);
+//! let b = Builder::new(&names);
+//! let n = b.symbol("n");
+//! let body = b.call(b.symbol("add.i64"), &[b.variable(n), Expression::ConstI64(1)]);
+//! let f = b.function(b.symbol("inc"), &[(n, Type::I64)], &[&[Type::I64]], EffectType::PURE, body);
+//! ```
+//!
+//! Everything passed to a builder is copied into the arena, so arguments
+//! only need to live for the call.
+
+use crate::arena::Arena;
+use crate::mir::Call;
+use crate::mir::EffectType;
+use crate::mir::Expression;
+use crate::mir::Field;
+use crate::mir::Function;
+use crate::mir::If;
+use crate::mir::Interner;
+use crate::mir::Logical;
+use crate::mir::Loop;
+use crate::mir::Statement;
+use crate::mir::Symbol;
+use crate::mir::Type;
+
+#[derive(Clone, Copy)]
+pub struct Builder<'a> {
+  arena: &'a Arena,
+  names: &'a Interner<'a>,
+}
+
+impl<'a> Builder<'a> {
+  pub fn new(names: &'a Interner<'a>) -> Self {
+    Self { arena: names.arena(), names }
+  }
+
+  pub fn arena(self) -> &'a Arena {
+    self.arena
+  }
+
+  pub fn names(self) -> &'a Interner<'a> {
+    self.names
+  }
+
+  pub fn symbol(self, name: &str) -> Symbol {
+    self.names.intern(name.as_bytes())
+  }
+
+  pub fn tuple_type(self, types: &[Type<'a>]) -> Type<'a> {
+    Type::Tuple(self.arena.alloc_slice(types))
+  }
+
+  pub fn call(self, function: Symbol, args: &[Expression<'a>]) -> Expression<'a> {
+    let args = self.arena.alloc_slice(args);
+    Expression::Call(self.arena.alloc(Call { function, args }))
+  }
+
+  pub fn do_(self, stmts: &[Statement<'a>]) -> Expression<'a> {
+    Expression::Do(self.arena.alloc_slice(stmts))
+  }
+
+  pub fn if_(self, condition: Expression<'a>, if_true: Expression<'a>, if_false: Expression<'a>) -> Expression<'a> {
+    Expression::If(self.arena.alloc(If { condition, if_true, if_false }))
+  }
+
+  pub fn and(self, left: Expression<'a>, right: Expression<'a>) -> Expression<'a> {
+    Expression::And(self.arena.alloc(Logical { left, right }))
+  }
+
+  pub fn or(self, left: Expression<'a>, right: Expression<'a>) -> Expression<'a> {
+    Expression::Or(self.arena.alloc(Logical { left, right }))
+  }
+
+  pub fn loop_(self, name: Symbol, bindings: &[(Symbol, Expression<'a>)], body: Expression<'a>) -> Expression<'a> {
+    let bindings = self.arena.alloc_slice(bindings);
+    Expression::Loop(self.arena.alloc(Loop { name, bindings, body }))
+  }
+
+  pub fn tuple(self, exps: &[Expression<'a>]) -> Expression<'a> {
+    Expression::Tuple(self.arena.alloc_slice(exps))
+  }
+
+  pub fn field(self, tuple: Expression<'a>, index: u32) -> Expression<'a> {
+    Expression::Field(self.arena.alloc(Field { tuple, index }))
+  }
+
+  pub fn variable(self, name: Symbol) -> Expression<'a> {
+    Expression::Variable(name)
+  }
+
+  pub fn goto(self, name: Symbol, args: &[Expression<'a>]) -> Statement<'a> {
+    Statement::Goto(name, self.arena.alloc_slice(args))
+  }
+
+  pub fn return_(self, k: u32, values: &[Expression<'a>]) -> Statement<'a> {
+    Statement::Return(k, self.arena.alloc_slice(values))
+  }
+
+  pub fn function(self, name: Symbol, params: &[(Symbol, Type<'a>)], rets: &[&[Type<'a>]], effects: EffectType, body: Expression<'a>) -> Function<'a> {
+    let rets: Vec<&'a [Type<'a>]> = rets.iter().map(|&ts| self.arena.alloc_slice(ts)).collect();
+    Function {
+      name,
+      params: self.arena.alloc_slice(params),
+      rets: self.arena.alloc_slice(&rets),
+      effects,
+      body,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compile;
+  use crate::interp;
+  use crate::ssa;
+
+  // The builder and every slice passed to it are gone by the time the
+  // function is used.
+
+  fn inc<'a>(names: &'a Interner<'a>) -> Function<'a> {
+    let b = Builder::new(names);
+    let n = b.symbol("n");
+    let body = b.call(b.symbol("add.i64"), &[b.variable(n), Expression::ConstI64(1)]);
+    b.function(b.symbol("inc"), &[(n, Type::I64)], &[&[Type::I64]], EffectType::PURE, body)
+  }
+
+  #[test]
+  fn trees_outlive_the_builder() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    let f = inc(&names);
+    assert!(f.params.len() == 1 && f.rets.len() == 1 && f.rets[0] == [Type::I64]);
+    let module = compile::compile_module(&[f], &names);
+    assert!(matches!(interp::run(module.code(), 0, &[41]), Ok(interp::Return { index: 0, values }) if values == [42]));
+  }
+
+  #[test]
+  fn symbols_round_trip() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    let b = Builder::new(&names);
+    let (x, y) = (b.symbol("x"), b.symbol("y"));
+    assert!(x != y && b.symbol("x") == x && x.0 >= Symbol::FIRST_NAME);
+    assert!(names.name(x) == b"x" && names.name(y) == b"y");
+    assert!(b.symbol("add.i64") == Symbol::op2(ssa::Op2::ADD_I64));
+    assert!(names.name(Symbol::op1(ssa::Op1::NEG_I32)) == b"neg.i32");
+  }
+}
@@ -0,0 +1,103 @@
+//! symbol interning
+//!
+//! An `Interner` maps names to `Symbol`s, which are compact ids, so that
+//! symbols compare in constant time and tables can be indexed by them. The
+//! names themselves live in an arena, and are only needed again for error
+//! messages and for naming the functions of a compiled module.
+//!
+//! Every ssa operation is pre-interned as a primitive, at an id determined by
+//! its opcode, e.g. `add.i64` is `Symbol::op2(Op2::ADD_I64)`. Other names get
+//! ids from `Symbol::FIRST_NAME` on, in the order they are first interned.
+
+use crate::arena::Arena;
+use crate::mir::Symbol;
+use crate::ssa;
+use core::cell::RefCell;
+use std::collections::HashMap;
+
+pub struct Interner<'a> {
+  arena: &'a Arena,
+  map: RefCell<HashMap<&'a [u8], Symbol>>,
+  // Indexed by id. Opcodes that aren't valid operations have empty names.
+  names: RefCell<Vec<&'a [u8]>>,
+}
+
+impl<'a> Interner<'a> {
+  pub fn new(arena: &'a Arena) -> Self {
+    let mut map = HashMap::new();
+    let mut names = Vec::with_capacity(Symbol::FIRST_NAME as usize);
+
+    for op in (0 ..= u8::MAX).map(ssa::Op1) {
+      let name = if op.is_valid() { op.name().as_bytes() } else { b"" };
+      if ! name.is_empty() {
+        let _ = map.insert(name, Symbol::op1(op));
+      }
+      names.push(name);
+    }
+
+    for op in (0 ..= u8::MAX).map(ssa::Op2) {
+      let name = if op.is_valid() { op.name().as_bytes() } else { b"" };
+      if ! name.is_empty() {
+        let _ = map.insert(name, Symbol::op2(op));
+      }
+      names.push(name);
+    }
+
+    Self { arena, map: RefCell::new(map), names: RefCell::new(names) }
+  }
+
+  pub fn arena(&self) -> &'a Arena {
+    self.arena
+  }
+
+  pub fn intern(&self, name: &[u8]) -> Symbol {
+    if let Some(&x) = self.map.borrow().get(name) {
+      return x;
+    }
+    let name = self.arena.alloc_bytes(name);
+    let mut names = self.names.borrow_mut();
+    let x = Symbol(names.len() as u32);
+    names.push(name);
+    let _ = self.map.borrow_mut().insert(name, x);
+    x
+  }
+
+  // The symbol for `name`, if it has been interned.
+
+  pub fn get(&self, name: &[u8]) -> Option<Symbol> {
+    self.map.borrow().get(name).copied()
+  }
+
+  pub fn name(&self, x: Symbol) -> &'a [u8] {
+    self.names.borrow()[x.0 as usize]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn interns_once() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    assert!(names.get(b"x").is_none());
+    let x = names.intern(b"x");
+    let y = names.intern(b"y");
+    assert!(x == Symbol(Symbol::FIRST_NAME) && y == Symbol(Symbol::FIRST_NAME + 1));
+    assert!(names.intern(b"x") == x && names.get(b"x") == Some(x));
+    assert!(names.name(x) == b"x" && names.name(y) == b"y");
+    assert!(names.intern(b"") != x && names.name(names.intern(b"")) == b"");
+  }
+
+  #[test]
+  fn primitives() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    let add = Symbol::op2(ssa::Op2::ADD_I64);
+    assert!(names.get(b"add.i64") == Some(add) && names.name(add) == b"add.i64");
+    assert!(names.intern(b"neg.i32") == Symbol::op1(ssa::Op1::NEG_I32));
+    assert!(matches!(add.primitive(), Some(crate::mir::Primitive::Op2(ssa::Op2::ADD_I64))));
+    assert!(names.intern(b"f").primitive().is_none());
+  }
+}
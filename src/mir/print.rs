@@ -0,0 +1,259 @@
+//! printing mir as s-expressions
+//!
+//! Functions are printed in the syntax of the example at the top of
+//! `mir.rs`. A form that fits in the line is printed on one line. Otherwise
+//! its leading items, e.g. the condition of an `if`, stay on the first line,
+//! and the rest go on lines of their own, indented by two.
+//!
+//! ```text
+//! (function $f (($n i64) ($t (i32 bool))) ((i64) (bool)) diverge
+//!   (do (let ($x) (add.i64 $n #1)) (return 1 ((field 1 $t)))))
+//! ```
+//!
+//! Variables and functions are prefixed with `$`, and primitives aren't. An
+//! i64 constant is `#n`, and an i32 constant is `(const.i32 #n)`.
+
+use crate::mir::EffectType;
+use crate::mir::Expression;
+use crate::mir::Function;
+use crate::mir::Interner;
+use crate::mir::Statement;
+use crate::mir::Symbol;
+use crate::mir::Type;
+
+const WIDTH: usize = 80;
+
+// A form with its width when printed on one line. The first `keep` items
+// stay on the first line when it is broken, and the others are indented by
+// `indent` from its opening paren.
+
+enum Doc {
+  Atom(String),
+  List { items: Vec<Doc>, keep: usize, indent: usize, width: usize },
+}
+
+impl Doc {
+  fn width(&self) -> usize {
+    match self {
+      Self::Atom(s) => s.len(),
+      &Self::List { width, .. } => width,
+    }
+  }
+
+  fn flat(&self, out: &mut String) {
+    match self {
+      Self::Atom(s) => out.push_str(s),
+      Self::List { items, .. } => {
+        out.push('(');
+        for (i, x) in items.iter().enumerate() {
+          if i != 0 {
+            out.push(' ');
+          }
+          x.flat(out);
+        }
+        out.push(')');
+      }
+    }
+  }
+
+  // Prints at column `column`, which is where `out` currently ends, followed
+  // by `trail` closing parens.
+
+  fn print(&self, column: usize, trail: usize, out: &mut String) {
+    let Self::List { items, keep, indent, .. } = self else { return self.flat(out); };
+
+    if column + self.width() + trail <= WIDTH {
+      return self.flat(out);
+    }
+
+    out.push('(');
+    let mut c = column + 1;
+    for (i, x) in items.iter().enumerate() {
+      if i != 0 && i < *keep {
+        out.push(' ');
+        c += 1;
+      }
+      if i >= *keep {
+        out.push('\n');
+        c = column + indent;
+        for _ in 0 .. c {
+          out.push(' ');
+        }
+      }
+      let start = out.len();
+      x.print(c, if i + 1 == items.len() { trail + 1 } else { 0 }, out);
+      // Only the last line of `x` matters for what follows it.
+      c = match out[start ..].rfind('\n') {
+        Some(j) => out.len() - (start + j + 1),
+        None => c + (out.len() - start),
+      };
+    }
+    out.push(')');
+  }
+}
+
+fn atom(s: impl Into<String>) -> Doc {
+  Doc::Atom(s.into())
+}
+
+fn list(keep: usize, indent: usize, items: Vec<Doc>) -> Doc {
+  let width = items.iter().map(|x| x.width()).sum::<usize>() + items.len().saturating_sub(1) + 2;
+  Doc::List { items, keep, indent, width }
+}
+
+// A form headed by a keyword, with `keep` items after the keyword that stay
+// on the first line.
+
+fn form(keyword: &str, keep: usize, items: Vec<Doc>) -> Doc {
+  let mut xs = Vec::with_capacity(items.len() + 1);
+  xs.push(atom(keyword));
+  xs.extend(items);
+  list(1 + keep, 2, xs)
+}
+
+// A plain list, broken with its items aligned.
+
+fn group(items: Vec<Doc>) -> Doc {
+  list(1, 1, items)
+}
+
+struct Printer<'x, 'n> {
+  names: &'x Interner<'n>,
+}
+
+impl<'x, 'n> Printer<'x, 'n> {
+  fn name(&self, x: Symbol) -> String {
+    format!("${}", String::from_utf8_lossy(self.names.name(x)))
+  }
+
+  fn ty(&self, t: Type<'_>) -> Doc {
+    match t {
+      Type::Scalar(t) => atom(t.name()),
+      Type::Tuple(ts) => group(ts.iter().map(|&t| self.ty(t)).collect()),
+    }
+  }
+
+  fn expressions(&self, exps: &[Expression<'_>]) -> Doc {
+    group(exps.iter().map(|&x| self.expression(x)).collect())
+  }
+
+  fn expression(&self, exp: Expression<'_>) -> Doc {
+    match exp {
+      Expression::ConstBool(p) => atom(format!("#{}", p)),
+      Expression::ConstI32(n) => form("const.i32", 1, vec![atom(format!("#{}", n))]),
+      Expression::ConstI64(n) => atom(format!("#{}", n)),
+      Expression::Variable(x) => atom(self.name(x)),
+      Expression::Call(&crate::mir::Call { function, args }) => {
+        let head =
+          match function.primitive() {
+            Some(_) => String::from_utf8_lossy(self.names.name(function)).into_owned(),
+            None => self.name(function),
+          };
+        form(&head, 0, args.iter().map(|&x| self.expression(x)).collect())
+      }
+      Expression::Do(stmts) => {
+        form("do", 0, stmts.iter().map(|&s| self.statement(s)).collect())
+      }
+      Expression::If(&crate::mir::If { condition, if_true, if_false }) => {
+        form("if", 1, vec![self.expression(condition), self.expression(if_true), self.expression(if_false)])
+      }
+      Expression::And(&crate::mir::Logical { left, right }) => {
+        form("and", 0, vec![self.expression(left), self.expression(right)])
+      }
+      Expression::Or(&crate::mir::Logical { left, right }) => {
+        form("or", 0, vec![self.expression(left), self.expression(right)])
+      }
+      Expression::Loop(&crate::mir::Loop { name, bindings, body }) => {
+        let bindings = group(bindings.iter().map(|&(x, e)| group(vec![atom(self.name(x)), self.expression(e)])).collect());
+        form("loop", 1, vec![atom(self.name(name)), bindings, self.expression(body)])
+      }
+      Expression::Tuple(exps) => {
+        form("tuple", 0, exps.iter().map(|&x| self.expression(x)).collect())
+      }
+      Expression::Field(&crate::mir::Field { tuple, index }) => {
+        form("field", 1, vec![atom(index.to_string()), self.expression(tuple)])
+      }
+    }
+  }
+
+  fn statement(&self, stmt: Statement<'_>) -> Doc {
+    match stmt {
+      Statement::Let(x, exp) => {
+        form("let", 1, vec![group(vec![atom(self.name(x))]), self.expression(exp)])
+      }
+      Statement::LetVariable(x, exp) => {
+        form("var", 1, vec![atom(self.name(x)), self.expression(exp)])
+      }
+      Statement::SetVariable(x, exp) => {
+        form("set", 1, vec![atom(self.name(x)), self.expression(exp)])
+      }
+      Statement::Goto(x, exps) => {
+        form("goto", 1, vec![atom(self.name(x)), self.expressions(exps)])
+      }
+      Statement::Return(k, exps) => {
+        form("return", 1, vec![atom(k.to_string()), self.expressions(exps)])
+      }
+    }
+  }
+
+  fn function(&self, fun: &Function<'_>) -> Doc {
+    let params = group(fun.params.iter().map(|&(x, t)| group(vec![atom(self.name(x)), self.ty(t)])).collect());
+    let rets = group(fun.rets.iter().map(|&ts| group(ts.iter().map(|&t| self.ty(t)).collect())).collect());
+    let mut items = vec![atom(self.name(fun.name)), params, rets];
+    if fun.effects.contains(EffectType::DIVERGE) {
+      items.push(atom("diverge"));
+    }
+    let keep = items.len();
+    items.push(self.expression(fun.body));
+    form("function", keep, items)
+  }
+}
+
+// `names` must be the interner that `fun` was built with.
+
+pub fn function(fun: &Function<'_>, names: &Interner<'_>) -> String {
+  let mut out = String::new();
+  Printer { names }.function(fun).print(0, 0, &mut out);
+  out
+}
+
+pub fn expression(exp: Expression<'_>, names: &Interner<'_>) -> String {
+  let mut out = String::new();
+  Printer { names }.expression(exp).print(0, 0, &mut out);
+  out
+}
+
+pub fn display(funs: &[Function<'_>], names: &Interner<'_>) {
+  for fun in funs.iter() {
+    print!("{}\n", function(fun, names));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::arena::Arena;
+  use crate::mir::Builder;
+  use crate::ssa::Op2;
+
+  // Every line fits, including the closing parens at the end of a nested
+  // form, whatever the length of the names.
+
+  #[test]
+  fn lines_fit() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    let b = Builder::new(&names);
+    let add = Symbol::op2(Op2::ADD_I64);
+    for n in 1 .. WIDTH {
+      let x = b.symbol("x");
+      let g = b.symbol(&"g".repeat(n));
+      let sum = b.call(add, &[b.call(g, &[b.variable(x)]), Expression::ConstI64(1)]);
+      let body = b.do_(&[b.return_(0, &[b.call(add, &[sum, sum])])]);
+      let f = b.function(b.symbol("f"), &[(x, Type::I64)], &[&[Type::I64]], EffectType::PURE, body);
+      let s = function(&f, &names);
+      // A line with a single atom can't be broken.
+      assert!(s.lines().all(|line| line.len() <= WIDTH || ! line.trim().contains(' ')), "{}", s);
+    }
+  }
+}
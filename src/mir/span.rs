@@ -0,0 +1,123 @@
+//! source spans for mir
+//!
+//! Spans are kept in a side table rather than in the nodes, so that they
+//! cost nothing when there is no source text, and so that `Expression` stays
+//! small. Expressions are identified by the address of the node they point
+//! to, as in `typecheck::Types`, statements by their address in the `do`
+//! that contains them, and functions by name.
+//!
+//! Variables and constants have no node of their own, so they can't have a
+//! span, and are reported at the span of the nearest enclosing node that
+//! has one.
+
+use crate::mir::Expression;
+use crate::mir::Statement;
+use crate::mir::Symbol;
+use std::collections::HashMap;
+
+// A byte range `start .. end` in the source file with id `file`. What the
+// ids refer to is up to the front end.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+  pub file: u32,
+  pub start: u32,
+  pub end: u32,
+}
+
+impl core::fmt::Display for Span {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "{}:{}..{}", self.file, self.start, self.end)
+  }
+}
+
+pub struct Spans {
+  functions: HashMap<Symbol, Span>,
+  expressions: HashMap<*const (), Span>,
+  statements: HashMap<*const (), Span>,
+}
+
+impl Spans {
+  pub fn new() -> Self {
+    Self {
+      functions: HashMap::new(),
+      expressions: HashMap::new(),
+      statements: HashMap::new(),
+    }
+  }
+
+  pub fn set_function(&mut self, name: Symbol, span: Span) {
+    let _ = self.functions.insert(name, span);
+  }
+
+  // Returns false if `exp` has no node to attach the span to.
+
+  pub fn set_expression(&mut self, exp: Expression<'_>, span: Span) -> bool {
+    let Some(k) = exp.address() else { return false; };
+    let _ = self.expressions.insert(k, span);
+    true
+  }
+
+  // `stmt` must be the statement in its `do`, not a copy of it.
+
+  pub fn set_statement(&mut self, stmt: &Statement<'_>, span: Span) {
+    let _ = self.statements.insert(stmt as *const _ as *const (), span);
+  }
+
+  pub fn function(&self, name: Symbol) -> Option<Span> {
+    self.functions.get(&name).copied()
+  }
+
+  pub fn expression(&self, exp: Expression<'_>) -> Option<Span> {
+    self.expressions.get(&exp.address()?).copied()
+  }
+
+  pub fn statement(&self, stmt: &Statement<'_>) -> Option<Span> {
+    self.statements.get(&(stmt as *const _ as *const ())).copied()
+  }
+}
+
+impl Default for Spans {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::arena::Arena;
+  use crate::mir::Builder;
+  use crate::mir::Interner;
+
+  fn span(start: u32, end: u32) -> Span {
+    Span { file: 0, start, end }
+  }
+
+  #[test]
+  fn spans_are_keyed_by_node() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    let b = Builder::new(&names);
+    let (f, g, n) = (b.symbol("f"), b.symbol("g"), b.symbol("n"));
+    let x = b.call(f, &[b.variable(n)]);
+    let y = b.call(f, &[b.variable(n)]);
+    let body = b.do_(&[b.return_(0, &[x]), b.return_(0, &[y])]);
+    let Expression::Do(stmts) = body else { panic!() };
+
+    let mut spans = Spans::new();
+    spans.set_function(f, span(0, 10));
+    assert!(spans.set_expression(x, span(1, 2)));
+    assert!(! spans.set_expression(b.variable(n), span(3, 4)));
+    assert!(! spans.set_expression(Expression::ConstI64(0), span(3, 4)));
+    spans.set_statement(&stmts[1], span(5, 6));
+
+    assert!(spans.function(f) == Some(span(0, 10)) && spans.function(g).is_none());
+    assert!(spans.expression(x) == Some(span(1, 2)) && spans.expression(y).is_none());
+    assert!(spans.expression(b.variable(n)).is_none());
+    // A `do` has the address of its first statement, but they are apart.
+    assert!(spans.expression(body).is_none() && spans.statement(&stmts[0]).is_none());
+    assert!(spans.statement(&stmts[1]) == Some(span(5, 6)));
+    assert!(span(5, 6).to_string() == "0:5..6");
+  }
+}
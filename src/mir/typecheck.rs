@@ -0,0 +1,741 @@
+//! type checking for mir
+//!
+//! `typecheck` checks a sequence of functions that may call each other by
+//! name, and infers the types of the values that each expression evaluates
+//! to. An expression may also never return to its continuation, e.g. a `do`
+//! that ends in a `goto`, in which case it has no type. Code after such an
+//! expression is unreachable, and isn't checked, as it isn't compiled.
+//!
+//! Inferred types are `OwnedType`s, as tuple types built by the checker
+//! have nowhere to live with the lifetime of the functions. The checker also
+//! infers the effects of each function's body, which must be among the
+//! effects the function declares. A loop may diverge, and so may a call to a
+//! function that calls the caller back, directly or through others, so every
+//! function in a cycle of calls must declare `DIVERGE`.
+//!
+//! Primitives are called by the names of the ssa operations that implement
+//! them, and have the same signatures, e.g.
+//!
+//! ```text
+//! add.i64 : (i64, i64) -> i64
+//! is_eq.i32 : (i32, i32) -> bool
+//! ```
+
+use crate::mir::EffectType;
+use crate::mir::Expression;
+use crate::mir::Function;
+use crate::mir::Interner;
+use crate::mir::Primitive;
+use crate::mir::ScalarType;
+use crate::mir::Span;
+use crate::mir::Spans;
+use crate::mir::Statement;
+use crate::mir::Symbol;
+use crate::mir::Type;
+use crate::ssa;
+use std::collections::HashMap;
+
+// `span` is that of the innermost node around the error that has one.
+
+pub struct TypeError {
+  pub function: Box<[u8]>,
+  pub message: &'static str,
+  pub symbol: Option<Box<[u8]>>,
+  pub span: Option<Span>,
+}
+
+impl core::fmt::Display for TypeError {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    if let Some(span) = self.span {
+      write!(f, "{}: ", span)?;
+    }
+    write!(f, "in ${}: {}", String::from_utf8_lossy(&self.function), self.message)?;
+    if let Some(x) = &self.symbol {
+      write!(f, " ${}", String::from_utf8_lossy(x))?;
+    }
+    Ok(())
+  }
+}
+
+// A `Type` that owns its components.
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum OwnedType {
+  Scalar(ScalarType),
+  Tuple(Box<[OwnedType]>),
+}
+
+impl OwnedType {
+  pub const BOOL: Self = Self::Scalar(ScalarType::Bool);
+  pub const I5: Self = Self::Scalar(ScalarType::I5);
+  pub const I6: Self = Self::Scalar(ScalarType::I6);
+  pub const I32: Self = Self::Scalar(ScalarType::I32);
+  pub const I64: Self = Self::Scalar(ScalarType::I64);
+}
+
+impl From<Type<'_>> for OwnedType {
+  fn from(t: Type<'_>) -> Self {
+    match t {
+      Type::Scalar(t) => Self::Scalar(t),
+      Type::Tuple(ts) => Self::Tuple(ts.iter().map(|&t| t.into()).collect()),
+    }
+  }
+}
+
+fn owned(ts: &[Type<'_>]) -> Vec<OwnedType> {
+  ts.iter().map(|&t| t.into()).collect()
+}
+
+// The types of the values of every compound expression that returns to its
+// continuation. Nodes are identified by address, so the map is only valid
+// for the functions it was computed from.
+
+pub struct Types {
+  map: HashMap<*const (), Box<[OwnedType]>>,
+}
+
+impl Types {
+  pub fn new() -> Self {
+    Self { map: HashMap::new() }
+  }
+
+  pub fn get(&self, exp: Expression<'_>) -> Option<&[OwnedType]> {
+    match exp {
+      Expression::ConstBool(_) => Some(&[OwnedType::BOOL]),
+      Expression::ConstI32(_) => Some(&[OwnedType::I32]),
+      Expression::ConstI64(_) => Some(&[OwnedType::I64]),
+      Expression::And(_) | Expression::Or(_) => Some(&[OwnedType::BOOL]),
+      exp => self.map.get(&exp.address()?).map(|ts| &**ts),
+    }
+  }
+}
+
+impl Default for Types {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+fn mir_type(t: ssa::Type) -> Option<OwnedType> {
+  match t {
+    ssa::Type::BOOL => Some(OwnedType::BOOL),
+    ssa::Type::I5 => Some(OwnedType::I5),
+    ssa::Type::I6 => Some(OwnedType::I6),
+    ssa::Type::I32 => Some(OwnedType::I32),
+    ssa::Type::I64 => Some(OwnedType::I64),
+    _ => None,
+  }
+}
+
+// The parameter and result types of a primitive, or `None` if `x` isn't a
+// primitive. Operations on types that mir doesn't have aren't primitives.
+
+pub fn primitive(x: Symbol) -> Option<(Vec<OwnedType>, OwnedType)> {
+  match x.primitive()? {
+    Primitive::Op1(op) => {
+      Some((vec![mir_type(op.arg_type())?], mir_type(op.ret_type())?))
+    }
+    Primitive::Op2(op) => {
+      let (a, b) = op.arg_types();
+      Some((vec![mir_type(a)?, mir_type(b)?], mir_type(op.ret_type())?))
+    }
+  }
+}
+
+// The functions that an expression calls by name, including primitives, in
+// any code, reachable or not.
+
+fn calls(exp: Expression<'_>, out: &mut Vec<Symbol>) {
+  match exp {
+    Expression::Call(x) => {
+      out.push(x.function);
+      for &arg in x.args.iter() {
+        calls(arg, out);
+      }
+    }
+    Expression::Do(stmts) => {
+      for &stmt in stmts.iter() {
+        match stmt {
+          Statement::Let(_, exp)
+          | Statement::LetVariable(_, exp)
+          | Statement::SetVariable(_, exp) => calls(exp, out),
+          Statement::Goto(_, exps) | Statement::Return(_, exps) => {
+            for &exp in exps.iter() {
+              calls(exp, out);
+            }
+          }
+        }
+      }
+    }
+    Expression::If(x) => {
+      calls(x.condition, out);
+      calls(x.if_true, out);
+      calls(x.if_false, out);
+    }
+    Expression::And(x) | Expression::Or(x) => {
+      calls(x.left, out);
+      calls(x.right, out);
+    }
+    Expression::Loop(x) => {
+      for &(_, exp) in x.bindings.iter() {
+        calls(exp, out);
+      }
+      calls(x.body, out);
+    }
+    Expression::Tuple(exps) => {
+      for &exp in exps.iter() {
+        calls(exp, out);
+      }
+    }
+    Expression::Field(x) => calls(x.tuple, out),
+    Expression::Variable(_)
+    | Expression::ConstBool(_)
+    | Expression::ConstI32(_)
+    | Expression::ConstI64(_) => {}
+  }
+}
+
+// Tarjan's algorithm for the strongly connected components of the call
+// graph, numbered in the order they are completed.
+
+struct Components<'e> {
+  edges: &'e [Vec<usize>],
+  order: Vec<usize>,
+  low: Vec<usize>,
+  stack: Vec<usize>,
+  component: Vec<usize>,
+  next: usize,
+  count: usize,
+}
+
+impl<'e> Components<'e> {
+  fn visit(&mut self, v: usize) {
+    self.order[v] = self.next;
+    self.low[v] = self.next;
+    self.next += 1;
+    self.stack.push(v);
+
+    for &w in self.edges[v].iter() {
+      if self.order[w] == usize::MAX {
+        self.visit(w);
+        self.low[v] = self.low[v].min(self.low[w]);
+      } else if self.component[w] == usize::MAX {
+        self.low[v] = self.low[v].min(self.order[w]);
+      }
+    }
+
+    if self.low[v] == self.order[v] {
+      loop {
+        let Some(w) = self.stack.pop() else { panic!() };
+        self.component[w] = self.count;
+        if w == v { break; }
+      }
+      self.count += 1;
+    }
+  }
+}
+
+// The component of each function. Two functions are in the same component
+// if and only if each calls the other, directly or through other functions.
+// Calls resolve to the first function with the name, as in `infer`.
+
+fn components(funs: &[Function<'_>]) -> Vec<usize> {
+  let mut index = HashMap::new();
+  for (i, f) in funs.iter().enumerate() {
+    let _ = index.entry(f.name).or_insert(i);
+  }
+
+  let edges: Vec<Vec<usize>> =
+    funs.iter().map(|f| {
+      let mut xs = Vec::new();
+      calls(f.body, &mut xs);
+      xs.iter().filter_map(|x| index.get(x).copied()).collect()
+    }).collect();
+
+  let mut c = Components {
+    edges: &edges,
+    order: vec![usize::MAX; funs.len()],
+    low: vec![0; funs.len()],
+    stack: Vec::new(),
+    component: vec![usize::MAX; funs.len()],
+    next: 0,
+    count: 0,
+  };
+
+  for v in 0 .. funs.len() {
+    if c.order[v] == usize::MAX {
+      c.visit(v);
+    }
+  }
+
+  c.component
+}
+
+struct Checker<'a, 'f, 'n> {
+  funs: &'f [Function<'a>],
+  names: &'f Interner<'n>,
+  spans: &'f Spans,
+  span: Option<Span>,
+  current: usize,
+  components: Vec<usize>,
+  scope: Vec<(Symbol, OwnedType)>,
+  loops: Vec<(Symbol, Vec<OwnedType>)>,
+  effects: EffectType,
+  types: Types,
+}
+
+// `Ok(None)` means that the expression doesn't return to its continuation.
+
+type Result<T> = core::result::Result<Option<T>, TypeError>;
+
+impl<'a, 'f, 'n> Checker<'a, 'f, 'n> {
+  fn error(&self, message: &'static str, symbol: Option<Symbol>) -> TypeError {
+    TypeError {
+      function: self.names.name(self.funs[self.current].name).into(),
+      message,
+      symbol: symbol.map(|x| self.names.name(x).into()),
+      span: self.span,
+    }
+  }
+
+  // Makes `span`, if any, the current span, and returns the one it replaces.
+
+  fn enter(&mut self, span: Option<Span>) -> Option<Span> {
+    let outer = self.span;
+    self.span = span.or(outer);
+    outer
+  }
+
+  // The values of a list of expressions, concatenated.
+
+  fn values(&mut self, exps: &[Expression<'a>]) -> Result<Vec<OwnedType>> {
+    let mut ts = Vec::new();
+    for &exp in exps.iter() {
+      let Some(us) = self.expression(exp)? else { return Ok(None); };
+      ts.extend(us);
+    }
+    Ok(Some(ts))
+  }
+
+  fn value(&mut self, exp: Expression<'a>) -> Result<OwnedType> {
+    let Some(mut ts) = self.expression(exp)? else { return Ok(None); };
+    if ts.len() != 1 {
+      return Err(self.error("expected exactly one value", None));
+    }
+    Ok(ts.pop())
+  }
+
+  fn expression(&mut self, exp: Expression<'a>) -> Result<Vec<OwnedType>> {
+    let outer = self.enter(self.spans.expression(exp));
+    let ts = self.infer(exp);
+    self.span = outer;
+    let ts = ts?;
+    if let (Some(ts), Some(k)) = (&ts, exp.address()) {
+      let _ = self.types.map.insert(k, ts.as_slice().into());
+    }
+    Ok(ts)
+  }
+
+  fn infer(&mut self, exp: Expression<'a>) -> Result<Vec<OwnedType>> {
+    match exp {
+      Expression::ConstBool(_) => {
+        Ok(Some(vec![OwnedType::BOOL]))
+      }
+      Expression::ConstI32(_) => {
+        Ok(Some(vec![OwnedType::I32]))
+      }
+      Expression::ConstI64(_) => {
+        Ok(Some(vec![OwnedType::I64]))
+      }
+      Expression::Variable(name) => {
+        match self.scope.iter().rev().find(|&&(x, _)| x == name) {
+          Some((_, t)) => Ok(Some(vec![t.clone()])),
+          None => Err(self.error("unknown variable", Some(name))),
+        }
+      }
+      Expression::Call(&crate::mir::Call { function, args }) => {
+        if let Some((params, ret)) = primitive(function) {
+          if args.len() != params.len() {
+            return Err(self.error("wrong number of arguments to", Some(function)));
+          }
+          for (&arg, t) in args.iter().zip(params.iter()) {
+            let Some(u) = self.value(arg)? else { return Ok(None); };
+            if u != *t {
+              return Err(self.error("argument of the wrong type to", Some(function)));
+            }
+          }
+          return Ok(Some(vec![ret]));
+        }
+
+        let Some(index) = self.funs.iter().position(|f| f.name == function) else {
+          return Err(self.error("unknown function", Some(function)));
+        };
+
+        let callee = &self.funs[index];
+
+        let Some(ts) = self.values(args)? else { return Ok(None); };
+
+        if ts.len() != callee.params.len() {
+          return Err(self.error("wrong number of arguments to", Some(function)));
+        }
+
+        if ! ts.iter().zip(callee.params.iter()).all(|(t, &(_, u))| *t == u.into()) {
+          return Err(self.error("argument of the wrong type to", Some(function)));
+        }
+
+        // The callee's continuations after the first return from the caller
+        // to its continuations with the same indexes.
+        let caller = &self.funs[self.current];
+        for (i, &rets) in callee.rets.iter().enumerate().skip(1) {
+          match caller.rets.get(i) {
+            None => return Err(self.error("caller lacks a continuation of", Some(function))),
+            Some(&us) if owned(us) != owned(rets) => return Err(self.error("caller's continuation has other types than that of", Some(function))),
+            Some(_) => {}
+          }
+        }
+
+        // A call within the caller's own component of the call graph is a
+        // recursive call, directly or through other functions, and so may
+        // diverge even if no function in the cycle loops.
+        self.effects = self.effects.union(callee.effects);
+        if self.components[index] == self.components[self.current] {
+          self.effects = self.effects.union(EffectType::DIVERGE);
+        }
+
+        Ok(callee.rets.first().map(|&rets| owned(rets)))
+      }
+      Expression::Do(stmts) => {
+        let depth = self.scope.len();
+        let mut r = Ok(Some(Vec::new()));
+        for stmt in stmts.iter() {
+          let outer = self.enter(self.spans.statement(stmt));
+          let s = self.statement(*stmt);
+          self.span = outer;
+          match s {
+            Ok(Some(())) => {}
+            Ok(None) => { r = Ok(None); break; }
+            Err(e) => { r = Err(e); break; }
+          }
+        }
+        self.scope.truncate(depth);
+        r
+      }
+      Expression::If(&crate::mir::If { condition, if_true, if_false }) => {
+        let Some(p) = self.value(condition)? else { return Ok(None); };
+
+        if p != OwnedType::BOOL {
+          return Err(self.error("condition isn't a bool", None));
+        }
+
+        match (self.expression(if_true)?, self.expression(if_false)?) {
+          (None, None) => Ok(None),
+          (Some(ts), None) | (None, Some(ts)) => Ok(Some(ts)),
+          (Some(ts), Some(us)) => {
+            if ts != us {
+              return Err(self.error("arms of if have different types", None));
+            }
+            Ok(Some(ts))
+          }
+        }
+      }
+      Expression::And(&crate::mir::Logical { left, right })
+      | Expression::Or(&crate::mir::Logical { left, right }) => {
+        let Some(p) = self.value(left)? else { return Ok(None); };
+        if p != OwnedType::BOOL {
+          return Err(self.error("operand of and/or isn't a bool", None));
+        }
+        // The result is `left` whenever `right` doesn't return.
+        if let Some(q) = self.value(right)? {
+          if q != OwnedType::BOOL {
+            return Err(self.error("operand of and/or isn't a bool", None));
+          }
+        }
+        Ok(Some(vec![OwnedType::BOOL]))
+      }
+      Expression::Loop(&crate::mir::Loop { name, bindings, body }) => {
+        let mut ts = Vec::with_capacity(bindings.len());
+        for &(_, exp) in bindings.iter() {
+          let Some(t) = self.value(exp)? else { return Ok(None); };
+          ts.push(t);
+        }
+        let depth = self.scope.len();
+        self.scope.extend(bindings.iter().zip(ts.iter()).map(|(&(x, _), t)| (x, t.clone())));
+        self.loops.push((name, ts));
+        self.effects = self.effects.union(EffectType::DIVERGE);
+        let r = self.expression(body);
+        let _ = self.loops.pop();
+        self.scope.truncate(depth);
+        r
+      }
+      Expression::Tuple(exps) => {
+        let mut ts = Vec::with_capacity(exps.len());
+        for &exp in exps.iter() {
+          let Some(t) = self.value(exp)? else { return Ok(None); };
+          ts.push(t);
+        }
+        Ok(Some(vec![OwnedType::Tuple(ts.into())]))
+      }
+      Expression::Field(&crate::mir::Field { tuple, index }) => {
+        let Some(t) = self.value(tuple)? else { return Ok(None); };
+        let OwnedType::Tuple(ts) = t else {
+          return Err(self.error("field of a value that isn't a tuple", None));
+        };
+        match ts.get(index as usize) {
+          Some(t) => Ok(Some(vec![t.clone()])),
+          None => Err(self.error("field index out of range", None)),
+        }
+      }
+    }
+  }
+
+  fn statement(&mut self, stmt: Statement<'a>) -> Result<()> {
+    match stmt {
+      Statement::Let(name, exp) => {
+        let Some(t) = self.value(exp)? else { return Ok(None); };
+        self.scope.push((name, t));
+        Ok(Some(()))
+      }
+      Statement::Return(index, exps) => {
+        let Some(ts) = self.values(exps)? else { return Ok(None); };
+        let Some(&rets) = self.funs[self.current].rets.get(index as usize) else {
+          return Err(self.error("return to a continuation that doesn't exist", None));
+        };
+        if ts != owned(rets) {
+          return Err(self.error("return values don't match the continuation's types", None));
+        }
+        Ok(None)
+      }
+      Statement::Goto(name, exps) => {
+        let Some(ts) = self.values(exps)? else { return Ok(None); };
+        let Some((_, us)) = self.loops.iter().rev().find(|&&(x, _)| x == name) else {
+          return Err(self.error("unknown loop", Some(name)));
+        };
+        if ts != *us {
+          return Err(self.error("goto arguments don't match the bindings of", Some(name)));
+        }
+        Ok(None)
+      }
+      Statement::LetVariable(name, _) | Statement::SetVariable(name, _) => {
+        Err(self.error("mutable variables aren't supported, found", Some(name)))
+      }
+    }
+  }
+
+  // If the body returns to its own continuation, its values are returned to
+  // the function's first continuation, as in `compile_function`.
+
+  fn function(&mut self, index: usize) -> core::result::Result<(), TypeError> {
+    let fun = &self.funs[index];
+    self.current = index;
+    self.scope.clear();
+    self.loops.clear();
+    self.effects = EffectType::PURE;
+    self.span = self.spans.function(fun.name);
+    self.scope.extend(fun.params.iter().map(|&(x, t)| (x, t.into())));
+
+    if let Some(ts) = self.expression(fun.body)? {
+      match fun.rets.first() {
+        Some(&rets) if ts == owned(rets) => {}
+        Some(_) => return Err(self.error("body doesn't match the first continuation's types", None)),
+        None => return Err(self.error("body returns, but there is no continuation", None)),
+      }
+    }
+
+    if ! fun.effects.contains(self.effects) {
+      return Err(self.error("body has effects that the function doesn't declare", None));
+    }
+
+    Ok(())
+  }
+}
+
+// `names` is only used for error messages.
+
+pub fn typecheck<'a>(funs: &[Function<'a>], names: &Interner<'_>) -> core::result::Result<Types, TypeError> {
+  typecheck_with_spans(funs, names, &Spans::new())
+}
+
+// As `typecheck`, but errors have the spans of the nodes they are in.
+
+pub fn typecheck_with_spans<'a>(funs: &[Function<'a>], names: &Interner<'_>, spans: &Spans) -> core::result::Result<Types, TypeError> {
+  let mut c = Checker {
+    funs,
+    names,
+    spans,
+    span: None,
+    current: 0,
+    components: components(funs),
+    scope: Vec::new(),
+    loops: Vec::new(),
+    effects: EffectType::PURE,
+    types: Types::new(),
+  };
+
+  for i in 0 .. funs.len() {
+    c.function(i)?;
+  }
+
+  Ok(c.types)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::arena::Arena;
+  use crate::mir::Builder;
+
+  fn undeclared(r: core::result::Result<Types, TypeError>) -> bool {
+    matches!(r, Err(e) if e.message == "body has effects that the function doesn't declare")
+  }
+
+  // $f calls $g and $g calls $f, each with `effects`, and neither loops.
+
+  fn pair<'a>(b: Builder<'a>, effects: EffectType) -> [Function<'a>; 2] {
+    let (f, g, n) = (b.symbol("f"), b.symbol("g"), b.symbol("n"));
+    let params = [(n, Type::I64)];
+    let rets: [&[Type<'_>]; 1] = [&[Type::I64]];
+    [
+      b.function(f, &params, &rets, effects, b.call(g, &[b.variable(n)])),
+      b.function(g, &params, &rets, effects, b.call(f, &[b.variable(n)])),
+    ]
+  }
+
+  #[test]
+  fn mutual_recursion_diverges() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    let b = Builder::new(&names);
+    assert!(undeclared(typecheck(&pair(b, EffectType::PURE), &names)));
+    assert!(typecheck(&pair(b, EffectType::DIVERGE), &names).is_ok());
+  }
+
+  #[test]
+  fn self_recursion_diverges() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    let b = Builder::new(&names);
+    let (f, n) = (b.symbol("f"), b.symbol("n"));
+    let fun = |effects| b.function(f, &[(n, Type::I64)], &[&[Type::I64]], effects, b.call(f, &[b.variable(n)]));
+    assert!(undeclared(typecheck(&[fun(EffectType::PURE)], &names)));
+    assert!(typecheck(&[fun(EffectType::DIVERGE)], &names).is_ok());
+  }
+
+  #[test]
+  fn calls_outside_a_cycle_are_pure() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    let b = Builder::new(&names);
+    let (f, g, n) = (b.symbol("f"), b.symbol("g"), b.symbol("n"));
+    let params = [(n, Type::I64)];
+    let rets: [&[Type<'_>]; 1] = [&[Type::I64]];
+    let funs = [
+      b.function(f, &params, &rets, EffectType::PURE, b.variable(n)),
+      b.function(g, &params, &rets, EffectType::PURE, b.call(f, &[b.call(f, &[b.variable(n)])])),
+    ];
+    assert!(typecheck(&funs, &names).is_ok());
+  }
+
+  #[test]
+  fn callers_have_the_callees_other_continuations() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    let b = Builder::new(&names);
+    let (f, g, n) = (b.symbol("f"), b.symbol("g"), b.symbol("n"));
+    let params = [(n, Type::I64)];
+    let body = b.call(g, &[b.variable(n)]);
+    let callee = || b.function(g, &params, &[&[Type::I64], &[Type::BOOL]], EffectType::PURE, b.variable(n));
+    let caller = |rets: &[&[Type<'static>]]| b.function(f, &params, rets, EffectType::PURE, body);
+    let error = |r: core::result::Result<Types, TypeError>, message| matches!(r, Err(e) if e.message == message);
+    assert!(typecheck(&[caller(&[&[Type::I64], &[Type::BOOL]]), callee()], &names).is_ok());
+    assert!(error(typecheck(&[caller(&[&[Type::I64]]), callee()], &names), "caller lacks a continuation of"));
+    assert!(error(typecheck(&[caller(&[&[Type::I64], &[Type::I64]]), callee()], &names), "caller's continuation has other types than that of"));
+  }
+
+  #[test]
+  fn loops_diverge() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    let b = Builder::new(&names);
+    let (f, n, l) = (b.symbol("f"), b.symbol("n"), b.symbol("l"));
+    let body = b.loop_(l, &[(n, b.variable(n))], b.do_(&[b.goto(l, &[b.variable(n)])]));
+    let fun = |effects| b.function(f, &[(n, Type::I64)], &[&[Type::I64]], effects, body);
+    assert!(undeclared(typecheck(&[fun(EffectType::PURE)], &names)));
+    assert!(typecheck(&[fun(EffectType::DIVERGE)], &names).is_ok());
+  }
+
+  #[test]
+  fn types_are_inferred() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    let b = Builder::new(&names);
+    let (f, n) = (b.symbol("f"), b.symbol("n"));
+    let add = Symbol::op2(ssa::Op2::ADD_I64);
+    let is_eq = Symbol::op2(ssa::Op2::IS_EQ_I64);
+    let sum = b.call(add, &[b.variable(n), Expression::ConstI64(1)]);
+    let zero = b.call(is_eq, &[b.variable(n), Expression::ConstI64(0)]);
+    let exit = b.do_(&[b.return_(0, &[b.tuple(&[Expression::ConstI64(0), Expression::ConstBool(true)])])]);
+    let pair = b.tuple(&[b.if_(zero, exit, sum), zero]);
+    let body = b.field(b.tuple(&[pair, b.variable(n)]), 0);
+    let pair_type = b.tuple_type(&[Type::I64, Type::BOOL]);
+    let funs = [b.function(f, &[(n, Type::I64)], &[&[pair_type]], EffectType::PURE, body)];
+    let types = typecheck(&funs, &names).ok().unwrap();
+    let pair_type = OwnedType::from(pair_type);
+    assert!(types.get(sum) == Some(&[OwnedType::I64][..]));
+    assert!(types.get(zero) == Some(&[OwnedType::BOOL][..]));
+    assert!(types.get(b.variable(n)).is_none());
+    assert!(types.get(exit).is_none());
+    assert!(types.get(pair) == Some(&[pair_type.clone()][..]));
+    assert!(types.get(body) == Some(&[pair_type][..]));
+  }
+
+  #[test]
+  fn type_errors() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    let b = Builder::new(&names);
+    let (f, n, m) = (b.symbol("f"), b.symbol("n"), b.symbol("m"));
+    let add = Symbol::op2(ssa::Op2::ADD_I64);
+    let check = |body| {
+      let funs = [b.function(f, &[(n, Type::I64)], &[&[Type::I64]], EffectType::PURE, body)];
+      typecheck(&funs, &names).err().map(|e| e.message)
+    };
+    assert!(check(b.variable(n)).is_none());
+    assert!(check(b.variable(m)) == Some("unknown variable"));
+    assert!(check(b.call(m, &[])) == Some("unknown function"));
+    assert!(check(b.call(add, &[b.variable(n)])) == Some("wrong number of arguments to"));
+    assert!(check(b.call(add, &[b.variable(n), Expression::ConstI32(1)])) == Some("argument of the wrong type to"));
+    assert!(check(b.if_(b.variable(n), b.variable(n), b.variable(n))) == Some("condition isn't a bool"));
+    assert!(check(b.if_(Expression::ConstBool(true), b.variable(n), Expression::ConstBool(false))) == Some("arms of if have different types"));
+    assert!(check(b.field(b.variable(n), 0)) == Some("field of a value that isn't a tuple"));
+    assert!(check(b.field(b.tuple(&[b.variable(n)]), 1)) == Some("field index out of range"));
+    assert!(check(Expression::ConstBool(true)) == Some("body doesn't match the first continuation's types"));
+  }
+
+  #[test]
+  fn errors_have_the_innermost_span() {
+    let arena = Arena::new();
+    let names = Interner::new(&arena);
+    let b = Builder::new(&names);
+    let (f, n, m) = (b.symbol("f"), b.symbol("n"), b.symbol("m"));
+    let add = Symbol::op2(ssa::Op2::ADD_I64);
+    let bad = b.call(add, &[b.variable(n), b.variable(m)]);
+    let outer = b.call(add, &[b.variable(n), bad]);
+    let body = b.do_(&[b.return_(0, &[outer])]);
+    let Expression::Do(stmts) = body else { panic!() };
+    let funs = [b.function(f, &[(n, Type::I64)], &[&[Type::I64]], EffectType::PURE, body)];
+    let span = |start| Span { file: 1, start, end: start + 1 };
+    let error = |spans: &Spans| typecheck_with_spans(&funs, &names, spans).err().and_then(|e| e.span);
+
+    let mut spans = Spans::new();
+    assert!(error(&spans).is_none());
+    spans.set_function(f, span(0));
+    assert!(error(&spans) == Some(span(0)));
+    spans.set_statement(&stmts[0], span(1));
+    assert!(error(&spans) == Some(span(1)));
+    let _ = spans.set_expression(outer, span(2));
+    assert!(error(&spans) == Some(span(2)));
+    let _ = spans.set_expression(bad, span(3));
+    assert!(error(&spans) == Some(span(3)));
+    assert!(typecheck_with_spans(&funs, &names, &spans).err().is_some_and(|e| e.to_string() == "1:3..4: in $f: unknown variable $m"));
+  }
+}
@@ -0,0 +1,829 @@
+//! ssa-to-ssa transformations
+
+use crate::ssa;
+use crate::ssa::Builder;
+use crate::ssa::Instruction;
+use crate::ssa::Type;
+use crate::ssa::TypeTable;
+use crate::ssa::Value;
+use crate::ssa::Variable;
+use std::time::Duration;
+use std::time::Instant;
+
+// A pass reads a sequence of functions and writes the transformed sequence
+// to a fresh builder. Passes assume that their input verifies, and panic on
+// code that doesn't decode.
+
+pub type Pass = fn(&[u8], &mut Builder);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OptLevel {
+  O0,
+  O1,
+  O2,
+}
+
+pub struct PassStats {
+  pub name: &'static str,
+  pub time: Duration,
+  pub instructions_before: usize,
+  pub instructions_after: usize,
+}
+
+pub struct PassError {
+  pub name: &'static str,
+  pub error: ssa::VerifyError,
+}
+
+pub struct PassManager {
+  passes: Vec<(&'static str, Pass)>,
+  verify: bool,
+  print: bool,
+  stats: Vec<PassStats>,
+}
+
+impl core::fmt::Display for OptLevel {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    let name =
+      match self {
+        Self::O0 => "O0",
+        Self::O1 => "O1",
+        Self::O2 => "O2",
+      };
+    write!(f, "{}", name)
+  }
+}
+
+impl core::fmt::Display for PassStats {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    let delta = self.instructions_after as isize - self.instructions_before as isize;
+    write!(f, "{}: {:?}, {} -> {} instructions ({:+})",
+      self.name, self.time, self.instructions_before, self.instructions_after, delta)
+  }
+}
+
+impl core::fmt::Display for PassError {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "after {}: {}", self.name, self.error)
+  }
+}
+
+impl PassManager {
+  pub fn new() -> Self {
+    Self {
+      passes: Vec::new(),
+      verify: false,
+      print: false,
+      stats: Vec::new(),
+    }
+  }
+
+  pub fn with_level(level: OptLevel) -> Self {
+    let mut pm = Self::new();
+    match level {
+      OptLevel::O0 => {
+        pm.add("scalarize", scalarize);
+      }
+      OptLevel::O1 => {
+        pm.add("scalarize", scalarize);
+        pm.add("dead-values", dead_values);
+      }
+      OptLevel::O2 => {
+        pm.add("scalarize", scalarize);
+        pm.add("forward-selects", forward_selects);
+        pm.add("dead-values", dead_values);
+      }
+    }
+    pm
+  }
+
+  pub fn add(&mut self, name: &'static str, pass: Pass) {
+    self.passes.push((name, pass));
+  }
+
+  // Run the verifier after every pass. The input is always verified.
+
+  pub fn set_verify(&mut self, verify: bool) {
+    self.verify = verify;
+  }
+
+  // Print the input and the output of every pass.
+
+  pub fn set_print(&mut self, print: bool) {
+    self.print = print;
+  }
+
+  // Statistics for every pass of the most recent `run`.
+
+  pub fn stats(&self) -> &[PassStats] {
+    &self.stats
+  }
+
+  pub fn run(&mut self, code: &[u8]) -> Result<Vec<u8>, PassError> {
+    self.stats.clear();
+
+    Self::check(true, self.print, "input", code)?;
+
+    let encoding = encoding(code);
+    let mut last: Option<Builder> = None;
+
+    for &(name, pass) in self.passes.iter() {
+      let code = last.as_ref().map_or(code, |b| b.view());
+      let mut out = Builder::with_encoding(encoding);
+      let start = Instant::now();
+      pass(code, &mut out);
+      let time = start.elapsed();
+      self.stats.push(PassStats {
+        name,
+        time,
+        instructions_before: count_instructions(code),
+        instructions_after: count_instructions(out.view()),
+      });
+      Self::check(self.verify, self.print, name, out.view())?;
+      last = Some(out);
+    }
+
+    Ok(last.as_ref().map_or(code, |b| b.view()).to_vec())
+  }
+
+  fn check(verify: bool, print: bool, name: &'static str, code: &[u8]) -> Result<(), PassError> {
+    if print {
+      print!("; {}\n", name);
+      ssa::display(code);
+      print!("\n");
+    }
+    if verify {
+      ssa::verify(code).map_err(|error| PassError { name, error })?;
+    }
+    Ok(())
+  }
+}
+
+impl Default for PassManager {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+// The encoding of the first function, which passes keep for their output.
+
+pub fn encoding(code: &[u8]) -> ssa::Encoding {
+  let mut r = code;
+  let mut s = ssa::ReadState::new();
+  loop {
+    match read(&mut r, &mut s) {
+      Some(Instruction::Function(..)) | None => { break; }
+      Some(_) => {}
+    }
+  }
+  s.encoding()
+}
+
+// Reads the next instruction of code that is known to decode.
+
+fn read<'a>(r: &mut &'a [u8], s: &mut ssa::ReadState) -> Option<Instruction<'a>> {
+  match ssa::read_with_state(r, s) {
+    Ok(inst) => inst,
+    Err(e) => panic!("{}", e),
+  }
+}
+
+fn count_instructions(code: &[u8]) -> usize {
+  let mut r = code;
+  let mut s = ssa::ReadState::new();
+  let mut n = 0;
+  while read(&mut r, &mut s).is_some() {
+    n += 1;
+  }
+  n
+}
+
+// Splits a sequence of functions into the encodings of each function. Any
+// type definitions are kept at the start of the first.
+
+fn functions(code: &[u8]) -> Vec<&[u8]> {
+  let mut r = code;
+  let mut s = ssa::ReadState::new();
+  let mut starts = Vec::new();
+  loop {
+    let offset = code.len() - r.len();
+    match read(&mut r, &mut s) {
+      None => { break; }
+      Some(Instruction::Function(..)) => { starts.push(offset); }
+      Some(_) => {}
+    }
+  }
+  let end = code.len() - r.len();
+  let mut funs = Vec::with_capacity(starts.len());
+  if let Some(first) = starts.first_mut() {
+    *first = 0;
+  }
+  for (i, &start) in starts.iter().enumerate() {
+    let stop = starts.get(i + 1).copied().unwrap_or(end);
+    funs.push(&code[start .. stop]);
+  }
+  funs
+}
+
+// Copies instructions from one function into a builder, renumbering values
+// and variables as instructions are added or dropped. Labels are copied
+// unchanged, so passes using a rewriter must not add or remove blocks.
+
+struct Rewriter {
+  values: Vec<Value>,
+  variables: Vec<Variable>,
+}
+
+impl Rewriter {
+  fn new() -> Self {
+    Self {
+      values: Vec::new(),
+      variables: Vec::new(),
+    }
+  }
+
+  fn value(&self, x: Value) -> Value {
+    self.values[x.0 as usize]
+  }
+
+  fn variable(&self, x: Variable) -> Variable {
+    self.variables[x.0 as usize]
+  }
+
+  // Make the next old value an alias for an existing new value instead of
+  // copying its definition.
+
+  fn alias(&mut self, x: Value) {
+    self.values.push(x);
+  }
+
+  fn copy(&mut self, out: &mut Builder, inst: &Instruction<'_>) {
+    match *inst {
+      Instruction::TypeStruct(ref fields) => {
+        let _ = out.emit_struct(fields.iter().count() as u32);
+        for t in fields.iter() {
+          let _ = out.emit_type(t);
+        }
+      }
+      Instruction::TypeArray(t, n) => {
+        let _ = out.emit_array(t, n);
+      }
+      Instruction::Function(ref konts, ref args) => {
+        self.values.clear();
+        self.variables.clear();
+        out.emit_function(konts.len(), args.iter().count() as u32);
+        for t in args.iter() {
+          self.values.push(out.emit_param(t));
+        }
+        for rets in konts.iter() {
+          out.emit_results(rets.iter().count() as u32);
+          for t in rets.iter() {
+            let _ = out.emit_type(t);
+          }
+        }
+      }
+      Instruction::Case() => {
+        let _ = out.emit_case();
+      }
+      Instruction::Join(ref args) => {
+        let _ = out.emit_join(args.iter().count() as u32);
+        for t in args.iter() {
+          self.values.push(out.emit_param(t));
+        }
+      }
+      Instruction::Kont(ref args) => {
+        let _ = out.emit_kont(args.iter().count() as u32);
+        for t in args.iter() {
+          self.values.push(out.emit_param(t));
+        }
+      }
+      Instruction::ConstBool(p) => {
+        self.values.push(out.emit_const_bool(p));
+      }
+      Instruction::ConstI32(c) => {
+        self.values.push(out.emit_const_i32(c));
+      }
+      Instruction::ConstI64(c) => {
+        self.values.push(out.emit_const_i64(c));
+      }
+      Instruction::ConstI5(c) => {
+        self.values.push(out.emit_const_i5(c));
+      }
+      Instruction::ConstI6(c) => {
+        self.values.push(out.emit_const_i6(c));
+      }
+      Instruction::ConstI7(c) => {
+        self.values.push(out.emit_const_i7(c));
+      }
+      Instruction::ConstF32(c) => {
+        self.values.push(out.emit_const_f32(c));
+      }
+      Instruction::ConstF64(c) => {
+        self.values.push(out.emit_const_f64(c));
+      }
+      Instruction::Op1(t, x) => {
+        self.values.push(out.emit_op1(t, self.value(x)));
+      }
+      Instruction::Op2(t, x, y) => {
+        self.values.push(out.emit_op2(t, self.value(x), self.value(y)));
+      }
+      Instruction::Select(p, x, y) => {
+        self.values.push(out.emit_select(self.value(p), self.value(x), self.value(y)));
+      }
+      Instruction::LetVariable(x) => {
+        self.variables.push(out.emit_let_variable(self.value(x)));
+      }
+      Instruction::GetVariable(v) => {
+        self.values.push(out.emit_get_variable(self.variable(v)));
+      }
+      Instruction::SetVariable(v, x) => {
+        out.emit_set_variable(self.variable(v), self.value(x));
+      }
+      Instruction::Alloca(size, align) => {
+        self.values.push(out.emit_alloca(size, 1 << align));
+      }
+      Instruction::Load(t, flags, x, offset) => {
+        self.values.push(out.emit_load(t, flags, self.value(x), offset));
+      }
+      Instruction::Store(t, flags, x, offset, y) => {
+        out.emit_store(t, flags, self.value(x), offset, self.value(y));
+      }
+      Instruction::Build(t, ref args) => {
+        let xs: Vec<_> = args.iter().map(|x| self.value(x)).collect();
+        self.values.push(out.emit_build(t, &xs));
+      }
+      Instruction::Extract(x, i) => {
+        self.values.push(out.emit_extract(self.value(x), i));
+      }
+      Instruction::Insert(x, i, y) => {
+        self.values.push(out.emit_insert(self.value(x), i, self.value(y)));
+      }
+      Instruction::If(p, a, b) => {
+        let _ = out.emit_if(self.value(p), a, b);
+      }
+      Instruction::Goto(a, ref args) => {
+        let _ = out.emit_goto(a, args.iter().count() as u32);
+        for x in args.iter() {
+          out.emit_value(self.value(x));
+        }
+      }
+      Instruction::Return(index, ref args) => {
+        out.emit_return(index, args.iter().count() as u32);
+        for x in args.iter() {
+          out.emit_value(self.value(x));
+        }
+      }
+      Instruction::Call(f, ref args, ref konts) => {
+        out.emit_call(f, args.iter().count() as u32, konts.iter().count() as u32);
+        for x in args.iter() {
+          out.emit_value(self.value(x));
+        }
+        for a in konts.iter() {
+          let _ = out.emit_label(a);
+        }
+      }
+      Instruction::TailCall(f, ref args) => {
+        out.emit_tailcall(f, args.iter().count() as u32);
+        for x in args.iter() {
+          out.emit_value(self.value(x));
+        }
+      }
+    }
+  }
+}
+
+// Whether an instruction defines a value, and may be dropped if that value
+// is unused.
+
+fn is_pure(inst: &Instruction<'_>) -> bool {
+  match inst {
+    | Instruction::ConstBool(_)
+    | Instruction::ConstI32(_)
+    | Instruction::ConstI64(_)
+    | Instruction::ConstI5(_)
+    | Instruction::ConstI6(_)
+    | Instruction::ConstI7(_)
+    | Instruction::ConstF32(_)
+    | Instruction::ConstF64(_)
+    | Instruction::Op1(..)
+    | Instruction::Op2(..)
+    | Instruction::Select(..)
+    | Instruction::GetVariable(_)
+    | Instruction::Build(..)
+    | Instruction::Extract(..)
+    | Instruction::Insert(..) => true,
+    _ => false,
+  }
+}
+
+// Calls `f` on every value operand of an instruction.
+
+fn for_each_operand(inst: &Instruction<'_>, mut f: impl FnMut(Value)) {
+  match *inst {
+    Instruction::Op1(_, x) => { f(x); }
+    Instruction::Op2(_, x, y) => { f(x); f(y); }
+    Instruction::Select(p, x, y) => { f(p); f(x); f(y); }
+    Instruction::LetVariable(x) => { f(x); }
+    Instruction::SetVariable(_, x) => { f(x); }
+    Instruction::Load(_, _, x, _) => { f(x); }
+    Instruction::Store(_, _, x, _, y) => { f(x); f(y); }
+    Instruction::Build(_, ref args) => { for x in args.iter() { f(x); } }
+    Instruction::Extract(x, _) => { f(x); }
+    Instruction::Insert(x, _, y) => { f(x); f(y); }
+    Instruction::If(p, _, _) => { f(p); }
+    Instruction::Goto(_, ref args) => { for x in args.iter() { f(x); } }
+    Instruction::Return(_, ref args) => { for x in args.iter() { f(x); } }
+    Instruction::Call(_, ref args, _) => { for x in args.iter() { f(x); } }
+    Instruction::TailCall(_, ref args) => { for x in args.iter() { f(x); } }
+    _ => {}
+  }
+}
+
+// The number of values defined by an instruction, counting block
+// parameters.
+
+fn num_values(inst: &Instruction<'_>) -> usize {
+  match inst {
+    Instruction::Function(_, args) => args.iter().count(),
+    Instruction::Join(args) => args.iter().count(),
+    Instruction::Kont(args) => args.iter().count(),
+    Instruction::Alloca(..) | Instruction::Load(..) => 1,
+    inst if is_pure(inst) => 1,
+    _ => 0,
+  }
+}
+
+// Removes pure instructions whose values are never used.
+//
+// Because values are used only after their definitions in the encoding, a
+// single backwards sweep also catches values whose only uses are themselves
+// dead.
+
+pub fn dead_values(code: &[u8], out: &mut Builder) {
+  for fun in functions(code) {
+    let mut insts = Vec::new();
+    let mut r = fun;
+    let mut s = ssa::ReadState::new();
+    while let Some(inst) = read(&mut r, &mut s) {
+      insts.push(inst);
+    }
+
+    let mut defs = Vec::with_capacity(insts.len());
+    let mut nvalues = 0;
+    for inst in insts.iter() {
+      defs.push(nvalues);
+      nvalues += num_values(inst);
+    }
+
+    let mut uses = vec![0u32; nvalues];
+    for inst in insts.iter() {
+      for_each_operand(inst, |x| uses[x.0 as usize] += 1);
+    }
+
+    let mut dead = vec![false; insts.len()];
+    for i in (0 .. insts.len()).rev() {
+      if is_pure(&insts[i]) && uses[defs[i]] == 0 {
+        dead[i] = true;
+        for_each_operand(&insts[i], |x| uses[x.0 as usize] -= 1);
+      }
+    }
+
+    let mut rw = Rewriter::new();
+    for (i, inst) in insts.iter().enumerate() {
+      if dead[i] {
+        // The value is never used, so any placeholder will do.
+        rw.alias(Value(0));
+      } else {
+        rw.copy(out, inst);
+      }
+    }
+  }
+}
+
+// Replaces `select` instructions that have a constant condition or two
+// identical arms by the value they select.
+
+pub fn forward_selects(code: &[u8], out: &mut Builder) {
+  let mut rw = Rewriter::new();
+  let mut consts = Vec::new();
+
+  for fun in functions(code) {
+    consts.clear();
+    let mut r = fun;
+    let mut s = ssa::ReadState::new();
+    while let Some(inst) = read(&mut r, &mut s) {
+      match inst {
+        Instruction::ConstBool(p) => {
+          consts.push(Some(p));
+          rw.copy(out, &inst);
+        }
+        Instruction::Select(p, x, y) if x == y || consts[p.0 as usize].is_some() => {
+          let z = if consts[p.0 as usize] == Some(false) { y } else { x };
+          consts.push(consts[z.0 as usize]);
+          rw.alias(rw.value(z));
+        }
+        inst => {
+          for _ in 0 .. num_values(&inst) {
+            consts.push(None);
+          }
+          rw.copy(out, &inst);
+        }
+      }
+    }
+  }
+}
+
+// Replaces every aggregate by the scalars it is made of: aggregate
+// parameters, arguments and results become several, `build`, `extract` and
+// `insert` only rename values, and `select` and variables are repeated for
+// each scalar. The type definitions are dropped.
+
+pub fn scalarize(code: &[u8], out: &mut Builder) {
+  let mut sc = Scalarizer {
+    table: TypeTable::read(code),
+    types: Vec::new(),
+    values: Vec::new(),
+    variables: Vec::new(),
+    rw: Rewriter::new(),
+  };
+
+  for fun in functions(code) {
+    let mut r = fun;
+    let mut s = ssa::ReadState::new();
+    while let Some(inst) = read(&mut r, &mut s) {
+      sc.rewrite(out, &inst);
+    }
+  }
+}
+
+// The type of every value of the function being scalarized, with the
+// scalars that replace it, and the same for variables. The rewriter maps
+// each value and variable to its first scalar, for copying instructions
+// that are already scalar.
+
+struct Scalarizer {
+  table: TypeTable,
+  types: Vec<Type>,
+  values: Vec<Vec<Value>>,
+  variables: Vec<(Type, Vec<Variable>)>,
+  rw: Rewriter,
+}
+
+impl Scalarizer {
+  fn define(&mut self, t: Type, xs: Vec<Value>) {
+    self.rw.alias(xs.first().copied().unwrap_or(Value(0)));
+    self.types.push(t);
+    self.values.push(xs);
+  }
+
+  fn flatten(&self, types: &ssa::TypeList<'_>) -> Vec<Type> {
+    let mut flat = Vec::new();
+    for t in types.iter() {
+      self.table.flatten(t, &mut flat);
+    }
+    flat
+  }
+
+  fn args(&self, args: &ssa::ValueList<'_>) -> Vec<Value> {
+    args.iter().flat_map(|x| self.values[x.0 as usize].iter().copied()).collect()
+  }
+
+  fn params(&mut self, out: &mut Builder, types: &ssa::TypeList<'_>) {
+    let mut flat = Vec::new();
+    for t in types.iter() {
+      flat.clear();
+      self.table.flatten(t, &mut flat);
+      let xs = flat.iter().map(|&u| out.emit_param(u)).collect();
+      self.define(t, xs);
+    }
+  }
+
+  fn rewrite(&mut self, out: &mut Builder, inst: &Instruction<'_>) {
+    match *inst {
+      Instruction::TypeStruct(_) | Instruction::TypeArray(..) => {}
+      Instruction::Function(ref konts, ref args) => {
+        self.types.clear();
+        self.values.clear();
+        self.variables.clear();
+        self.rw.values.clear();
+        self.rw.variables.clear();
+        out.emit_function(konts.len(), self.flatten(args).len() as u32);
+        self.params(out, args);
+        for rets in konts.iter() {
+          let flat = self.flatten(&rets);
+          out.emit_results(flat.len() as u32);
+          for &t in flat.iter() {
+            let _ = out.emit_type(t);
+          }
+        }
+      }
+      Instruction::Join(ref args) => {
+        let _ = out.emit_join(self.flatten(args).len() as u32);
+        self.params(out, args);
+      }
+      Instruction::Kont(ref args) => {
+        let _ = out.emit_kont(self.flatten(args).len() as u32);
+        self.params(out, args);
+      }
+      Instruction::Select(p, x, y) => {
+        let p = self.values[p.0 as usize][0];
+        let mut zs = Vec::new();
+        for (&x, &y) in self.values[x.0 as usize].iter().zip(self.values[y.0 as usize].iter()) {
+          zs.push(out.emit_select(p, x, y));
+        }
+        self.define(self.types[x.0 as usize], zs);
+      }
+      Instruction::LetVariable(x) => {
+        let vs: Vec<_> = self.values[x.0 as usize].iter().map(|&x| out.emit_let_variable(x)).collect();
+        self.rw.variables.push(vs.first().copied().unwrap_or(Variable(0)));
+        self.variables.push((self.types[x.0 as usize], vs));
+      }
+      Instruction::GetVariable(v) => {
+        let (t, ref vs) = self.variables[v.0 as usize];
+        let xs = vs.iter().map(|&v| out.emit_get_variable(v)).collect();
+        self.define(t, xs);
+      }
+      Instruction::SetVariable(v, x) => {
+        for (&v, &x) in self.variables[v.0 as usize].1.iter().zip(self.values[x.0 as usize].iter()) {
+          out.emit_set_variable(v, x);
+        }
+      }
+      Instruction::Build(t, ref args) => {
+        let xs = self.args(args);
+        self.define(t, xs);
+      }
+      Instruction::Extract(x, i) => {
+        let t = self.types[x.0 as usize];
+        let u = self.table.field(t, i);
+        let start = self.table.offset(t, i) as usize;
+        let xs = self.values[x.0 as usize][start .. start + self.table.width(u) as usize].to_vec();
+        self.define(u, xs);
+      }
+      Instruction::Insert(x, i, y) => {
+        let t = self.types[x.0 as usize];
+        let start = self.table.offset(t, i) as usize;
+        let mut xs = self.values[x.0 as usize].clone();
+        let ys = &self.values[y.0 as usize];
+        xs[start .. start + ys.len()].copy_from_slice(ys);
+        self.define(t, xs);
+      }
+      Instruction::Goto(a, ref args) => {
+        let xs = self.args(args);
+        let _ = out.emit_goto(a, xs.len() as u32);
+        xs.iter().for_each(|&x| out.emit_value(x));
+      }
+      Instruction::Return(index, ref args) => {
+        let xs = self.args(args);
+        out.emit_return(index, xs.len() as u32);
+        xs.iter().for_each(|&x| out.emit_value(x));
+      }
+      Instruction::Call(f, ref args, ref konts) => {
+        let xs = self.args(args);
+        out.emit_call(f, xs.len() as u32, konts.iter().count() as u32);
+        xs.iter().for_each(|&x| out.emit_value(x));
+        for a in konts.iter() {
+          let _ = out.emit_label(a);
+        }
+      }
+      Instruction::TailCall(f, ref args) => {
+        let xs = self.args(args);
+        out.emit_tailcall(f, xs.len() as u32);
+        xs.iter().for_each(|&x| out.emit_value(x));
+      }
+      ref inst => {
+        // The remaining instructions are scalar, with scalar operands.
+        let n = self.rw.values.len();
+        self.rw.copy(out, inst);
+        let xs: Vec<_> = self.rw.values.drain(n ..).collect();
+        for x in xs {
+          self.define(Type::UNKNOWN, vec![x]);
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A function of one i64 parameter that computes its sum with itself twice
+  // over, uses only the first, and then selects between its parameter and
+  // the sum on `p`.
+
+  fn sample(p: bool) -> Builder {
+    let mut b = Builder::new();
+    b.emit_function(1, 1);
+    let x = b.emit_param(Type::I64);
+    b.emit_results(1);
+    let _ = b.emit_type(Type::I64);
+    let y = b.emit_op2(ssa::Op2::ADD_I64, x, x);
+    let _ = b.emit_op2(ssa::Op2::ADD_I64, x, x);
+    let q = b.emit_const_bool(p);
+    let z = b.emit_select(q, x, y);
+    b.emit_return(0, 1);
+    b.emit_value(z);
+    b
+  }
+
+  fn selects(code: &[u8]) -> usize {
+    let mut r = code;
+    let mut s = ssa::ReadState::new();
+    let mut n = 0;
+    while let Some(inst) = read(&mut r, &mut s) {
+      if matches!(inst, Instruction::Select(..)) {
+        n += 1;
+      }
+    }
+    n
+  }
+
+  #[test]
+  fn dead_values_are_counted() {
+    let b = sample(true);
+    let mut pm = PassManager::new();
+    pm.add("dead-values", dead_values);
+    let code = pm.run(b.view()).ok().unwrap();
+    let stats = pm.stats();
+    assert!(stats.len() == 1);
+    assert!(stats[0].instructions_before == count_instructions(b.view()));
+    assert!(stats[0].instructions_before - stats[0].instructions_after == 1);
+    assert!(count_instructions(&code) == stats[0].instructions_after);
+  }
+
+  #[test]
+  fn constant_selects_are_forwarded() {
+    for p in [false, true] {
+      let b = sample(p);
+      let mut out = Builder::new();
+      forward_selects(b.view(), &mut out);
+      assert!(ssa::verify(out.view()).is_ok());
+      assert!(selects(b.view()) == 1);
+      assert!(selects(out.view()) == 0);
+      assert!(count_instructions(out.view()) == count_instructions(b.view()) - 1);
+    }
+  }
+
+  #[test]
+  fn forwarded_selects_keep_their_value() {
+    // With both passes, the sum survives only when it's selected.
+    let mut pm = PassManager::new();
+    pm.add("forward-selects", forward_selects);
+    pm.add("dead-values", dead_values);
+    let t = pm.run(sample(true).view()).ok().unwrap();
+    let f = pm.run(sample(false).view()).ok().unwrap();
+    assert!(count_instructions(&t) + 1 == count_instructions(&f));
+  }
+
+  // A function of a struct of an i64 and a bool, and of a bool `q`, that
+  // returns either the struct or the struct with one added to its first
+  // field, as selected by `q`.
+
+  fn aggregates() -> Builder {
+    let mut b = Builder::new();
+    let t = b.emit_struct(2);
+    let _ = b.emit_type(Type::I64);
+    let _ = b.emit_type(Type::BOOL);
+    b.emit_function(1, 2);
+    let x = b.emit_param(t);
+    let q = b.emit_param(Type::BOOL);
+    b.emit_results(1);
+    let _ = b.emit_type(t);
+    let one = b.emit_const_i64(1);
+    let y = b.emit_extract(x, 0);
+    let z = b.emit_op2(ssa::Op2::ADD_I64, y, one);
+    let w = b.emit_insert(x, 0, z);
+    let v = b.emit_select(q, x, w);
+    b.emit_return(0, 1);
+    b.emit_value(v);
+    b
+  }
+
+  #[test]
+  fn scalarize_splits_aggregates() {
+    let b = aggregates();
+    let mut out = Builder::new();
+    scalarize(b.view(), &mut out);
+    assert!(ssa::verify(out.view()).is_ok());
+    assert!(TypeTable::read(out.view()).is_empty());
+    assert!(selects(out.view()) == 2);
+    let mut r = out.view();
+    let mut s = ssa::ReadState::new();
+    let Some(Instruction::Function(konts, args)) = read(&mut r, &mut s) else { panic!() };
+    assert!(args.iter().collect::<Vec<_>>() == [Type::I64, Type::BOOL, Type::BOOL]);
+    assert!(konts.iter().map(|ts| ts.iter().collect::<Vec<_>>()).collect::<Vec<_>>() == [vec![Type::I64, Type::BOOL]]);
+    for (q, x) in [(1, 5), (0, 6)] {
+      for code in [b.view(), out.view()] {
+        assert!(matches!(crate::interp::run(code, 0, &[5, 1, q]), Ok(crate::interp::Return { values, .. }) if values == [x, 1]));
+      }
+    }
+  }
+
+  #[test]
+  fn o0_keeps_its_input() {
+    let b = sample(true);
+    let mut pm = PassManager::with_level(OptLevel::O0);
+    pm.set_verify(true);
+    assert!(matches!(pm.run(b.view()), Ok(code) if code == b.view()));
+  }
+}
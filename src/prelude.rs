@@ -1,6 +1,3 @@
-// pub(crate) use oxcart::Store;
-// pub(crate) use oxcart::Arena;
-
 pub(crate) use pop::ptr;
 
 pub(crate) use crate::byte_slice::*;
@@ -1,9 +1,15 @@
 use crate::prelude::*;
+use std::collections::HashMap;
 
 pub enum Instruction<'a> {
+  // type definition
+
+  TypeStruct(TypeList<'a>),
+  TypeArray(Type, u32),
+
   // block entry
 
-  Function(u32, TypeList<'a>),
+  Function(KontList<'a>, TypeList<'a>),
   Case(),
   Join(TypeList<'a>),
   Kont(TypeList<'a>),
@@ -13,18 +19,31 @@ pub enum Instruction<'a> {
   ConstBool(bool),
   ConstI32(u32),
   ConstI64(u64),
+  ConstI5(u8),
+  ConstI6(u8),
+  ConstI7(u8),
+  ConstF32(f32),
+  ConstF64(f64),
   Op1(Op1, Value),
   Op2(Op2, Value, Value),
   Select(Value, Value, Value),
   LetVariable(Value),
   GetVariable(Variable),
   SetVariable(Variable, Value),
+  Alloca(u32, u8),
+  Load(MemType, MemFlags, Value, u32),
+  Store(MemType, MemFlags, Value, u32, Value),
+  Build(Type, ValueList<'a>),
+  Extract(Value, u32),
+  Insert(Value, u32, Value),
 
   // block terminator
 
   If(Value, Label, Label),
   Return(u32, ValueList<'a>),
   Goto(Label, ValueList<'a>),
+  Call(u32, ValueList<'a>, LabelList<'a>),
+  TailCall(u32, ValueList<'a>),
 }
 
 #[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
@@ -39,6 +58,12 @@ pub struct Op1(pub u8);
 #[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
 pub struct Op2(pub u8);
 
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
+pub struct MemType(pub u8);
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
+pub struct MemFlags(pub u8);
+
 #[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
 pub struct Value(pub u32);
 
@@ -50,7 +75,15 @@ pub struct Variable(pub u32);
 
 pub struct TypeList<'a>(&'a [u8]);
 
-pub struct ValueList<'a>(&'a [u8]);
+// In the compact encoding, the values are deltas back from the given value.
+
+pub struct ValueList<'a>(&'a [u8], Option<u32>);
+
+pub struct LabelList<'a>(&'a [u8]);
+
+// The result types of each of a function's continuations.
+
+pub struct KontList<'a>(u32, &'a [u8]);
 
 impl Tag {
   pub const FUNCTION: Self = Self(0x01);
@@ -61,6 +94,11 @@ impl Tag {
   pub const CONST_BOOL: Self = Self(0x0f);
   pub const CONST_I32: Self = Self(0x08);
   pub const CONST_I64: Self = Self(0x09);
+  pub const CONST_F32: Self = Self(0x13);
+  pub const CONST_F64: Self = Self(0x14);
+  pub const CONST_I5: Self = Self(0x16);
+  pub const CONST_I6: Self = Self(0x17);
+  pub const CONST_I7: Self = Self(0x18);
   pub const OP1: Self = Self(0x05);
   pub const OP2: Self = Self(0x06);
   pub const SELECT: Self = Self(0x07);
@@ -69,34 +107,141 @@ impl Tag {
   pub const GET_VARIABLE: Self = Self(0x11);
   pub const SET_VARIABLE: Self = Self(0x12);
 
+  pub const ALLOCA: Self = Self(0x19);
+  pub const LOAD: Self = Self(0x1a);
+  pub const STORE: Self = Self(0x1b);
+
+  pub const TYPE_STRUCT: Self = Self(0x1c);
+  pub const TYPE_ARRAY: Self = Self(0x1d);
+  pub const BUILD: Self = Self(0x1e);
+  pub const EXTRACT: Self = Self(0x1f);
+  pub const INSERT: Self = Self(0x20);
+
   pub const IF: Self = Self(0x0a);
   pub const GOTO: Self = Self(0x0b);
   pub const RETURN: Self = Self(0x0c);
   pub const CALL: Self = Self(0x0d);
   pub const TAILCALL: Self = Self(0x0e);
+
+  pub const FUNCTION_COMPACT: Self = Self(0x15);
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::FUNCTION | Self::FUNCTION_COMPACT => "function",
+      Self::CASE => "case",
+      Self::JOIN => "join",
+      Self::KONT => "kont",
+      Self::CONST_BOOL => "const.bool",
+      Self::CONST_I32 => "const.i32",
+      Self::CONST_I64 => "const.i64",
+      Self::CONST_F32 => "const.f32",
+      Self::CONST_F64 => "const.f64",
+      Self::CONST_I5 => "const.i5",
+      Self::CONST_I6 => "const.i6",
+      Self::CONST_I7 => "const.i7",
+      Self::OP1 => "op1",
+      Self::OP2 => "op2",
+      Self::SELECT => "select",
+      Self::LET_VARIABLE => "let_variable",
+      Self::GET_VARIABLE => "get_variable",
+      Self::SET_VARIABLE => "set_variable",
+      Self::ALLOCA => "alloca",
+      Self::LOAD => "load",
+      Self::STORE => "store",
+      Self::TYPE_STRUCT => "struct",
+      Self::TYPE_ARRAY => "array",
+      Self::BUILD => "build",
+      Self::EXTRACT => "extract",
+      Self::INSERT => "insert",
+      Self::IF => "if",
+      Self::GOTO => "goto",
+      Self::RETURN => "return",
+      Self::CALL => "call",
+      Self::TAILCALL => "tailcall",
+      _ => "unknown",
+    }
+  }
 }
 
 impl Type {
+  pub const UNKNOWN: Self = Self(0x00);
   pub const BOOL: Self = Self(0x02);
+  // Small integers, for shift and rotate amounts, bit indexes and bit
+  // counts. Operations that take them can't be given out of range amounts.
   pub const I5: Self = Self(0x03);
   pub const I6: Self = Self(0x04);
+  pub const I7: Self = Self(0x05);
   pub const I32: Self = Self(0x07);
   pub const I64: Self = Self(0x08);
   pub const Ref: Self = Self(0x09);
+  pub const F32: Self = Self(0x0a);
+  pub const F64: Self = Self(0x0b);
+
+  // Aggregate types are numbered from here, in the order of their
+  // definitions.
+  pub const FIRST_AGGREGATE: Self = Self(0x80);
+
+  pub fn aggregate(i: u32) -> Self {
+    assert!(i < MAX_AGGREGATES);
+    Self(Self::FIRST_AGGREGATE.0 + i as u8)
+  }
+
+  pub fn is_aggregate(self) -> bool {
+    self >= Self::FIRST_AGGREGATE
+  }
 
   pub fn name(self) -> &'static str {
     self.info().0
   }
 
+  // Whether `x` represents a value of this type, as described for `Op1` and
+  // `Op2`. Any address is a `ref`, and aggregates have no representation.
+
+  pub fn is_value(self, x: u64) -> bool {
+    match self {
+      Self::BOOL => x <= 1,
+      Self::I5 => x < 1 << 5,
+      Self::I6 => x < 1 << 6,
+      Self::I7 => x < 1 << 7,
+      Self::I32 | Self::F32 => x <= u32::MAX as u64,
+      Self::I64 | Self::F64 | Self::Ref => true,
+      _ => false,
+    }
+  }
+
   fn info(self)
     -> &'static (
       &'static str,
     )
   {
     match self {
+      Self::BOOL => &(
+        "bool",
+      ),
+      Self::I5 => &(
+        "i5",
+      ),
+      Self::I6 => &(
+        "i6",
+      ),
+      Self::I7 => &(
+        "i7",
+      ),
+      Self::I32 => &(
+        "i32",
+      ),
       Self::I64 => &(
         "i64",
       ),
+      Self::Ref => &(
+        "ref",
+      ),
+      Self::F32 => &(
+        "f32",
+      ),
+      Self::F64 => &(
+        "f64",
+      ),
       _ => &(
         "unknown",
       )
@@ -104,100 +249,1706 @@ impl Type {
   }
 }
 
+// Integer values of type `iN` are represented by their N-bit two's complement
+// bit patterns, zero extended to 64 bits. Bools are represented by 0 and 1.
+// Floats are represented by their IEEE 754 binary32 or binary64 bit patterns,
+// zero extended to 64 bits.
+//
+// Float arithmetic rounds to nearest, ties to even. Every operation that may
+// produce a NaN produces the canonical quiet NaN, with a clear sign bit and
+// only the most significant bit of the payload set, except for `neg` and
+// `abs`, which only change the sign bit. `min` and `max` produce NaN if
+// either operand is NaN, and order -0.0 before +0.0.
+//
+// Comparisons involving NaN are false, except for `is_ne`, which is true.
+//
+// Conversions from bools to integers give 0 or 1, and conversions from
+// integers to bools are true for any non-zero value.
+//
+// Conversions from integers to floats round to nearest, ties to even.
+// Conversions from floats to integers round towards zero and saturate at the
+// bounds of the integer type, and NaN converts to 0. Conversions from f64 to
+// f32 round to nearest, ties to even, and the `bitcast` operations preserve
+// the bit pattern exactly.
+//
+// Shifts and rotates come in two families. In the first, e.g. `shl.i64`, the
+// amount has the same type as the shifted value, and only its low log2(N)
+// bits are used, where N is the width. In the second, e.g. `shl.i64.i6`, the
+// amount of an `i32` is an `i5` and of an `i64` an `i6`, which can only hold
+// amounts less than N, so there is nothing to mask. Truncations to `i5` and `i6` keep the low bits, and `clz`, `ctz` and
+// `popcount` count into a type just wide enough to hold the width.
+//
+// A `ref` is the address of a byte of memory, and is represented by that
+// address. Memory is allocated by `alloca`, whose slots are zeroed and last
+// until the function that allocated them returns or makes a tail call. It is
+// accessed by `load` and `store` at a constant byte offset from a `ref`, in
+// little endian byte order. Narrow loads extend to an `i32`, and narrow stores
+// keep the low bits. Every access must be aligned as its flags say. Volatile
+// accesses may not be removed, duplicated or reordered with each other.
+//
+// An aggregate is a struct or a fixed-length array of scalars and of other
+// aggregates. Aggregates are values like any other, and `insert` makes a new
+// aggregate rather than changing the old one. They are scalarized before the
+// code is run, after which an aggregate is passed as the scalars it is made
+// of, in order.
+
+fn to_f32(x: u64) -> f32 {
+  f32::from_bits(x as u32)
+}
+
+fn to_f64(x: u64) -> f64 {
+  f64::from_bits(x)
+}
+
+fn from_f32(x: f32) -> u64 {
+  if x.is_nan() { 0x7fc0_0000 } else { x.to_bits() as u64 }
+}
+
+fn from_f64(x: f64) -> u64 {
+  if x.is_nan() { 0x7ff8_0000_0000_0000 } else { x.to_bits() }
+}
+
+fn min_f32(x: f32, y: f32) -> f32 {
+  if x.is_nan() || y.is_nan() { f32::NAN }
+  else if x == y { if x.is_sign_negative() { x } else { y } }
+  else if x < y { x } else { y }
+}
+
+fn max_f32(x: f32, y: f32) -> f32 {
+  if x.is_nan() || y.is_nan() { f32::NAN }
+  else if x == y { if x.is_sign_positive() { x } else { y } }
+  else if x > y { x } else { y }
+}
+
+fn min_f64(x: f64, y: f64) -> f64 {
+  if x.is_nan() || y.is_nan() { f64::NAN }
+  else if x == y { if x.is_sign_negative() { x } else { y } }
+  else if x < y { x } else { y }
+}
+
+fn max_f64(x: f64, y: f64) -> f64 {
+  if x.is_nan() || y.is_nan() { f64::NAN }
+  else if x == y { if x.is_sign_positive() { x } else { y } }
+  else if x > y { x } else { y }
+}
+
 impl Op1 {
   pub const CAST_I32_I64_SX: Self = Self(0x01);
   pub const CAST_I32_I64_ZX: Self = Self(0x02);
   pub const CAST_I64_I32: Self = Self(0x03);
+  pub const BSWAP_I64: Self = Self(0x04);
+  pub const CLZ_I64: Self = Self(0x05);
+  pub const POPCOUNT_I64: Self = Self(0x06);
   pub const CTZ_I64: Self = Self(0x07);
   pub const NEG_I64: Self = Self(0x08);
+  pub const NOT_I64: Self = Self(0x09);
+  pub const CLZ_I64_I7: Self = Self(0x0a);
+  pub const CTZ_I64_I7: Self = Self(0x0b);
+  pub const POPCOUNT_I64_I7: Self = Self(0x0c);
+  pub const BSWAP_I32: Self = Self(0x14);
+  pub const CLZ_I32: Self = Self(0x15);
+  pub const POPCOUNT_I32: Self = Self(0x16);
+  pub const CTZ_I32: Self = Self(0x17);
+  pub const NEG_I32: Self = Self(0x18);
+  pub const NOT_I32: Self = Self(0x19);
+  pub const CLZ_I32_I6: Self = Self(0x1a);
+  pub const CTZ_I32_I6: Self = Self(0x1b);
+  pub const POPCOUNT_I32_I6: Self = Self(0x1c);
+  pub const NEG_F32: Self = Self(0x20);
+  pub const ABS_F32: Self = Self(0x21);
+  pub const SQRT_F32: Self = Self(0x22);
+  pub const NEG_F64: Self = Self(0x30);
+  pub const ABS_F64: Self = Self(0x31);
+  pub const SQRT_F64: Self = Self(0x32);
+  pub const CAST_I32_F32_S: Self = Self(0x40);
+  pub const CAST_I32_F32_U: Self = Self(0x41);
+  pub const CAST_I32_F64_S: Self = Self(0x42);
+  pub const CAST_I32_F64_U: Self = Self(0x43);
+  pub const CAST_I64_F32_S: Self = Self(0x44);
+  pub const CAST_I64_F32_U: Self = Self(0x45);
+  pub const CAST_I64_F64_S: Self = Self(0x46);
+  pub const CAST_I64_F64_U: Self = Self(0x47);
+  pub const CAST_F32_I32_S: Self = Self(0x48);
+  pub const CAST_F32_I32_U: Self = Self(0x49);
+  pub const CAST_F32_I64_S: Self = Self(0x4a);
+  pub const CAST_F32_I64_U: Self = Self(0x4b);
+  pub const CAST_F64_I32_S: Self = Self(0x4c);
+  pub const CAST_F64_I32_U: Self = Self(0x4d);
+  pub const CAST_F64_I64_S: Self = Self(0x4e);
+  pub const CAST_F64_I64_U: Self = Self(0x4f);
+  pub const CAST_F32_F64: Self = Self(0x50);
+  pub const CAST_F64_F32: Self = Self(0x51);
+  pub const BITCAST_F32_I32: Self = Self(0x52);
+  pub const BITCAST_I32_F32: Self = Self(0x53);
+  pub const BITCAST_F64_I64: Self = Self(0x54);
+  pub const BITCAST_I64_F64: Self = Self(0x55);
+  pub const CAST_I5_I32: Self = Self(0x60);
+  pub const CAST_I6_I32: Self = Self(0x61);
+  pub const CAST_I7_I32: Self = Self(0x62);
+  pub const CAST_I5_I64: Self = Self(0x63);
+  pub const CAST_I6_I64: Self = Self(0x64);
+  pub const CAST_I7_I64: Self = Self(0x65);
+  pub const CAST_I32_I5: Self = Self(0x66);
+  pub const CAST_I32_I6: Self = Self(0x67);
+  pub const CAST_I64_I5: Self = Self(0x68);
+  pub const CAST_I64_I6: Self = Self(0x69);
+  pub const NOT_BOOL: Self = Self(0x70);
+  pub const CAST_BOOL_I32: Self = Self(0x71);
+  pub const CAST_BOOL_I64: Self = Self(0x72);
+  pub const CAST_I32_BOOL: Self = Self(0x73);
+  pub const CAST_I64_BOOL: Self = Self(0x74);
 
   pub fn name(self) -> &'static str {
     self.info().0
   }
 
+  pub fn arg_type(self) -> Type {
+    self.info().1
+  }
+
+  pub fn ret_type(self) -> Type {
+    self.info().2
+  }
+
+  pub fn is_valid(self) -> bool {
+    self.ret_type() != Type::UNKNOWN
+  }
+
+  pub fn from_name(name: &[u8]) -> Option<Self> {
+    (0 ..= u8::MAX).map(Self).find(|t| t.is_valid() && t.name().as_bytes() == name)
+  }
+
   fn info(self)
     -> &'static (
       &'static str,
+      Type,
+      Type,
     )
   {
     match self {
+      Self::CAST_I32_I64_SX => &(
+        "cast.i32.i64.sx",
+        Type::I32,
+        Type::I64,
+      ),
+      Self::CAST_I32_I64_ZX => &(
+        "cast.i32.i64.zx",
+        Type::I32,
+        Type::I64,
+      ),
+      Self::CAST_I64_I32 => &(
+        "cast.i64.i32",
+        Type::I64,
+        Type::I32,
+      ),
+      Self::BSWAP_I64 => &(
+        "bswap.i64",
+        Type::I64,
+        Type::I64,
+      ),
+      Self::CLZ_I64 => &(
+        "clz.i64",
+        Type::I64,
+        Type::I64,
+      ),
+      Self::POPCOUNT_I64 => &(
+        "popcount.i64",
+        Type::I64,
+        Type::I64,
+      ),
       Self::CTZ_I64 => &(
         "ctz.i64",
+        Type::I64,
+        Type::I64,
       ),
       Self::NEG_I64 => &(
         "neg.i64",
+        Type::I64,
+        Type::I64,
+      ),
+      Self::NOT_I64 => &(
+        "not.i64",
+        Type::I64,
+        Type::I64,
+      ),
+      Self::CLZ_I64_I7 => &(
+        "clz.i64.i7",
+        Type::I64,
+        Type::I7,
+      ),
+      Self::CTZ_I64_I7 => &(
+        "ctz.i64.i7",
+        Type::I64,
+        Type::I7,
+      ),
+      Self::POPCOUNT_I64_I7 => &(
+        "popcount.i64.i7",
+        Type::I64,
+        Type::I7,
+      ),
+      Self::BSWAP_I32 => &(
+        "bswap.i32",
+        Type::I32,
+        Type::I32,
+      ),
+      Self::CLZ_I32 => &(
+        "clz.i32",
+        Type::I32,
+        Type::I32,
+      ),
+      Self::POPCOUNT_I32 => &(
+        "popcount.i32",
+        Type::I32,
+        Type::I32,
+      ),
+      Self::CTZ_I32 => &(
+        "ctz.i32",
+        Type::I32,
+        Type::I32,
+      ),
+      Self::NEG_I32 => &(
+        "neg.i32",
+        Type::I32,
+        Type::I32,
+      ),
+      Self::NOT_I32 => &(
+        "not.i32",
+        Type::I32,
+        Type::I32,
+      ),
+      Self::CLZ_I32_I6 => &(
+        "clz.i32.i6",
+        Type::I32,
+        Type::I6,
+      ),
+      Self::CTZ_I32_I6 => &(
+        "ctz.i32.i6",
+        Type::I32,
+        Type::I6,
+      ),
+      Self::POPCOUNT_I32_I6 => &(
+        "popcount.i32.i6",
+        Type::I32,
+        Type::I6,
+      ),
+      Self::NEG_F32 => &(
+        "neg.f32",
+        Type::F32,
+        Type::F32,
+      ),
+      Self::ABS_F32 => &(
+        "abs.f32",
+        Type::F32,
+        Type::F32,
+      ),
+      Self::SQRT_F32 => &(
+        "sqrt.f32",
+        Type::F32,
+        Type::F32,
+      ),
+      Self::NEG_F64 => &(
+        "neg.f64",
+        Type::F64,
+        Type::F64,
+      ),
+      Self::ABS_F64 => &(
+        "abs.f64",
+        Type::F64,
+        Type::F64,
+      ),
+      Self::SQRT_F64 => &(
+        "sqrt.f64",
+        Type::F64,
+        Type::F64,
+      ),
+      Self::CAST_I32_F32_S => &(
+        "cast.i32.f32.s",
+        Type::I32,
+        Type::F32,
+      ),
+      Self::CAST_I32_F32_U => &(
+        "cast.i32.f32.u",
+        Type::I32,
+        Type::F32,
+      ),
+      Self::CAST_I32_F64_S => &(
+        "cast.i32.f64.s",
+        Type::I32,
+        Type::F64,
+      ),
+      Self::CAST_I32_F64_U => &(
+        "cast.i32.f64.u",
+        Type::I32,
+        Type::F64,
+      ),
+      Self::CAST_I64_F32_S => &(
+        "cast.i64.f32.s",
+        Type::I64,
+        Type::F32,
+      ),
+      Self::CAST_I64_F32_U => &(
+        "cast.i64.f32.u",
+        Type::I64,
+        Type::F32,
+      ),
+      Self::CAST_I64_F64_S => &(
+        "cast.i64.f64.s",
+        Type::I64,
+        Type::F64,
+      ),
+      Self::CAST_I64_F64_U => &(
+        "cast.i64.f64.u",
+        Type::I64,
+        Type::F64,
+      ),
+      Self::CAST_F32_I32_S => &(
+        "cast.f32.i32.s",
+        Type::F32,
+        Type::I32,
+      ),
+      Self::CAST_F32_I32_U => &(
+        "cast.f32.i32.u",
+        Type::F32,
+        Type::I32,
+      ),
+      Self::CAST_F32_I64_S => &(
+        "cast.f32.i64.s",
+        Type::F32,
+        Type::I64,
+      ),
+      Self::CAST_F32_I64_U => &(
+        "cast.f32.i64.u",
+        Type::F32,
+        Type::I64,
+      ),
+      Self::CAST_F64_I32_S => &(
+        "cast.f64.i32.s",
+        Type::F64,
+        Type::I32,
+      ),
+      Self::CAST_F64_I32_U => &(
+        "cast.f64.i32.u",
+        Type::F64,
+        Type::I32,
+      ),
+      Self::CAST_F64_I64_S => &(
+        "cast.f64.i64.s",
+        Type::F64,
+        Type::I64,
+      ),
+      Self::CAST_F64_I64_U => &(
+        "cast.f64.i64.u",
+        Type::F64,
+        Type::I64,
+      ),
+      Self::CAST_F32_F64 => &(
+        "cast.f32.f64",
+        Type::F32,
+        Type::F64,
+      ),
+      Self::CAST_F64_F32 => &(
+        "cast.f64.f32",
+        Type::F64,
+        Type::F32,
+      ),
+      Self::BITCAST_F32_I32 => &(
+        "bitcast.f32.i32",
+        Type::F32,
+        Type::I32,
+      ),
+      Self::BITCAST_I32_F32 => &(
+        "bitcast.i32.f32",
+        Type::I32,
+        Type::F32,
+      ),
+      Self::BITCAST_F64_I64 => &(
+        "bitcast.f64.i64",
+        Type::F64,
+        Type::I64,
+      ),
+      Self::BITCAST_I64_F64 => &(
+        "bitcast.i64.f64",
+        Type::I64,
+        Type::F64,
+      ),
+      Self::CAST_I5_I32 => &(
+        "cast.i5.i32",
+        Type::I5,
+        Type::I32,
+      ),
+      Self::CAST_I6_I32 => &(
+        "cast.i6.i32",
+        Type::I6,
+        Type::I32,
+      ),
+      Self::CAST_I7_I32 => &(
+        "cast.i7.i32",
+        Type::I7,
+        Type::I32,
+      ),
+      Self::CAST_I5_I64 => &(
+        "cast.i5.i64",
+        Type::I5,
+        Type::I64,
+      ),
+      Self::CAST_I6_I64 => &(
+        "cast.i6.i64",
+        Type::I6,
+        Type::I64,
+      ),
+      Self::CAST_I7_I64 => &(
+        "cast.i7.i64",
+        Type::I7,
+        Type::I64,
+      ),
+      Self::CAST_I32_I5 => &(
+        "cast.i32.i5",
+        Type::I32,
+        Type::I5,
+      ),
+      Self::CAST_I32_I6 => &(
+        "cast.i32.i6",
+        Type::I32,
+        Type::I6,
+      ),
+      Self::CAST_I64_I5 => &(
+        "cast.i64.i5",
+        Type::I64,
+        Type::I5,
+      ),
+      Self::CAST_I64_I6 => &(
+        "cast.i64.i6",
+        Type::I64,
+        Type::I6,
+      ),
+      Self::NOT_BOOL => &(
+        "not.bool",
+        Type::BOOL,
+        Type::BOOL,
+      ),
+      Self::CAST_BOOL_I32 => &(
+        "cast.bool.i32",
+        Type::BOOL,
+        Type::I32,
+      ),
+      Self::CAST_BOOL_I64 => &(
+        "cast.bool.i64",
+        Type::BOOL,
+        Type::I64,
+      ),
+      Self::CAST_I32_BOOL => &(
+        "cast.i32.bool",
+        Type::I32,
+        Type::BOOL,
+      ),
+      Self::CAST_I64_BOOL => &(
+        "cast.i64.bool",
+        Type::I64,
+        Type::BOOL,
       ),
       _ => &(
         "unknown",
+        Type::UNKNOWN,
+        Type::UNKNOWN,
       )
     }
   }
+
+  // Computes the result of the operation. The argument must be a valid
+  // representation of a value of the argument type.
+
+  pub fn eval(self, x: u64) -> u64 {
+    match self {
+      Self::CAST_I32_I64_SX => x as i32 as i64 as u64,
+      Self::CAST_I32_I64_ZX => x as u32 as u64,
+      Self::CAST_I64_I32 => x as u32 as u64,
+      Self::BSWAP_I64 => x.swap_bytes(),
+      Self::CLZ_I64 => x.leading_zeros() as u64,
+      Self::POPCOUNT_I64 => x.count_ones() as u64,
+      Self::CTZ_I64 => x.trailing_zeros() as u64,
+      Self::NEG_I64 => x.wrapping_neg(),
+      Self::NOT_I64 => ! x,
+      Self::CLZ_I64_I7 => x.leading_zeros() as u64,
+      Self::CTZ_I64_I7 => x.trailing_zeros() as u64,
+      Self::POPCOUNT_I64_I7 => x.count_ones() as u64,
+      Self::BSWAP_I32 => (x as u32).swap_bytes() as u64,
+      Self::CLZ_I32 => (x as u32).leading_zeros() as u64,
+      Self::POPCOUNT_I32 => (x as u32).count_ones() as u64,
+      Self::CTZ_I32 => (x as u32).trailing_zeros() as u64,
+      Self::NEG_I32 => (x as u32).wrapping_neg() as u64,
+      Self::NOT_I32 => (! (x as u32)) as u64,
+      Self::CLZ_I32_I6 => (x as u32).leading_zeros() as u64,
+      Self::CTZ_I32_I6 => (x as u32).trailing_zeros() as u64,
+      Self::POPCOUNT_I32_I6 => (x as u32).count_ones() as u64,
+      Self::NEG_F32 => x ^ 0x8000_0000,
+      Self::ABS_F32 => x & 0x7fff_ffff,
+      Self::SQRT_F32 => from_f32(to_f32(x).sqrt()),
+      Self::NEG_F64 => x ^ 0x8000_0000_0000_0000,
+      Self::ABS_F64 => x & 0x7fff_ffff_ffff_ffff,
+      Self::SQRT_F64 => from_f64(to_f64(x).sqrt()),
+      Self::CAST_I32_F32_S => from_f32(x as i32 as f32),
+      Self::CAST_I32_F32_U => from_f32(x as u32 as f32),
+      Self::CAST_I32_F64_S => from_f64(x as i32 as f64),
+      Self::CAST_I32_F64_U => from_f64(x as u32 as f64),
+      Self::CAST_I64_F32_S => from_f32(x as i64 as f32),
+      Self::CAST_I64_F32_U => from_f32(x as f32),
+      Self::CAST_I64_F64_S => from_f64(x as i64 as f64),
+      Self::CAST_I64_F64_U => from_f64(x as f64),
+      Self::CAST_F32_I32_S => to_f32(x) as i32 as u32 as u64,
+      Self::CAST_F32_I32_U => to_f32(x) as u32 as u64,
+      Self::CAST_F32_I64_S => to_f32(x) as i64 as u64,
+      Self::CAST_F32_I64_U => to_f32(x) as u64,
+      Self::CAST_F64_I32_S => to_f64(x) as i32 as u32 as u64,
+      Self::CAST_F64_I32_U => to_f64(x) as u32 as u64,
+      Self::CAST_F64_I64_S => to_f64(x) as i64 as u64,
+      Self::CAST_F64_I64_U => to_f64(x) as u64,
+      Self::CAST_F32_F64 => from_f64(to_f32(x) as f64),
+      Self::CAST_F64_F32 => from_f32(to_f64(x) as f32),
+      Self::BITCAST_F32_I32 => x,
+      Self::BITCAST_I32_F32 => x,
+      Self::BITCAST_F64_I64 => x,
+      Self::BITCAST_I64_F64 => x,
+      Self::CAST_I5_I32 | Self::CAST_I6_I32 | Self::CAST_I7_I32 => x,
+      Self::CAST_I5_I64 | Self::CAST_I6_I64 | Self::CAST_I7_I64 => x,
+      Self::CAST_I32_I5 | Self::CAST_I64_I5 => x & 0x1f,
+      Self::CAST_I32_I6 | Self::CAST_I64_I6 => x & 0x3f,
+      Self::NOT_BOOL => x ^ 1,
+      Self::CAST_BOOL_I32 | Self::CAST_BOOL_I64 => x,
+      Self::CAST_I32_BOOL => (x as u32 != 0) as u64,
+      Self::CAST_I64_BOOL => (x != 0) as u64,
+      _ => panic!(),
+    }
+  }
 }
 
+// Division and remainder are total.
+//
+// - `div_u` by zero gives all ones, and `div_s` by zero gives -1.
+// - `rem_u` and `rem_s` by zero give the dividend.
+// - `div_s` of the minimum value by -1 wraps to the minimum value, and the
+//   corresponding `rem_s` is 0.
+//
+// Shifts and rotates by an amount of the same type as the shifted value use
+// only the low log2(N) bits of the amount, so shifting by N or more is the
+// same as shifting by the amount modulo N. Those by an `i5` or `i6` amount
+// take it as is, as it is always less than N.
+
 impl Op2 {
   pub const ADD_I64: Self = Self(0x06);
   pub const SUB_I64: Self = Self(0x07);
   pub const IS_EQ_I64: Self = Self(0x08);
+  pub const MUL_I64: Self = Self(0x09);
+  pub const DIV_S_I64: Self = Self(0x0a);
+  pub const DIV_U_I64: Self = Self(0x0b);
+  pub const REM_S_I64: Self = Self(0x0c);
+  pub const REM_U_I64: Self = Self(0x0d);
+  pub const AND_I64: Self = Self(0x0e);
+  pub const OR_I64: Self = Self(0x0f);
+  pub const XOR_I64: Self = Self(0x10);
+  pub const SHL_I64: Self = Self(0x11);
+  pub const SHR_S_I64: Self = Self(0x12);
+  pub const SHR_U_I64: Self = Self(0x13);
+  pub const ROTL_I64: Self = Self(0x14);
+  pub const ROTR_I64: Self = Self(0x15);
+  pub const IS_NE_I64: Self = Self(0x16);
+  pub const IS_LT_S_I64: Self = Self(0x17);
+  pub const IS_LT_U_I64: Self = Self(0x18);
+  pub const IS_LE_S_I64: Self = Self(0x19);
+  pub const IS_LE_U_I64: Self = Self(0x1a);
+  pub const IS_GT_S_I64: Self = Self(0x1b);
+  pub const IS_GT_U_I64: Self = Self(0x1c);
+  pub const IS_GE_S_I64: Self = Self(0x1d);
+  pub const IS_GE_U_I64: Self = Self(0x1e);
+  pub const ADD_I32: Self = Self(0x46);
+  pub const SUB_I32: Self = Self(0x47);
+  pub const IS_EQ_I32: Self = Self(0x48);
+  pub const MUL_I32: Self = Self(0x49);
+  pub const DIV_S_I32: Self = Self(0x4a);
+  pub const DIV_U_I32: Self = Self(0x4b);
+  pub const REM_S_I32: Self = Self(0x4c);
+  pub const REM_U_I32: Self = Self(0x4d);
+  pub const AND_I32: Self = Self(0x4e);
+  pub const OR_I32: Self = Self(0x4f);
+  pub const XOR_I32: Self = Self(0x50);
+  pub const SHL_I32: Self = Self(0x51);
+  pub const SHR_S_I32: Self = Self(0x52);
+  pub const SHR_U_I32: Self = Self(0x53);
+  pub const ROTL_I32: Self = Self(0x54);
+  pub const ROTR_I32: Self = Self(0x55);
+  pub const IS_NE_I32: Self = Self(0x56);
+  pub const IS_LT_S_I32: Self = Self(0x57);
+  pub const IS_LT_U_I32: Self = Self(0x58);
+  pub const IS_LE_S_I32: Self = Self(0x59);
+  pub const IS_LE_U_I32: Self = Self(0x5a);
+  pub const IS_GT_S_I32: Self = Self(0x5b);
+  pub const IS_GT_U_I32: Self = Self(0x5c);
+  pub const IS_GE_S_I32: Self = Self(0x5d);
+  pub const IS_GE_U_I32: Self = Self(0x5e);
+  pub const ADD_F32: Self = Self(0x60);
+  pub const SUB_F32: Self = Self(0x61);
+  pub const MUL_F32: Self = Self(0x62);
+  pub const DIV_F32: Self = Self(0x63);
+  pub const MIN_F32: Self = Self(0x64);
+  pub const MAX_F32: Self = Self(0x65);
+  pub const IS_EQ_F32: Self = Self(0x66);
+  pub const IS_NE_F32: Self = Self(0x67);
+  pub const IS_LT_F32: Self = Self(0x68);
+  pub const IS_LE_F32: Self = Self(0x69);
+  pub const IS_GT_F32: Self = Self(0x6a);
+  pub const IS_GE_F32: Self = Self(0x6b);
+  pub const ADD_F64: Self = Self(0x70);
+  pub const SUB_F64: Self = Self(0x71);
+  pub const MUL_F64: Self = Self(0x72);
+  pub const DIV_F64: Self = Self(0x73);
+  pub const MIN_F64: Self = Self(0x74);
+  pub const MAX_F64: Self = Self(0x75);
+  pub const IS_EQ_F64: Self = Self(0x76);
+  pub const IS_NE_F64: Self = Self(0x77);
+  pub const IS_LT_F64: Self = Self(0x78);
+  pub const IS_LE_F64: Self = Self(0x79);
+  pub const IS_GT_F64: Self = Self(0x7a);
+  pub const IS_GE_F64: Self = Self(0x7b);
+  pub const SHL_I64_I6: Self = Self(0x20);
+  pub const SHR_S_I64_I6: Self = Self(0x21);
+  pub const SHR_U_I64_I6: Self = Self(0x22);
+  pub const ROTL_I64_I6: Self = Self(0x23);
+  pub const ROTR_I64_I6: Self = Self(0x24);
+  pub const SHL_I32_I5: Self = Self(0x40);
+  pub const SHR_S_I32_I5: Self = Self(0x41);
+  pub const SHR_U_I32_I5: Self = Self(0x42);
+  pub const ROTL_I32_I5: Self = Self(0x43);
+  pub const ROTR_I32_I5: Self = Self(0x44);
+  pub const ADD_REF_I64: Self = Self(0x25);
+  pub const SUB_REF: Self = Self(0x26);
+  pub const IS_EQ_REF: Self = Self(0x27);
+  pub const AND_BOOL: Self = Self(0x01);
+  pub const OR_BOOL: Self = Self(0x02);
+  pub const XOR_BOOL: Self = Self(0x03);
+  pub const IS_EQ_BOOL: Self = Self(0x04);
+  pub const IS_NE_BOOL: Self = Self(0x05);
 
   pub fn name(self) -> &'static str {
     self.info().0
   }
 
+  pub fn arg_types(self) -> (Type, Type) {
+    (self.info().1, self.info().2)
+  }
+
+  pub fn ret_type(self) -> Type {
+    self.info().3
+  }
+
+  pub fn is_valid(self) -> bool {
+    self.ret_type() != Type::UNKNOWN
+  }
+
+  pub fn from_name(name: &[u8]) -> Option<Self> {
+    (0 ..= u8::MAX).map(Self).find(|t| t.is_valid() && t.name().as_bytes() == name)
+  }
+
   fn info(self)
     -> &'static (
       &'static str,
+      Type,
+      Type,
+      Type,
     )
   {
     match self {
       Self::ADD_I64 => &(
         "add.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
       ),
       Self::SUB_I64 => &(
         "sub.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
       ),
       Self::IS_EQ_I64 => &(
         "is_eq.i64",
+        Type::I64,
+        Type::I64,
+        Type::BOOL,
+      ),
+      Self::MUL_I64 => &(
+        "mul.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
+      ),
+      Self::DIV_S_I64 => &(
+        "div_s.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
+      ),
+      Self::DIV_U_I64 => &(
+        "div_u.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
+      ),
+      Self::REM_S_I64 => &(
+        "rem_s.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
+      ),
+      Self::REM_U_I64 => &(
+        "rem_u.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
+      ),
+      Self::AND_I64 => &(
+        "and.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
+      ),
+      Self::OR_I64 => &(
+        "or.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
+      ),
+      Self::XOR_I64 => &(
+        "xor.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
+      ),
+      Self::SHL_I64 => &(
+        "shl.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
+      ),
+      Self::SHR_S_I64 => &(
+        "shr_s.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
+      ),
+      Self::SHR_U_I64 => &(
+        "shr_u.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
+      ),
+      Self::ROTL_I64 => &(
+        "rotl.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
+      ),
+      Self::ROTR_I64 => &(
+        "rotr.i64",
+        Type::I64,
+        Type::I64,
+        Type::I64,
+      ),
+      Self::IS_NE_I64 => &(
+        "is_ne.i64",
+        Type::I64,
+        Type::I64,
+        Type::BOOL,
+      ),
+      Self::IS_LT_S_I64 => &(
+        "is_lt_s.i64",
+        Type::I64,
+        Type::I64,
+        Type::BOOL,
+      ),
+      Self::IS_LT_U_I64 => &(
+        "is_lt_u.i64",
+        Type::I64,
+        Type::I64,
+        Type::BOOL,
+      ),
+      Self::IS_LE_S_I64 => &(
+        "is_le_s.i64",
+        Type::I64,
+        Type::I64,
+        Type::BOOL,
+      ),
+      Self::IS_LE_U_I64 => &(
+        "is_le_u.i64",
+        Type::I64,
+        Type::I64,
+        Type::BOOL,
+      ),
+      Self::IS_GT_S_I64 => &(
+        "is_gt_s.i64",
+        Type::I64,
+        Type::I64,
+        Type::BOOL,
+      ),
+      Self::IS_GT_U_I64 => &(
+        "is_gt_u.i64",
+        Type::I64,
+        Type::I64,
+        Type::BOOL,
+      ),
+      Self::IS_GE_S_I64 => &(
+        "is_ge_s.i64",
+        Type::I64,
+        Type::I64,
+        Type::BOOL,
+      ),
+      Self::IS_GE_U_I64 => &(
+        "is_ge_u.i64",
+        Type::I64,
+        Type::I64,
+        Type::BOOL,
+      ),
+      Self::ADD_I32 => &(
+        "add.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::SUB_I32 => &(
+        "sub.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::IS_EQ_I32 => &(
+        "is_eq.i32",
+        Type::I32,
+        Type::I32,
+        Type::BOOL,
+      ),
+      Self::MUL_I32 => &(
+        "mul.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::DIV_S_I32 => &(
+        "div_s.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::DIV_U_I32 => &(
+        "div_u.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::REM_S_I32 => &(
+        "rem_s.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::REM_U_I32 => &(
+        "rem_u.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::AND_I32 => &(
+        "and.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::OR_I32 => &(
+        "or.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::XOR_I32 => &(
+        "xor.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::SHL_I32 => &(
+        "shl.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::SHR_S_I32 => &(
+        "shr_s.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::SHR_U_I32 => &(
+        "shr_u.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::ROTL_I32 => &(
+        "rotl.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::ROTR_I32 => &(
+        "rotr.i32",
+        Type::I32,
+        Type::I32,
+        Type::I32,
+      ),
+      Self::IS_NE_I32 => &(
+        "is_ne.i32",
+        Type::I32,
+        Type::I32,
+        Type::BOOL,
+      ),
+      Self::IS_LT_S_I32 => &(
+        "is_lt_s.i32",
+        Type::I32,
+        Type::I32,
+        Type::BOOL,
+      ),
+      Self::IS_LT_U_I32 => &(
+        "is_lt_u.i32",
+        Type::I32,
+        Type::I32,
+        Type::BOOL,
+      ),
+      Self::IS_LE_S_I32 => &(
+        "is_le_s.i32",
+        Type::I32,
+        Type::I32,
+        Type::BOOL,
+      ),
+      Self::IS_LE_U_I32 => &(
+        "is_le_u.i32",
+        Type::I32,
+        Type::I32,
+        Type::BOOL,
+      ),
+      Self::IS_GT_S_I32 => &(
+        "is_gt_s.i32",
+        Type::I32,
+        Type::I32,
+        Type::BOOL,
+      ),
+      Self::IS_GT_U_I32 => &(
+        "is_gt_u.i32",
+        Type::I32,
+        Type::I32,
+        Type::BOOL,
+      ),
+      Self::IS_GE_S_I32 => &(
+        "is_ge_s.i32",
+        Type::I32,
+        Type::I32,
+        Type::BOOL,
+      ),
+      Self::IS_GE_U_I32 => &(
+        "is_ge_u.i32",
+        Type::I32,
+        Type::I32,
+        Type::BOOL,
+      ),
+      Self::ADD_F32 => &(
+        "add.f32",
+        Type::F32,
+        Type::F32,
+        Type::F32,
+      ),
+      Self::SUB_F32 => &(
+        "sub.f32",
+        Type::F32,
+        Type::F32,
+        Type::F32,
+      ),
+      Self::MUL_F32 => &(
+        "mul.f32",
+        Type::F32,
+        Type::F32,
+        Type::F32,
+      ),
+      Self::DIV_F32 => &(
+        "div.f32",
+        Type::F32,
+        Type::F32,
+        Type::F32,
+      ),
+      Self::MIN_F32 => &(
+        "min.f32",
+        Type::F32,
+        Type::F32,
+        Type::F32,
+      ),
+      Self::MAX_F32 => &(
+        "max.f32",
+        Type::F32,
+        Type::F32,
+        Type::F32,
+      ),
+      Self::IS_EQ_F32 => &(
+        "is_eq.f32",
+        Type::F32,
+        Type::F32,
+        Type::BOOL,
+      ),
+      Self::IS_NE_F32 => &(
+        "is_ne.f32",
+        Type::F32,
+        Type::F32,
+        Type::BOOL,
+      ),
+      Self::IS_LT_F32 => &(
+        "is_lt.f32",
+        Type::F32,
+        Type::F32,
+        Type::BOOL,
+      ),
+      Self::IS_LE_F32 => &(
+        "is_le.f32",
+        Type::F32,
+        Type::F32,
+        Type::BOOL,
+      ),
+      Self::IS_GT_F32 => &(
+        "is_gt.f32",
+        Type::F32,
+        Type::F32,
+        Type::BOOL,
+      ),
+      Self::IS_GE_F32 => &(
+        "is_ge.f32",
+        Type::F32,
+        Type::F32,
+        Type::BOOL,
+      ),
+      Self::ADD_F64 => &(
+        "add.f64",
+        Type::F64,
+        Type::F64,
+        Type::F64,
+      ),
+      Self::SUB_F64 => &(
+        "sub.f64",
+        Type::F64,
+        Type::F64,
+        Type::F64,
+      ),
+      Self::MUL_F64 => &(
+        "mul.f64",
+        Type::F64,
+        Type::F64,
+        Type::F64,
+      ),
+      Self::DIV_F64 => &(
+        "div.f64",
+        Type::F64,
+        Type::F64,
+        Type::F64,
+      ),
+      Self::MIN_F64 => &(
+        "min.f64",
+        Type::F64,
+        Type::F64,
+        Type::F64,
+      ),
+      Self::MAX_F64 => &(
+        "max.f64",
+        Type::F64,
+        Type::F64,
+        Type::F64,
+      ),
+      Self::IS_EQ_F64 => &(
+        "is_eq.f64",
+        Type::F64,
+        Type::F64,
+        Type::BOOL,
+      ),
+      Self::IS_NE_F64 => &(
+        "is_ne.f64",
+        Type::F64,
+        Type::F64,
+        Type::BOOL,
+      ),
+      Self::IS_LT_F64 => &(
+        "is_lt.f64",
+        Type::F64,
+        Type::F64,
+        Type::BOOL,
+      ),
+      Self::IS_LE_F64 => &(
+        "is_le.f64",
+        Type::F64,
+        Type::F64,
+        Type::BOOL,
+      ),
+      Self::IS_GT_F64 => &(
+        "is_gt.f64",
+        Type::F64,
+        Type::F64,
+        Type::BOOL,
+      ),
+      Self::IS_GE_F64 => &(
+        "is_ge.f64",
+        Type::F64,
+        Type::F64,
+        Type::BOOL,
+      ),
+      Self::SHL_I64_I6 => &(
+        "shl.i64.i6",
+        Type::I64,
+        Type::I6,
+        Type::I64,
+      ),
+      Self::SHR_S_I64_I6 => &(
+        "shr_s.i64.i6",
+        Type::I64,
+        Type::I6,
+        Type::I64,
+      ),
+      Self::SHR_U_I64_I6 => &(
+        "shr_u.i64.i6",
+        Type::I64,
+        Type::I6,
+        Type::I64,
+      ),
+      Self::ROTL_I64_I6 => &(
+        "rotl.i64.i6",
+        Type::I64,
+        Type::I6,
+        Type::I64,
+      ),
+      Self::ROTR_I64_I6 => &(
+        "rotr.i64.i6",
+        Type::I64,
+        Type::I6,
+        Type::I64,
+      ),
+      Self::SHL_I32_I5 => &(
+        "shl.i32.i5",
+        Type::I32,
+        Type::I5,
+        Type::I32,
+      ),
+      Self::SHR_S_I32_I5 => &(
+        "shr_s.i32.i5",
+        Type::I32,
+        Type::I5,
+        Type::I32,
+      ),
+      Self::SHR_U_I32_I5 => &(
+        "shr_u.i32.i5",
+        Type::I32,
+        Type::I5,
+        Type::I32,
+      ),
+      Self::ROTL_I32_I5 => &(
+        "rotl.i32.i5",
+        Type::I32,
+        Type::I5,
+        Type::I32,
+      ),
+      Self::ROTR_I32_I5 => &(
+        "rotr.i32.i5",
+        Type::I32,
+        Type::I5,
+        Type::I32,
+      ),
+      Self::ADD_REF_I64 => &(
+        "add.ref.i64",
+        Type::Ref,
+        Type::I64,
+        Type::Ref,
+      ),
+      Self::SUB_REF => &(
+        "sub.ref",
+        Type::Ref,
+        Type::Ref,
+        Type::I64,
+      ),
+      Self::IS_EQ_REF => &(
+        "is_eq.ref",
+        Type::Ref,
+        Type::Ref,
+        Type::BOOL,
+      ),
+      Self::AND_BOOL => &(
+        "and.bool",
+        Type::BOOL,
+        Type::BOOL,
+        Type::BOOL,
+      ),
+      Self::OR_BOOL => &(
+        "or.bool",
+        Type::BOOL,
+        Type::BOOL,
+        Type::BOOL,
+      ),
+      Self::XOR_BOOL => &(
+        "xor.bool",
+        Type::BOOL,
+        Type::BOOL,
+        Type::BOOL,
+      ),
+      Self::IS_EQ_BOOL => &(
+        "is_eq.bool",
+        Type::BOOL,
+        Type::BOOL,
+        Type::BOOL,
+      ),
+      Self::IS_NE_BOOL => &(
+        "is_ne.bool",
+        Type::BOOL,
+        Type::BOOL,
+        Type::BOOL,
       ),
       _ => &(
         "unknown",
+        Type::UNKNOWN,
+        Type::UNKNOWN,
+        Type::UNKNOWN,
       )
     }
   }
-}
 
-impl core::fmt::Display for Type {
-  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-    write!(f, "{}", self.name())
-  }
-}
+  // Computes the result of the operation. The arguments must be valid
+  // representations of values of the argument types.
 
-impl core::fmt::Display for Op1 {
-  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-    write!(f, "{}", self.name())
+  pub fn eval(self, x: u64, y: u64) -> u64 {
+    match self {
+      Self::ADD_I64 => x.wrapping_add(y),
+      Self::SUB_I64 => x.wrapping_sub(y),
+      Self::IS_EQ_I64 => (x == y) as u64,
+      Self::MUL_I64 => x.wrapping_mul(y),
+      Self::DIV_S_I64 => (if (y as i64) == 0 { -1 } else { (x as i64).wrapping_div(y as i64) }) as u64,
+      Self::DIV_U_I64 => if y == 0 { u64::MAX } else { x / y },
+      Self::REM_S_I64 => (if (y as i64) == 0 { x as i64 } else { (x as i64).wrapping_rem(y as i64) }) as u64,
+      Self::REM_U_I64 => if y == 0 { x } else { x % y },
+      Self::AND_I64 => x & y,
+      Self::OR_I64 => x | y,
+      Self::XOR_I64 => x ^ y,
+      Self::SHL_I64 => x.wrapping_shl(y as u32),
+      Self::SHR_S_I64 => (x as i64).wrapping_shr(y as u32) as u64,
+      Self::SHR_U_I64 => x.wrapping_shr(y as u32),
+      Self::ROTL_I64 => x.rotate_left(y as u32 % 64),
+      Self::ROTR_I64 => x.rotate_right(y as u32 % 64),
+      Self::IS_NE_I64 => (x != y) as u64,
+      Self::IS_LT_S_I64 => ((x as i64) < (y as i64)) as u64,
+      Self::IS_LT_U_I64 => (x < y) as u64,
+      Self::IS_LE_S_I64 => ((x as i64) <= (y as i64)) as u64,
+      Self::IS_LE_U_I64 => (x <= y) as u64,
+      Self::IS_GT_S_I64 => ((x as i64) > (y as i64)) as u64,
+      Self::IS_GT_U_I64 => (x > y) as u64,
+      Self::IS_GE_S_I64 => ((x as i64) >= (y as i64)) as u64,
+      Self::IS_GE_U_I64 => (x >= y) as u64,
+      Self::ADD_I32 => (x as u32).wrapping_add(y as u32) as u64,
+      Self::SUB_I32 => (x as u32).wrapping_sub(y as u32) as u64,
+      Self::IS_EQ_I32 => ((x as u32) == (y as u32)) as u64,
+      Self::MUL_I32 => (x as u32).wrapping_mul(y as u32) as u64,
+      Self::DIV_S_I32 => (if (y as i32) == 0 { -1 } else { (x as i32).wrapping_div(y as i32) }) as u32 as u64,
+      Self::DIV_U_I32 => (if (y as u32) == 0 { u32::MAX } else { (x as u32) / (y as u32) }) as u64,
+      Self::REM_S_I32 => (if (y as i32) == 0 { x as i32 } else { (x as i32).wrapping_rem(y as i32) }) as u32 as u64,
+      Self::REM_U_I32 => (if (y as u32) == 0 { x as u32 } else { (x as u32) % (y as u32) }) as u64,
+      Self::AND_I32 => x & y,
+      Self::OR_I32 => x | y,
+      Self::XOR_I32 => x ^ y,
+      Self::SHL_I32 => (x as u32).wrapping_shl(y as u32) as u64,
+      Self::SHR_S_I32 => (x as i32).wrapping_shr(y as u32) as u32 as u64,
+      Self::SHR_U_I32 => (x as u32).wrapping_shr(y as u32) as u64,
+      Self::ROTL_I32 => (x as u32).rotate_left(y as u32 % 32) as u64,
+      Self::ROTR_I32 => (x as u32).rotate_right(y as u32 % 32) as u64,
+      Self::IS_NE_I32 => ((x as u32) != (y as u32)) as u64,
+      Self::IS_LT_S_I32 => ((x as i32) < (y as i32)) as u64,
+      Self::IS_LT_U_I32 => ((x as u32) < (y as u32)) as u64,
+      Self::IS_LE_S_I32 => ((x as i32) <= (y as i32)) as u64,
+      Self::IS_LE_U_I32 => ((x as u32) <= (y as u32)) as u64,
+      Self::IS_GT_S_I32 => ((x as i32) > (y as i32)) as u64,
+      Self::IS_GT_U_I32 => ((x as u32) > (y as u32)) as u64,
+      Self::IS_GE_S_I32 => ((x as i32) >= (y as i32)) as u64,
+      Self::IS_GE_U_I32 => ((x as u32) >= (y as u32)) as u64,
+      Self::ADD_F32 => from_f32(to_f32(x) + to_f32(y)),
+      Self::SUB_F32 => from_f32(to_f32(x) - to_f32(y)),
+      Self::MUL_F32 => from_f32(to_f32(x) * to_f32(y)),
+      Self::DIV_F32 => from_f32(to_f32(x) / to_f32(y)),
+      Self::MIN_F32 => from_f32(min_f32(to_f32(x), to_f32(y))),
+      Self::MAX_F32 => from_f32(max_f32(to_f32(x), to_f32(y))),
+      Self::IS_EQ_F32 => (to_f32(x) == to_f32(y)) as u64,
+      Self::IS_NE_F32 => (to_f32(x) != to_f32(y)) as u64,
+      Self::IS_LT_F32 => (to_f32(x) < to_f32(y)) as u64,
+      Self::IS_LE_F32 => (to_f32(x) <= to_f32(y)) as u64,
+      Self::IS_GT_F32 => (to_f32(x) > to_f32(y)) as u64,
+      Self::IS_GE_F32 => (to_f32(x) >= to_f32(y)) as u64,
+      Self::ADD_F64 => from_f64(to_f64(x) + to_f64(y)),
+      Self::SUB_F64 => from_f64(to_f64(x) - to_f64(y)),
+      Self::MUL_F64 => from_f64(to_f64(x) * to_f64(y)),
+      Self::DIV_F64 => from_f64(to_f64(x) / to_f64(y)),
+      Self::MIN_F64 => from_f64(min_f64(to_f64(x), to_f64(y))),
+      Self::MAX_F64 => from_f64(max_f64(to_f64(x), to_f64(y))),
+      Self::IS_EQ_F64 => (to_f64(x) == to_f64(y)) as u64,
+      Self::IS_NE_F64 => (to_f64(x) != to_f64(y)) as u64,
+      Self::IS_LT_F64 => (to_f64(x) < to_f64(y)) as u64,
+      Self::IS_LE_F64 => (to_f64(x) <= to_f64(y)) as u64,
+      Self::IS_GT_F64 => (to_f64(x) > to_f64(y)) as u64,
+      Self::IS_GE_F64 => (to_f64(x) >= to_f64(y)) as u64,
+      Self::SHL_I64_I6 => x.wrapping_shl(y as u32),
+      Self::SHR_S_I64_I6 => (x as i64).wrapping_shr(y as u32) as u64,
+      Self::SHR_U_I64_I6 => x.wrapping_shr(y as u32),
+      Self::ROTL_I64_I6 => x.rotate_left(y as u32),
+      Self::ROTR_I64_I6 => x.rotate_right(y as u32),
+      Self::SHL_I32_I5 => (x as u32).wrapping_shl(y as u32) as u64,
+      Self::SHR_S_I32_I5 => (x as i32).wrapping_shr(y as u32) as u32 as u64,
+      Self::SHR_U_I32_I5 => (x as u32).wrapping_shr(y as u32) as u64,
+      Self::ROTL_I32_I5 => (x as u32).rotate_left(y as u32) as u64,
+      Self::ROTR_I32_I5 => (x as u32).rotate_right(y as u32) as u64,
+      Self::ADD_REF_I64 => x.wrapping_add(y),
+      Self::SUB_REF => x.wrapping_sub(y),
+      Self::IS_EQ_REF => (x == y) as u64,
+      Self::AND_BOOL => x & y,
+      Self::OR_BOOL => x | y,
+      Self::XOR_BOOL => x ^ y,
+      Self::IS_EQ_BOOL => (x == y) as u64,
+      Self::IS_NE_BOOL => (x != y) as u64,
+      _ => panic!(),
+    }
   }
 }
 
-impl core::fmt::Display for Op2 {
-  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-    write!(f, "{}", self.name())
+// The width of a memory access, and the type of the value loaded or stored.
+
+impl MemType {
+  pub const I8_U: Self = Self(0x01);
+  pub const I8_S: Self = Self(0x02);
+  pub const I16_U: Self = Self(0x03);
+  pub const I16_S: Self = Self(0x04);
+  pub const I32: Self = Self(0x05);
+  pub const I64: Self = Self(0x06);
+  pub const F32: Self = Self(0x07);
+  pub const F64: Self = Self(0x08);
+
+  pub fn name(self) -> &'static str {
+    self.info().0
   }
-}
 
-impl core::fmt::Display for Value {
-  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-    write!(f, "%{}", self.0)
+  pub fn size(self) -> u32 {
+    self.info().1
   }
-}
 
-impl core::fmt::Display for Label {
-  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-    write!(f, "=>{}", self.0)
+  pub fn value_type(self) -> Type {
+    self.info().2
   }
-}
 
-impl core::fmt::Display for Variable {
-  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-    write!(f, "@{}", self.0)
+  pub fn is_valid(self) -> bool {
+    self.value_type() != Type::UNKNOWN
+  }
+
+  fn info(self)
+    -> &'static (
+      &'static str,
+      u32,
+      Type,
+    )
+  {
+    match self {
+      Self::I8_U => &(
+        "i8_u",
+        1,
+        Type::I32,
+      ),
+      Self::I8_S => &(
+        "i8_s",
+        1,
+        Type::I32,
+      ),
+      Self::I16_U => &(
+        "i16_u",
+        2,
+        Type::I32,
+      ),
+      Self::I16_S => &(
+        "i16_s",
+        2,
+        Type::I32,
+      ),
+      Self::I32 => &(
+        "i32",
+        4,
+        Type::I32,
+      ),
+      Self::I64 => &(
+        "i64",
+        8,
+        Type::I64,
+      ),
+      Self::F32 => &(
+        "f32",
+        4,
+        Type::F32,
+      ),
+      Self::F64 => &(
+        "f64",
+        8,
+        Type::F64,
+      ),
+      _ => &(
+        "unknown",
+        0,
+        Type::UNKNOWN,
+      ),
+    }
+  }
+
+  // The value loaded from the given bytes, zero extended to 64 bits.
+
+  pub fn extend(self, x: u64) -> u64 {
+    match self {
+      Self::I8_S => x as u8 as i8 as i32 as u32 as u64,
+      Self::I16_S => x as u16 as i16 as i32 as u32 as u64,
+      _ => x,
+    }
+  }
+}
+
+// The alignment and volatility of a memory access. The low four bits are the
+// log2 of the alignment, which is at most 16 bytes, and the top bit is set for
+// volatile accesses. The other bits are reserved and must be clear.
+
+impl MemFlags {
+  pub const VOLATILE: Self = Self(0x80);
+
+  pub fn new(align: u32, volatile: bool) -> Self {
+    assert!(align.is_power_of_two() && align <= MAX_ALIGN);
+    Self(align.trailing_zeros() as u8 | if volatile { Self::VOLATILE.0 } else { 0 })
+  }
+
+  pub fn align(self) -> u32 {
+    1 << (self.0 & 0x0f)
+  }
+
+  pub fn is_volatile(self) -> bool {
+    self.0 & Self::VOLATILE.0 != 0
+  }
+
+  pub fn is_valid(self) -> bool {
+    self.0 & 0x70 == 0 && self.align() <= MAX_ALIGN
+  }
+}
+
+// The largest alignment of a memory access or an `alloca`.
+
+pub const MAX_ALIGN: u32 = 16;
+
+// The fields of an aggregate type: a struct's field types, or an array's
+// element type and length.
+
+#[derive(Clone)]
+pub enum Aggregate {
+  Struct(Vec<Type>),
+  Array(Type, u32),
+}
+
+// The largest number of aggregate types, and the largest number of scalars
+// that an aggregate may be made of.
+
+pub const MAX_AGGREGATES: u32 = 0x80;
+pub const MAX_WIDTH: u32 = 1 << 12;
+
+// The aggregate types defined by the `struct` and `array` instructions at the
+// start of a sequence of functions, with the number of scalars each is made
+// of.
+
+pub struct TypeTable {
+  defs: Vec<(Aggregate, u32)>,
+}
+
+impl TypeTable {
+  pub fn new() -> Self {
+    Self { defs: Vec::new() }
+  }
+
+  // Reads the definitions at the start of `code`, up to the most there may
+  // be.
+
+  pub fn read(code: &[u8]) -> Self {
+    let mut table = Self::new();
+    for inst in Reader::new(code).take(MAX_AGGREGATES as usize) {
+      match inst {
+        Ok((_, Instruction::TypeStruct(fields))) => {
+          let _ = table.push(Aggregate::Struct(fields.iter().collect()));
+        }
+        Ok((_, Instruction::TypeArray(t, n))) => {
+          let _ = table.push(Aggregate::Array(t, n));
+        }
+        _ => {
+          break;
+        }
+      }
+    }
+    table
+  }
+
+  pub fn len(&self) -> u32 {
+    self.defs.len() as u32
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.defs.is_empty()
+  }
+
+  // Defines the next aggregate type. Its fields must be scalars or already
+  // defined.
+
+  pub fn push(&mut self, a: Aggregate) -> Type {
+    let t = Type::aggregate(self.len());
+    let width =
+      match &a {
+        Aggregate::Struct(fields) => fields.iter().fold(0u32, |n, &u| n.saturating_add(self.width(u))),
+        Aggregate::Array(u, n) => self.width(*u).saturating_mul(*n),
+      };
+    self.defs.push((a, width));
+    t
+  }
+
+  // The definition of an aggregate type, or `None` for a scalar or an
+  // undefined type.
+
+  pub fn get(&self, t: Type) -> Option<&Aggregate> {
+    if ! t.is_aggregate() {
+      return None;
+    }
+    self.defs.get((t.0 - Type::FIRST_AGGREGATE.0) as usize).map(|d| &d.0)
+  }
+
+  pub fn is_defined(&self, t: Type) -> bool {
+    ! t.is_aggregate() || self.get(t).is_some()
+  }
+
+  pub fn num_fields(&self, t: Type) -> u32 {
+    match self.get(t) {
+      Some(Aggregate::Struct(fields)) => fields.len() as u32,
+      Some(&Aggregate::Array(_, n)) => n,
+      None => 0,
+    }
+  }
+
+  pub fn field(&self, t: Type, i: u32) -> Type {
+    match self.get(t) {
+      Some(Aggregate::Struct(fields)) => fields[i as usize],
+      Some(&Aggregate::Array(u, _)) => u,
+      None => panic!(),
+    }
+  }
+
+  // The number of scalars that a value of type `t` is made of.
+
+  pub fn width(&self, t: Type) -> u32 {
+    if ! t.is_aggregate() {
+      return 1;
+    }
+    match self.defs.get((t.0 - Type::FIRST_AGGREGATE.0) as usize) {
+      Some(&(_, width)) => width,
+      None => 0,
+    }
+  }
+
+  // The number of scalars before the `i`th field of a value of type `t`.
+
+  pub fn offset(&self, t: Type, i: u32) -> u32 {
+    match self.get(t) {
+      Some(Aggregate::Struct(fields)) => fields[.. i as usize].iter().map(|&u| self.width(u)).sum(),
+      Some(&Aggregate::Array(u, _)) => self.width(u) * i,
+      None => panic!(),
+    }
+  }
+
+  // Appends the scalar types that a value of type `t` is made of, in order.
+
+  pub fn flatten(&self, t: Type, out: &mut Vec<Type>) {
+    if self.width(t) == 0 {
+      return;
+    }
+    match self.get(t) {
+      None => {
+        out.push(t);
+      }
+      Some(Aggregate::Struct(fields)) => {
+        for &u in fields.iter() {
+          self.flatten(u, out);
+        }
+      }
+      Some(&Aggregate::Array(u, n)) => {
+        for _ in 0 .. n {
+          self.flatten(u, out);
+        }
+      }
+    }
+  }
+}
+
+impl Default for TypeTable {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl core::fmt::Display for Type {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    if self.is_aggregate() {
+      write!(f, "agg{}", self.0 - Self::FIRST_AGGREGATE.0)
+    } else {
+      write!(f, "{}", self.name())
+    }
+  }
+}
+
+impl core::fmt::Display for Op1 {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+impl core::fmt::Display for Op2 {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+impl core::fmt::Display for MemType {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+impl core::fmt::Display for MemFlags {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "align {}", self.align())?;
+    if self.is_volatile() {
+      write!(f, " volatile")?;
+    }
+    Ok(())
+  }
+}
+
+impl core::fmt::Display for Value {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "%{}", self.0)
+  }
+}
+
+impl core::fmt::Display for Label {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "=>{}", self.0)
+  }
+}
+
+impl core::fmt::Display for Variable {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "@{}", self.0)
   }
 }
 
@@ -210,16 +1961,70 @@ impl<'a> TypeList<'a> {
 
 impl<'a> ValueList<'a> {
   #[inline(always)]
-  pub fn iter(&self) -> impl Iterator<Item = Value> + use<'_> {
-    self.0.iter_chunks().map(|x| Value(u32::from_le_bytes(*x)))
+  pub fn iter(&self) -> impl Iterator<Item = Value> + use<'a> {
+    let mut r = ByteReader::new(self.0);
+    let base = self.1;
+    core::iter::from_fn(move || {
+      match base {
+        None => Some(Value(r.try_pop_u32()?)),
+        Some(n) => Some(Value(n.wrapping_sub(1).wrapping_sub(r.try_pop_uleb128()? as u32))),
+      }
+    })
+  }
+}
+
+impl<'a> KontList<'a> {
+  #[inline(always)]
+  pub fn len(&self) -> u32 {
+    self.0
+  }
+
+  #[inline(always)]
+  pub fn is_empty(&self) -> bool {
+    self.0 == 0
+  }
+
+  #[inline(always)]
+  pub fn iter(&self) -> impl Iterator<Item = TypeList<'a>> + use<'a> {
+    let mut r = self.1;
+    (0 .. self.0).map(move |_| {
+      let n = r.pop_u32();
+      TypeList(r.pop_slice(n as usize))
+    })
+  }
+}
+
+impl<'a> LabelList<'a> {
+  #[inline(always)]
+  pub fn iter(&self) -> impl Iterator<Item = Label> + use<'_> {
+    self.0.iter_chunks().map(|x| Label(u32::from_le_bytes(*x)))
   }
 }
 
+// How the operands of instructions are encoded.
+//
+// - `Fixed`: every value, label and count is a little-endian u32
+// - `Compact`: counts, variables and integer constants are LEB128, and each
+//   value is the LEB128 distance back from the next value to be defined, so
+//   that recent values take one byte, and operands must be defined earlier
+//   in the encoding. Labels stay fixed-width so that they can be patched, as
+//   does the function header.
+//
+// The encoding is chosen per function, by the tag of its header.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+  Fixed,
+  Compact,
+}
+
 pub struct Builder {
   buf: Buf,
+  encoding: Encoding,
   value_id: u32,
   label_id: u32,
   variable_id: u32,
+  type_id: u32,
 }
 
 #[derive(Clone, Copy)]
@@ -233,14 +2038,24 @@ fn incr(x: &mut u32) -> u32 {
 
 impl Builder {
   pub fn new() -> Self {
+    Self::with_encoding(Encoding::Fixed)
+  }
+
+  pub fn with_encoding(encoding: Encoding) -> Self {
     Self {
       buf: Buf::new(),
+      encoding,
       value_id: 0,
       label_id: 0,
       variable_id: 0,
+      type_id: 0,
     }
   }
 
+  pub fn encoding(&self) -> Encoding {
+    self.encoding
+  }
+
   pub fn next_value(&mut self) -> Value {
     Value(incr(&mut self.value_id))
   }
@@ -257,242 +2072,751 @@ impl Builder {
     self.buf.view()
   }
 
+  fn put_u8(&mut self, x: u8) {
+    let mut w = self.buf.append(1);
+    w.put_u8(x);
+  }
+
+  fn put_u32(&mut self, x: u32) -> PatchPoint {
+    let n = self.buf.len();
+    let mut w = self.buf.append(4);
+    w.put_u32(x);
+    PatchPoint(n)
+  }
+
+  fn put_uleb128(&mut self, x: u64) {
+    let w = self.buf.append(uleb128_len(x));
+    let _ = ByteWriter::new(w).try_put_uleb128(x);
+  }
+
+  fn put_sleb128(&mut self, x: i64) {
+    let w = self.buf.append(sleb128_len(x));
+    let _ = ByteWriter::new(w).try_put_sleb128(x);
+  }
+
+  // Counts, indexes and variables.
+
+  fn put_count(&mut self, x: u32) {
+    match self.encoding {
+      Encoding::Fixed => { let _ = self.put_u32(x); }
+      Encoding::Compact => { self.put_uleb128(x as u64); }
+    }
+  }
+
+  // Operands, which in the compact encoding refer back from the next value
+  // to be defined, so they must already be defined in the current function.
+
+  fn put_value(&mut self, x: Value) {
+    match self.encoding {
+      Encoding::Fixed => { let _ = self.put_u32(x.0); }
+      Encoding::Compact => {
+        let Some(n) = self.value_id.checked_sub(x.0).and_then(|n| n.checked_sub(1)) else {
+          panic!("operand %{} isn't defined yet, the next value is %{}", x.0, self.value_id)
+        };
+        self.put_uleb128(n as u64);
+      }
+    }
+  }
+
   pub fn patch_label(&mut self, i: PatchPoint, a: Label) {
     let mut w = self.buf.get_slice_mut(i.0, 4);
     w.put_u32(a.0)
   }
 
   pub fn emit_param(&mut self, t: Type) -> Value {
-    let mut w = self.buf.append(1);
-    w.put_u8(t.0);
+    self.put_u8(t.0);
     self.next_value()
   }
 
   pub fn emit_value(&mut self, x: Value) {
-    let mut w = self.buf.append(4);
-    w.put_u32(x.0);
+    self.put_value(x);
+  }
+
+  // Aggregate type definitions, which must come before the first function
+  // header. A struct must be followed by `nfields` types. Like the function
+  // header, they are fixed-width in either encoding.
+
+  pub fn emit_struct(&mut self, nfields: u32) -> Type {
+    self.put_u8(Tag::TYPE_STRUCT.0);
+    let _ = self.put_u32(nfields);
+    Type::aggregate(incr(&mut self.type_id))
+  }
+
+  pub fn emit_array(&mut self, t: Type, len: u32) -> Type {
+    self.put_u8(Tag::TYPE_ARRAY.0);
+    self.put_u8(t.0);
+    let _ = self.put_u32(len);
+    Type::aggregate(incr(&mut self.type_id))
   }
 
+  // A function header. It must be followed by `nargs` parameters, and then
+  // by `nkonts` result lists, each given by `emit_results` and its types.
+  // The header itself is fixed-width in either encoding.
+
   pub fn emit_function(&mut self, nkonts: u32, nargs: u32) {
-    let mut w = self.buf.append(9);
-    w.put_u8(Tag::FUNCTION.0);
-    w.put_u32(nkonts);
-    w.put_u32(nargs);
+    let tag =
+      match self.encoding {
+        Encoding::Fixed => Tag::FUNCTION,
+        Encoding::Compact => Tag::FUNCTION_COMPACT,
+      };
+    self.put_u8(tag.0);
+    let _ = self.put_u32(nkonts);
+    let _ = self.put_u32(nargs);
     self.value_id = 0;
     self.label_id = 1;
+    self.variable_id = 0;
+  }
+
+  pub fn emit_results(&mut self, nrets: u32) {
+    let _ = self.put_u32(nrets);
+  }
+
+  pub fn emit_type(&mut self, t: Type) -> PatchPoint {
+    let n = self.buf.len();
+    self.put_u8(t.0);
+    PatchPoint(n)
+  }
+
+  pub fn patch_type(&mut self, i: PatchPoint, t: Type) {
+    let mut w = self.buf.get_slice_mut(i.0, 1);
+    w.put_u8(t.0)
   }
 
   pub fn emit_case(&mut self) -> Label {
-    let mut w = self.buf.append(1);
-    w.put_u8(Tag::CASE.0);
+    self.put_u8(Tag::CASE.0);
     self.next_label()
   }
 
   pub fn emit_join(&mut self, nargs: u32) -> Label {
-    let mut w = self.buf.append(5);
-    w.put_u8(Tag::JOIN.0);
-    w.put_u32(nargs);
+    self.put_u8(Tag::JOIN.0);
+    self.put_count(nargs);
+    self.next_label()
+  }
+
+  pub fn emit_kont(&mut self, nargs: u32) -> Label {
+    self.put_u8(Tag::KONT.0);
+    self.put_count(nargs);
     self.next_label()
   }
 
   pub fn emit_const_bool(&mut self, p: bool) -> Value {
-    let mut w = self.buf.append(2);
-    w.put_u8(Tag::CONST_BOOL.0);
-    w.put_u8(p as u8);
+    self.put_u8(Tag::CONST_BOOL.0);
+    self.put_u8(p as u8);
+    self.next_value()
+  }
+
+  pub fn emit_const_i32(&mut self, c: u32) -> Value {
+    self.put_u8(Tag::CONST_I32.0);
+    match self.encoding {
+      Encoding::Fixed => { let _ = self.put_u32(c); }
+      Encoding::Compact => { self.put_sleb128(c as i32 as i64); }
+    }
     self.next_value()
   }
 
   pub fn emit_const_i64(&mut self, c: u64) -> Value {
+    self.put_u8(Tag::CONST_I64.0);
+    match self.encoding {
+      Encoding::Fixed => { let mut w = self.buf.append(8); w.put_u64(c); }
+      Encoding::Compact => { self.put_sleb128(c as i64); }
+    }
+    self.next_value()
+  }
+
+  // Small integer constants, which must be in range for their type.
+
+  pub fn emit_const_i5(&mut self, c: u8) -> Value {
+    assert!(c < 1 << 5);
+    self.put_u8(Tag::CONST_I5.0);
+    self.put_u8(c);
+    self.next_value()
+  }
+
+  pub fn emit_const_i6(&mut self, c: u8) -> Value {
+    assert!(c < 1 << 6);
+    self.put_u8(Tag::CONST_I6.0);
+    self.put_u8(c);
+    self.next_value()
+  }
+
+  pub fn emit_const_i7(&mut self, c: u8) -> Value {
+    assert!(c < 1 << 7);
+    self.put_u8(Tag::CONST_I7.0);
+    self.put_u8(c);
+    self.next_value()
+  }
+
+  pub fn emit_const_f32(&mut self, c: f32) -> Value {
+    let mut w = self.buf.append(5);
+    w.put_u8(Tag::CONST_F32.0);
+    w.put_f32(c);
+    self.next_value()
+  }
+
+  pub fn emit_const_f64(&mut self, c: f64) -> Value {
     let mut w = self.buf.append(9);
-    w.put_u8(Tag::CONST_I64.0);
-    w.put_u64(c);
+    w.put_u8(Tag::CONST_F64.0);
+    w.put_f64(c);
     self.next_value()
   }
 
   pub fn emit_op1(&mut self, t: Op1, x: Value) -> Value {
-    let mut w = self.buf.append(6);
-    w.put_u8(Tag::OP1.0);
-    w.put_u8(t.0);
-    w.put_u32(x.0);
+    self.put_u8(Tag::OP1.0);
+    self.put_u8(t.0);
+    self.put_value(x);
     self.next_value()
   }
 
   pub fn emit_op2(&mut self, t: Op2, x: Value, y: Value) -> Value {
-    let mut w = self.buf.append(10);
-    w.put_u8(Tag::OP2.0);
-    w.put_u8(t.0);
-    w.put_u32(x.0);
-    w.put_u32(y.0);
+    self.put_u8(Tag::OP2.0);
+    self.put_u8(t.0);
+    self.put_value(x);
+    self.put_value(y);
     self.next_value()
   }
 
   pub fn emit_select(&mut self, p: Value, x: Value, y: Value) -> Value {
-    let mut w = self.buf.append(13);
-    w.put_u8(Tag::SELECT.0);
-    w.put_u32(p.0);
-    w.put_u32(x.0);
-    w.put_u32(y.0);
+    self.put_u8(Tag::SELECT.0);
+    self.put_value(p);
+    self.put_value(x);
+    self.put_value(y);
     self.next_value()
   }
 
   pub fn emit_let_variable(&mut self, x: Value) -> Variable {
-    let mut w = self.buf.append(5);
-    w.put_u8(Tag::LET_VARIABLE.0);
-    w.put_u32(x.0);
+    self.put_u8(Tag::LET_VARIABLE.0);
+    self.put_value(x);
     self.next_variable()
   }
 
   pub fn emit_get_variable(&mut self, x: Variable) -> Value {
-    let mut w = self.buf.append(5);
-    w.put_u8(Tag::GET_VARIABLE.0);
-    w.put_u32(x.0);
+    self.put_u8(Tag::GET_VARIABLE.0);
+    self.put_count(x.0);
     self.next_value()
   }
 
   pub fn emit_set_variable(&mut self, x: Variable, y: Value) {
-    let mut w = self.buf.append(9);
-    w.put_u8(Tag::SET_VARIABLE.0);
-    w.put_u32(x.0);
-    w.put_u32(y.0);
+    self.put_u8(Tag::SET_VARIABLE.0);
+    self.put_count(x.0);
+    self.put_value(y);
   }
 
-  pub fn emit_if(&mut self, p: Value, a: Label, b: Label) -> (PatchPoint, PatchPoint) {
-    let n = self.buf.len();
-    let mut w = self.buf.append(13);
-    w.put_u8(Tag::IF.0);
-    w.put_u32(p.0);
-    w.put_u32(a.0);
-    w.put_u32(b.0);
-    (PatchPoint(n + 5), PatchPoint(n + 9))
+  // A stack slot of `size` bytes, aligned to `align` bytes.
+
+  pub fn emit_alloca(&mut self, size: u32, align: u32) -> Value {
+    assert!(align.is_power_of_two() && align <= MAX_ALIGN);
+    self.put_u8(Tag::ALLOCA.0);
+    self.put_count(size);
+    self.put_u8(align.trailing_zeros() as u8);
+    self.next_value()
   }
 
-  pub fn emit_goto(&mut self, a: Label, nargs: u32) -> PatchPoint {
-    let n = self.buf.len();
-    let mut w = self.buf.append(9);
-    w.put_u8(Tag::GOTO.0);
-    w.put_u32(a.0);
-    w.put_u32(nargs);
-    PatchPoint(n + 1)
+  pub fn emit_load(&mut self, t: MemType, flags: MemFlags, x: Value, offset: u32) -> Value {
+    self.put_u8(Tag::LOAD.0);
+    self.put_u8(t.0);
+    self.put_u8(flags.0);
+    self.put_value(x);
+    self.put_count(offset);
+    self.next_value()
   }
 
-  pub fn emit_return(&mut self, index: u32, nargs: u32) {
-    let mut w = self.buf.append(9);
-    w.put_u8(Tag::RETURN.0);
-    w.put_u32(index);
-    w.put_u32(nargs);
+  pub fn emit_store(&mut self, t: MemType, flags: MemFlags, x: Value, offset: u32, y: Value) {
+    self.put_u8(Tag::STORE.0);
+    self.put_u8(t.0);
+    self.put_u8(flags.0);
+    self.put_value(x);
+    self.put_count(offset);
+    self.put_value(y);
   }
-}
 
-fn chomp<'a, 'b>(buf: &'a mut &'b [u8], size: usize) -> Option<&'b [u8]> {
-  if size <= buf.len() {
-    Some(buf.pop_slice(size))
-  } else {
-    None
+  // An aggregate of type `t` with the given fields. Unlike the other
+  // instructions with a list of operands, it takes the list directly, as
+  // they come before the value it defines.
+
+  pub fn emit_build(&mut self, t: Type, args: &[Value]) -> Value {
+    self.put_u8(Tag::BUILD.0);
+    self.put_u8(t.0);
+    self.put_count(args.len() as u32);
+    for &x in args.iter() {
+      self.put_value(x);
+    }
+    self.next_value()
   }
-}
 
-pub fn read<'a, 'b>(buf: &'a mut &'b [u8]) -> Option<Instruction<'b>> {
-  let mut cursor = *buf;
+  pub fn emit_extract(&mut self, x: Value, i: u32) -> Value {
+    self.put_u8(Tag::EXTRACT.0);
+    self.put_value(x);
+    self.put_count(i);
+    self.next_value()
+  }
 
-  let instr =
-    match Tag(chomp(&mut cursor, 1)?.pop_u8()) {
-      Tag::FUNCTION => {
-        let mut r = chomp(&mut cursor, 8)?;
-        let nkonts = r.pop_u32();
-        let nargs = r.pop_u32();
-        let mut r = chomp(&mut cursor, nargs as usize)?;
-        Instruction::Function(nkonts, TypeList(r.pop_all()))
-      }
-      Tag::CASE => {
+  pub fn emit_insert(&mut self, x: Value, i: u32, y: Value) -> Value {
+    self.put_u8(Tag::INSERT.0);
+    self.put_value(x);
+    self.put_count(i);
+    self.put_value(y);
+    self.next_value()
+  }
+
+  pub fn emit_if(&mut self, p: Value, a: Label, b: Label) -> (PatchPoint, PatchPoint) {
+    self.put_u8(Tag::IF.0);
+    self.put_value(p);
+    let a = self.put_u32(a.0);
+    let b = self.put_u32(b.0);
+    (a, b)
+  }
+
+  pub fn emit_goto(&mut self, a: Label, nargs: u32) -> PatchPoint {
+    self.put_u8(Tag::GOTO.0);
+    let a = self.put_u32(a.0);
+    self.put_count(nargs);
+    a
+  }
+
+  pub fn emit_return(&mut self, index: u32, nargs: u32) {
+    self.put_u8(Tag::RETURN.0);
+    self.put_count(index);
+    self.put_count(nargs);
+  }
+
+  // A call to the `f`th function in the sequence. It must be followed by
+  // `nargs` values and then `nkonts` labels, one for each of the callee's
+  // continuations.
+
+  pub fn emit_call(&mut self, f: u32, nargs: u32, nkonts: u32) {
+    self.put_u8(Tag::CALL.0);
+    self.put_count(f);
+    self.put_count(nargs);
+    self.put_count(nkonts);
+  }
+
+  // A tail call to the `f`th function in the sequence, which returns
+  // directly to the caller's continuations. It must be followed by `nargs`
+  // values.
+
+  pub fn emit_tailcall(&mut self, f: u32, nargs: u32) {
+    self.put_u8(Tag::TAILCALL.0);
+    self.put_count(f);
+    self.put_count(nargs);
+  }
+
+  pub fn emit_label(&mut self, a: Label) -> PatchPoint {
+    self.put_u32(a.0)
+  }
+}
+
+// An error decoding the instruction at `offset`, which is relative to the
+// start of the buffer that `read` was given, or to the start of the code for
+// a `Reader`.
+
+pub struct DecodeError {
+  pub offset: usize,
+  pub tag: u8,
+  pub kind: DecodeErrorKind,
+}
+
+// An unknown tag is reported at the tag itself, and a truncated or invalid
+// operand at the operand, with a description of what was expected there. An
+// operand is invalid if it is all there but out of range, e.g. a compact
+// value that refers to a value not yet defined. `NeedsState` is a compact
+// function header given to `read`, which can't decode compact code.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+  UnknownTag,
+  Truncated { expected: &'static str },
+  Invalid { expected: &'static str },
+  NeedsState,
+}
+
+impl core::fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self.kind {
+      DecodeErrorKind::UnknownTag =>
+        write!(f, "at byte {}: unknown tag {:#04x}", self.offset, self.tag),
+      DecodeErrorKind::Truncated { expected } =>
+        write!(f, "at byte {}: truncated {} instruction, expected {}", self.offset, Tag(self.tag).name(), expected),
+      DecodeErrorKind::Invalid { expected } =>
+        write!(f, "at byte {}: invalid {} instruction, expected {}", self.offset, Tag(self.tag).name(), expected),
+      DecodeErrorKind::NeedsState =>
+        write!(f, "at byte {}: {} instruction needs a ReadState", self.offset, Tag(self.tag).name()),
+    }
+  }
+}
+
+fn truncated(expected: &'static str) -> DecodeErrorKind {
+  DecodeErrorKind::Truncated { expected }
+}
+
+// Decodes the operands of an instruction with the given tag, or returns
+// `None` if the tag is unknown. On failure, `r` is left at the operand that
+// couldn't be decoded.
+
+fn decode<'a, 'b>(tag: Tag, r: &'a mut ByteReader<'b>, state: &ReadState) -> Result<Option<Instruction<'b>>, DecodeErrorKind> {
+  let compact = state.compact;
+  let base = state.value_id;
+
+  // A compact operand that is all there but out of range is invalid, and is
+  // left unread, like a truncated one.
+
+  let count = |r: &mut ByteReader<'b>, expected| {
+    if compact {
+      let mut s = *r;
+      match s.try_pop_uleb128() {
+        Some(x) if x <= u32::MAX as u64 => { *r = s; Ok(x as u32) }
+        Some(_) => Err(DecodeErrorKind::Invalid { expected }),
+        None => Err(truncated(expected)),
+      }
+    } else {
+      r.try_pop_u32().ok_or(truncated(expected))
+    }
+  };
+
+  // A compact value counts back from the next one to be defined, so it must
+  // be less than `base`.
+
+  let value = |r: &mut ByteReader<'b>, expected| {
+    if compact {
+      let mut s = *r;
+      match s.try_pop_uleb128() {
+        Some(x) if x < base as u64 => { *r = s; Ok(Value(base - 1 - x as u32)) }
+        Some(_) => Err(DecodeErrorKind::Invalid { expected }),
+        None => Err(truncated(expected)),
+      }
+    } else {
+      Ok(Value(r.try_pop_u32().ok_or(truncated(expected))?))
+    }
+  };
+
+  let values = |r: &mut ByteReader<'b>, n: u32| {
+    if compact {
+      let start = r.rest();
+      for _ in 0 .. n {
+        let _ = value(r, "arguments")?;
+      }
+      Ok(ValueList(&start[.. start.len() - r.remaining()], Some(base)))
+    } else {
+      Ok(ValueList(r.try_pop_slice(n as usize * 4).ok_or(truncated("arguments"))?, None))
+    }
+  };
+
+  let instr =
+    match tag {
+      Tag::TYPE_STRUCT => {
+        let nfields = r.try_pop_u32().ok_or(truncated("field count"))?;
+        Instruction::TypeStruct(TypeList(r.try_pop_slice(nfields as usize).ok_or(truncated("field types"))?))
+      }
+      Tag::TYPE_ARRAY => {
+        let t = Type(r.try_pop_u8().ok_or(truncated("element type"))?);
+        let n = r.try_pop_u32().ok_or(truncated("length"))?;
+        Instruction::TypeArray(t, n)
+      }
+      Tag::FUNCTION | Tag::FUNCTION_COMPACT => {
+        let nkonts = r.try_pop_u32().ok_or(truncated("continuation count"))?;
+        let nargs = r.try_pop_u32().ok_or(truncated("parameter count"))?;
+        let args = TypeList(r.try_pop_slice(nargs as usize).ok_or(truncated("parameter types"))?);
+        let start = r.rest();
+        for _ in 0 .. nkonts {
+          let nrets = r.try_pop_u32().ok_or(truncated("result count"))?;
+          let _ = r.try_pop_slice(nrets as usize).ok_or(truncated("result types"))?;
+        }
+        let konts = KontList(nkonts, &start[.. start.len() - r.remaining()]);
+        Instruction::Function(konts, args)
+      }
+      Tag::CASE => {
         Instruction::Case()
       }
       Tag::JOIN => {
-        let mut r = chomp(&mut cursor, 4)?;
-        let nargs = r.pop_u32();
-        let mut r = chomp(&mut cursor, nargs as usize)?;
-        Instruction::Join(TypeList(r.pop_all()))
+        let nargs = count(r, "parameter count")?;
+        Instruction::Join(TypeList(r.try_pop_slice(nargs as usize).ok_or(truncated("parameter types"))?))
+      }
+      Tag::KONT => {
+        let nargs = count(r, "parameter count")?;
+        Instruction::Kont(TypeList(r.try_pop_slice(nargs as usize).ok_or(truncated("parameter types"))?))
       }
       Tag::CONST_BOOL => {
-        let mut r = chomp(&mut cursor, 1)?;
-        Instruction::ConstBool(r.pop_u8() != 0)
+        Instruction::ConstBool(r.try_pop_u8().ok_or(truncated("bool"))? != 0)
+      }
+      Tag::CONST_I32 => {
+        if compact {
+          let mut s = *r;
+          match s.try_pop_sleb128() {
+            Some(c) if c as i32 as i64 == c => { *r = s; Instruction::ConstI32(c as u32) }
+            Some(_) => return Err(DecodeErrorKind::Invalid { expected: "i32" }),
+            None => return Err(truncated("i32")),
+          }
+        } else {
+          Instruction::ConstI32(r.try_pop_u32().ok_or(truncated("i32"))?)
+        }
       }
       Tag::CONST_I64 => {
-        let mut r = chomp(&mut cursor, 8)?;
-        Instruction::ConstI64(r.pop_u64())
+        if compact {
+          Instruction::ConstI64(r.try_pop_sleb128().ok_or(truncated("i64"))? as u64)
+        } else {
+          Instruction::ConstI64(r.try_pop_u64().ok_or(truncated("i64"))?)
+        }
+      }
+      Tag::CONST_I5 => {
+        Instruction::ConstI5(r.try_pop_u8().ok_or(truncated("i5"))?)
+      }
+      Tag::CONST_I6 => {
+        Instruction::ConstI6(r.try_pop_u8().ok_or(truncated("i6"))?)
+      }
+      Tag::CONST_I7 => {
+        Instruction::ConstI7(r.try_pop_u8().ok_or(truncated("i7"))?)
+      }
+      Tag::CONST_F32 => {
+        Instruction::ConstF32(r.try_pop_f32().ok_or(truncated("f32"))?)
+      }
+      Tag::CONST_F64 => {
+        Instruction::ConstF64(r.try_pop_f64().ok_or(truncated("f64"))?)
       }
       Tag::OP1 => {
-        let mut r = chomp(&mut cursor, 5)?;
-        let t = Op1(r.pop_u8());
-        let x = Value(r.pop_u32());
+        let t = Op1(r.try_pop_u8().ok_or(truncated("operation"))?);
+        let x = value(r, "operand")?;
         Instruction::Op1(t, x)
       }
       Tag::OP2 => {
-        let mut r = chomp(&mut cursor, 9)?;
-        let t = Op2(r.pop_u8());
-        let x = Value(r.pop_u32());
-        let y = Value(r.pop_u32());
+        let t = Op2(r.try_pop_u8().ok_or(truncated("operation"))?);
+        let x = value(r, "operand")?;
+        let y = value(r, "operand")?;
         Instruction::Op2(t, x, y)
       }
       Tag::SELECT => {
-        let mut r = chomp(&mut cursor, 12)?;
-        let p = Value(r.pop_u32());
-        let x = Value(r.pop_u32());
-        let y = Value(r.pop_u32());
+        let p = value(r, "condition")?;
+        let x = value(r, "operand")?;
+        let y = value(r, "operand")?;
         Instruction::Select(p, x, y)
       }
       Tag::LET_VARIABLE => {
-        let mut r = chomp(&mut cursor, 4)?;
-        let x = Value(r.pop_u32());
+        let x = value(r, "operand")?;
         Instruction::LetVariable(x)
       }
       Tag::GET_VARIABLE => {
-        let mut r = chomp(&mut cursor, 4)?;
-        let x = Variable(r.pop_u32());
+        let x = Variable(count(r, "variable")?);
         Instruction::GetVariable(x)
       }
       Tag::SET_VARIABLE => {
-        let mut r = chomp(&mut cursor, 8)?;
-        let x = Variable(r.pop_u32());
-        let y = Value(r.pop_u32());
+        let x = Variable(count(r, "variable")?);
+        let y = value(r, "operand")?;
         Instruction::SetVariable(x, y)
       }
+      Tag::ALLOCA => {
+        let size = count(r, "size")?;
+        let align = r.try_pop_u8().ok_or(truncated("alignment"))?;
+        Instruction::Alloca(size, align)
+      }
+      Tag::LOAD => {
+        let t = MemType(r.try_pop_u8().ok_or(truncated("memory type"))?);
+        let flags = MemFlags(r.try_pop_u8().ok_or(truncated("flags"))?);
+        let x = value(r, "address")?;
+        let offset = count(r, "offset")?;
+        Instruction::Load(t, flags, x, offset)
+      }
+      Tag::STORE => {
+        let t = MemType(r.try_pop_u8().ok_or(truncated("memory type"))?);
+        let flags = MemFlags(r.try_pop_u8().ok_or(truncated("flags"))?);
+        let x = value(r, "address")?;
+        let offset = count(r, "offset")?;
+        let y = value(r, "operand")?;
+        Instruction::Store(t, flags, x, offset, y)
+      }
+      Tag::BUILD => {
+        let t = Type(r.try_pop_u8().ok_or(truncated("type"))?);
+        let nargs = count(r, "field count")?;
+        let args = values(r, nargs)?;
+        Instruction::Build(t, args)
+      }
+      Tag::EXTRACT => {
+        let x = value(r, "operand")?;
+        let i = count(r, "field")?;
+        Instruction::Extract(x, i)
+      }
+      Tag::INSERT => {
+        let x = value(r, "operand")?;
+        let i = count(r, "field")?;
+        let y = value(r, "operand")?;
+        Instruction::Insert(x, i, y)
+      }
       Tag::IF => {
-        let mut r = chomp(&mut cursor, 12)?;
-        let p = Value(r.pop_u32());
-        let a = Label(r.pop_u32());
-        let b = Label(r.pop_u32());
+        let p = value(r, "condition")?;
+        let a = Label(r.try_pop_u32().ok_or(truncated("label"))?);
+        let b = Label(r.try_pop_u32().ok_or(truncated("label"))?);
         Instruction::If(p, a, b)
       }
       Tag::GOTO => {
-        let mut r = chomp(&mut cursor, 8)?;
-        let a = r.pop_u32();
-        let nargs = r.pop_u32();
-        let mut r = chomp(&mut cursor, nargs as usize * 4)?;
-        Instruction::Goto(Label(a), ValueList(r.pop_all()))
+        let a = Label(r.try_pop_u32().ok_or(truncated("label"))?);
+        let nargs = count(r, "argument count")?;
+        let args = values(r, nargs)?;
+        Instruction::Goto(a, args)
       }
       Tag::RETURN => {
-        let mut r = chomp(&mut cursor, 8)?;
-        let index = r.pop_u32();
-        let nargs = r.pop_u32();
-        let mut r = chomp(&mut cursor, nargs as usize * 4)?;
-        Instruction::Return(index, ValueList(r.pop_all()))
+        let index = count(r, "continuation")?;
+        let nargs = count(r, "argument count")?;
+        let args = values(r, nargs)?;
+        Instruction::Return(index, args)
+      }
+      Tag::CALL => {
+        let f = count(r, "function")?;
+        let nargs = count(r, "argument count")?;
+        let nkonts = count(r, "continuation count")?;
+        let args = values(r, nargs)?;
+        let konts = LabelList(r.try_pop_slice(nkonts as usize * 4).ok_or(truncated("labels"))?);
+        Instruction::Call(f, args, konts)
+      }
+      Tag::TAILCALL => {
+        let f = count(r, "function")?;
+        let nargs = count(r, "argument count")?;
+        let args = values(r, nargs)?;
+        Instruction::TailCall(f, args)
       }
       _ => {
-        return None;
+        return Ok(None);
       }
     };
 
-  *buf = cursor;
+  Ok(Some(instr))
+}
+
+// What `read` needs to know about the instructions before the next one: the
+// encoding of the current function, and the number of values it has defined
+// so far.
+
+#[derive(Clone, Copy)]
+pub struct ReadState {
+  compact: bool,
+  value_id: u32,
+}
+
+impl ReadState {
+  pub fn new() -> Self {
+    Self { compact: false, value_id: 0 }
+  }
+
+  pub fn encoding(&self) -> Encoding {
+    if self.compact { Encoding::Compact } else { Encoding::Fixed }
+  }
+}
+
+impl Default for ReadState {
+  fn default() -> Self {
+    Self::new()
+  }
+}
 
-  return Some(instr);
+// Reads one instruction of code in the fixed encoding, which needs no state
+// from the instructions before it. A compact function header is reported as
+// `NeedsState`.
+
+pub fn read<'a, 'b>(buf: &'a mut &'b [u8]) -> Result<Option<Instruction<'b>>, DecodeError> {
+  if buf.first() == Some(&Tag::FUNCTION_COMPACT.0) {
+    return Err(DecodeError { offset: 0, tag: Tag::FUNCTION_COMPACT.0, kind: DecodeErrorKind::NeedsState });
+  }
+  read_with_state(buf, &mut ReadState::new())
+}
+
+// Reads one instruction and advances `buf` past it, or returns `None` at the
+// end of the buffer. On error, `buf` and `state` are left unchanged.
+//
+// The same `state` must be used for every instruction of a function, starting
+// from its header.
+
+pub fn read_with_state<'a, 'b>(buf: &'a mut &'b [u8], state: &mut ReadState) -> Result<Option<Instruction<'b>>, DecodeError> {
+  let mut r = ByteReader::new(buf);
+
+  let Some(tag) = r.try_pop_u8() else {
+    return Ok(None);
+  };
+
+  match decode(Tag(tag), &mut r, state) {
+    Ok(Some(instr)) => {
+      let ndefs =
+        match &instr {
+          Instruction::Function(_, args) => {
+            state.compact = Tag(tag) == Tag::FUNCTION_COMPACT;
+            state.value_id = 0;
+            args.0.len()
+          }
+          Instruction::Join(args) | Instruction::Kont(args) => args.0.len(),
+          Instruction::ConstBool(_)
+          | Instruction::ConstI32(_)
+          | Instruction::ConstI64(_)
+          | Instruction::ConstI5(_)
+          | Instruction::ConstI6(_)
+          | Instruction::ConstI7(_)
+          | Instruction::ConstF32(_)
+          | Instruction::ConstF64(_)
+          | Instruction::Op1(..)
+          | Instruction::Op2(..)
+          | Instruction::Select(..)
+          | Instruction::GetVariable(_)
+          | Instruction::Alloca(..)
+          | Instruction::Load(..)
+          | Instruction::Build(..)
+          | Instruction::Extract(..)
+          | Instruction::Insert(..) => 1,
+          _ => 0,
+        };
+      state.value_id = state.value_id.wrapping_add(ndefs as u32);
+      *buf = r.rest();
+      Ok(Some(instr))
+    }
+    Ok(None) => {
+      Err(DecodeError { offset: 0, tag, kind: DecodeErrorKind::UnknownTag })
+    }
+    Err(kind) => {
+      Err(DecodeError { offset: r.position(), tag, kind })
+    }
+  }
+}
+
+// Iterates over the instructions in `code` with their offsets. After an
+// error, the iteration ends.
+
+pub struct Reader<'a> {
+  code: &'a [u8],
+  rest: &'a [u8],
+  state: ReadState,
+}
+
+impl<'a> Reader<'a> {
+  pub fn new(code: &'a [u8]) -> Self {
+    Self { code, rest: code, state: ReadState::new() }
+  }
+
+  // The offset of the next instruction.
+
+  pub fn offset(&self) -> usize {
+    self.code.len() - self.rest.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.rest.is_empty()
+  }
+}
+
+impl<'a> Iterator for Reader<'a> {
+  type Item = Result<(usize, Instruction<'a>), DecodeError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let offset = self.offset();
+    match read_with_state(&mut self.rest, &mut self.state) {
+      Ok(instr) => instr.map(|instr| Ok((offset, instr))),
+      Err(e) => {
+        self.rest = &[];
+        Some(Err(DecodeError { offset: offset + e.offset, ..e }))
+      }
+    }
+  }
 }
 
 pub fn display(buf: &[u8]) {
-  let mut r = buf;
+  let mut r = Reader::new(buf);
   let mut function_id = 0;
   let mut label_id = 0;
   let mut value_id = 0;
   let mut nkonts = 0;
+  let mut type_id = 0;
 
   fn next(x: &mut u32) -> u32 {
     let y = *x;
@@ -501,14 +2825,31 @@ pub fn display(buf: &[u8]) {
   }
 
   loop {
-    match read(&mut r) {
+    match r.next() {
       None => { break; }
-      Some(inst) => {
+      Some(Err(e)) => {
+        print!("ERROR {}\n", e);
+        break;
+      }
+      Some(Ok((_, inst))) => {
         match inst {
-          Instruction::Function(n, args) => {
+          Instruction::TypeStruct(fields) => {
+            print!("agg{} = struct (", next(&mut type_id));
+            for (i, ty) in fields.iter().enumerate() {
+              if i != 0 {
+                print!(", ");
+              }
+              print!("{}", ty);
+            }
+            print!(")\n");
+          }
+          Instruction::TypeArray(ty, n) => {
+            print!("agg{} = array {} #{}\n", next(&mut type_id), ty, n);
+          }
+          Instruction::Function(konts, args) => {
             label_id = 0;
             value_id = 0;
-            nkonts = n;
+            nkonts = konts.len();
             print!("{}: function ${} (", next(&mut function_id), next(&mut label_id));
             for (i, ty) in args.iter().enumerate() {
               if i != 0 {
@@ -521,11 +2862,16 @@ pub fn display(buf: &[u8]) {
               print!("!");
             } else {
               print!("(");
-              for i in 0 .. nkonts {
+              for (i, rets) in konts.iter().enumerate() {
                 if i != 0 {
-                  print!("|");
+                  print!(" | ");
+                }
+                for (j, ty) in rets.iter().enumerate() {
+                  if j != 0 {
+                    print!(", ");
+                  }
+                  print!("{}", ty);
                 }
-                print!("...");
               }
               print!(")");
             }
@@ -544,12 +2890,48 @@ pub fn display(buf: &[u8]) {
             }
             print!(")\n");
           }
+          Instruction::Kont(args) => {
+            print!("{}: kont (", next(&mut label_id));
+            for (i, ty) in args.iter().enumerate() {
+              if i != 0 {
+                print!(", ");
+              }
+              print!("%{} {}", next(&mut value_id), ty);
+            }
+            print!(")\n");
+          }
           Instruction::ConstBool(p) => {
             print!("\t%{} = const.bool #{}\n", next(&mut value_id), p);
           }
+          Instruction::ConstI32(c) => {
+            print!("\t%{} = const.i32 #{}\n", next(&mut value_id), c);
+          }
           Instruction::ConstI64(c) => {
             print!("\t%{} = const.i64 #{}\n", next(&mut value_id), c);
           }
+          Instruction::ConstI5(c) => {
+            print!("\t%{} = const.i5 #{}\n", next(&mut value_id), c);
+          }
+          Instruction::ConstI6(c) => {
+            print!("\t%{} = const.i6 #{}\n", next(&mut value_id), c);
+          }
+          Instruction::ConstI7(c) => {
+            print!("\t%{} = const.i7 #{}\n", next(&mut value_id), c);
+          }
+          Instruction::ConstF32(c) => {
+            if c.is_nan() {
+              print!("\t%{} = const.f32 #nan:0x{:08x}\n", next(&mut value_id), c.to_bits());
+            } else {
+              print!("\t%{} = const.f32 #{:?}\n", next(&mut value_id), c);
+            }
+          }
+          Instruction::ConstF64(c) => {
+            if c.is_nan() {
+              print!("\t%{} = const.f64 #nan:0x{:016x}\n", next(&mut value_id), c.to_bits());
+            } else {
+              print!("\t%{} = const.f64 #{:?}\n", next(&mut value_id), c);
+            }
+          }
           Instruction::Op1(t, x) => {
             print!("\t%{} = {} {}\n", next(&mut value_id), t, x);
           }
@@ -559,6 +2941,31 @@ pub fn display(buf: &[u8]) {
           Instruction::Select(p, x, y) => {
             print!("\t%{} = select {} {} {}\n", next(&mut value_id), p, x, y);
           }
+          Instruction::Alloca(size, align) => {
+            print!("\t%{} = alloca #{} align {}\n", next(&mut value_id), size, 1u64 << (align & 0x3f));
+          }
+          Instruction::Load(t, flags, x, offset) => {
+            print!("\t%{} = load.{} {} +{} {}\n", next(&mut value_id), t, x, offset, flags);
+          }
+          Instruction::Store(t, flags, x, offset, y) => {
+            print!("\tstore.{} {} +{} {} {}\n", t, x, offset, y, flags);
+          }
+          Instruction::Build(t, args) => {
+            print!("\t%{} = build {} (", next(&mut value_id), t);
+            for (i, x) in args.iter().enumerate() {
+              if i != 0 {
+                print!(", ");
+              }
+              print!("{}", x);
+            }
+            print!(")\n");
+          }
+          Instruction::Extract(x, i) => {
+            print!("\t%{} = extract {} #{}\n", next(&mut value_id), x, i);
+          }
+          Instruction::Insert(x, i, y) => {
+            print!("\t%{} = insert {} #{} {}\n", next(&mut value_id), x, i, y);
+          }
           Instruction::If(p, a, b) => {
             print!("\tif {} then {} else {}\n", p, a, b);
           }
@@ -588,6 +2995,33 @@ pub fn display(buf: &[u8]) {
             }
             print!(")\n");
           }
+          Instruction::Call(f, args, konts) => {
+            print!("\tcall {} (", f);
+            for (i, x) in args.iter().enumerate() {
+              if i != 0 {
+                print!(", ");
+              }
+              print!("{}", x);
+            }
+            print!(") -> (");
+            for (i, a) in konts.iter().enumerate() {
+              if i != 0 {
+                print!(" | ");
+              }
+              print!("{}", a);
+            }
+            print!(")\n");
+          }
+          Instruction::TailCall(f, args) => {
+            print!("\ttailcall {} (", f);
+            for (i, x) in args.iter().enumerate() {
+              if i != 0 {
+                print!(", ");
+              }
+              print!("{}", x);
+            }
+            print!(")\n");
+          }
           _ => {
             print!("UNKNOWN INSTRUCTION\n")
           }
@@ -596,3 +3030,1102 @@ pub fn display(buf: &[u8]) {
     }
   }
 }
+
+pub struct VerifyError {
+  pub offset: usize,
+  pub message: &'static str,
+}
+
+impl core::fmt::Display for VerifyError {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(f, "at byte {}: {}", self.offset, self.message)
+  }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum BlockKind {
+  Entry,
+  Case,
+  Join(Vec<Type>),
+  Kont(Vec<Type>),
+}
+
+struct FunctionInfo {
+  results: Vec<Vec<Type>>,
+  values: Vec<Type>,
+  variables: Vec<Type>,
+  blocks: Vec<BlockKind>,
+  targets: Vec<(usize, Label, BlockKind)>,
+}
+
+// The parameter types and the result types of each continuation of a
+// function.
+
+struct Signature {
+  params: Vec<Type>,
+  results: Vec<Vec<Type>>,
+}
+
+fn verify_error(offset: usize, message: &'static str) -> VerifyError {
+  VerifyError { offset, message }
+}
+
+// Checks that every type in `types` is a scalar or a defined aggregate.
+
+fn check_types(table: &TypeTable, offset: usize, types: &TypeList<'_>) -> Result<(), VerifyError> {
+  if types.iter().all(|t| table.is_defined(t)) {
+    Ok(())
+  } else {
+    Err(verify_error(offset, "use of undefined type"))
+  }
+}
+
+// Checks an aggregate type definition, and adds it to `table`.
+
+fn define_type(table: &mut TypeTable, offset: usize, a: Aggregate) -> Result<(), VerifyError> {
+  if table.len() == MAX_AGGREGATES {
+    return Err(verify_error(offset, "too many aggregate types"));
+  }
+  let defined =
+    match &a {
+      Aggregate::Struct(fields) => fields.iter().all(|&t| table.is_defined(t)),
+      &Aggregate::Array(t, _) => table.is_defined(t),
+    };
+  if ! defined {
+    return Err(verify_error(offset, "use of undefined type"));
+  }
+  let t = table.push(a);
+  if table.width(t) > MAX_WIDTH {
+    return Err(verify_error(offset, "aggregate too large"));
+  }
+  Ok(())
+}
+
+impl FunctionInfo {
+  fn value(&self, offset: usize, x: Value) -> Result<Type, VerifyError> {
+    match self.values.get(x.0 as usize) {
+      Some(&t) => Ok(t),
+      None => Err(verify_error(offset, "use of undefined value")),
+    }
+  }
+
+  fn variable(&self, offset: usize, x: Variable) -> Result<Type, VerifyError> {
+    match self.variables.get(x.0 as usize) {
+      Some(&t) => Ok(t),
+      None => Err(verify_error(offset, "use of undefined variable")),
+    }
+  }
+
+  fn value_of_type(&self, offset: usize, x: Value, t: Type) -> Result<(), VerifyError> {
+    if self.value(offset, x)? == t {
+      Ok(())
+    } else {
+      Err(verify_error(offset, "operand has the wrong type"))
+    }
+  }
+
+  fn args(&self, offset: usize, args: &ValueList<'_>, types: &[Type]) -> Result<(), VerifyError> {
+    if args.iter().count() != types.len() {
+      return Err(verify_error(offset, "wrong number of arguments"));
+    }
+    for (x, &t) in args.iter().zip(types.iter()) {
+      self.value_of_type(offset, x, t)?;
+    }
+    Ok(())
+  }
+
+  fn finish(&self) -> Result<(), VerifyError> {
+    for (offset, a, kind) in self.targets.iter() {
+      match self.blocks.get(a.0 as usize) {
+        None => {
+          return Err(verify_error(*offset, "branch to undefined label"));
+        }
+        Some(BlockKind::Entry) => {
+          return Err(verify_error(*offset, "branch to function entry"));
+        }
+        Some(k) if k != kind => {
+          return Err(verify_error(*offset, "branch to block of the wrong kind or type"));
+        }
+        Some(_) => {}
+      }
+    }
+    Ok(())
+  }
+}
+
+// Checks that `buf` is a well-formed and well-typed sequence of functions.
+//
+// - every instruction decodes
+// - every block starts with an entry instruction and ends with a terminator
+// - every value and variable is defined earlier in the same function
+// - every operation is known, and its operands have the types it expects
+// - conditions are bools, and the arms of a `select` have the same type
+// - memory accesses are through a `ref`, with a known memory type and valid
+//   flags, and stores store a value of that type
+// - aggregate types are defined before the first function, in terms of
+//   scalars and earlier aggregates, and are no larger than `MAX_WIDTH`
+// - `build` is given a value of each field's type, and `extract` and
+//   `insert` use a field that exists
+// - `if` branches to `case` blocks and `goto` branches to `join` blocks with
+//   parameters of the same types as its arguments
+// - `return` uses one of the function's continuations, and passes values of
+//   its result types
+// - calls pass arguments of the callee's parameter types, and supply a `kont`
+//   block for each of its continuations with parameters of the corresponding
+//   result types
+// - tail calls are to functions with the same result types as the caller
+//
+// Note that "defined earlier" is about the order of the encoding, and is
+// weaker than dominance.
+
+pub fn verify(buf: &[u8]) -> Result<(), VerifyError> {
+  let mut r = buf;
+  let mut s = ReadState::new();
+  let mut signatures = Vec::new();
+
+  while let Ok(Some(inst)) = read_with_state(&mut r, &mut s) {
+    if let Instruction::Function(konts, args) = inst {
+      signatures.push(Signature {
+        params: args.iter().collect(),
+        results: konts.iter().map(|rets| rets.iter().collect()).collect(),
+      });
+    }
+  }
+
+  let mut r = buf;
+  let mut s = ReadState::new();
+  let mut table = TypeTable::new();
+  let mut fun: Option<FunctionInfo> = None;
+  let mut in_block = false;
+
+  loop {
+    let offset = buf.len() - r.len();
+
+    let inst =
+      match read_with_state(&mut r, &mut s) {
+        Ok(Some(inst)) => inst,
+        Ok(None) => {
+          if in_block {
+            return Err(verify_error(offset, "unterminated block"));
+          }
+          if let Some(f) = fun.as_ref() {
+            f.finish()?;
+          }
+          return Ok(());
+        }
+        Err(e) => {
+          return Err(verify_error(offset + e.offset, "malformed instruction"));
+        }
+      };
+
+    match inst {
+      Instruction::TypeStruct(ref fields) => {
+        if fun.is_some() {
+          return Err(verify_error(offset, "type definition after a function"));
+        }
+        define_type(&mut table, offset, Aggregate::Struct(fields.iter().collect()))?;
+        continue;
+      }
+      Instruction::TypeArray(t, n) => {
+        if fun.is_some() {
+          return Err(verify_error(offset, "type definition after a function"));
+        }
+        define_type(&mut table, offset, Aggregate::Array(t, n))?;
+        continue;
+      }
+      _ => {}
+    }
+
+    if let Instruction::Function(konts, args) = inst {
+      if in_block {
+        return Err(verify_error(offset, "unterminated block"));
+      }
+      check_types(&table, offset, &args)?;
+      for rets in konts.iter() {
+        check_types(&table, offset, &rets)?;
+      }
+      if let Some(f) = fun.as_ref() {
+        f.finish()?;
+      }
+      fun = Some(FunctionInfo {
+        results: konts.iter().map(|rets| rets.iter().collect()).collect(),
+        values: args.iter().collect(),
+        variables: Vec::new(),
+        blocks: vec![BlockKind::Entry],
+        targets: Vec::new(),
+      });
+      in_block = true;
+      continue;
+    }
+
+    let Some(f) = fun.as_mut() else {
+      return Err(verify_error(offset, "instruction outside of function"));
+    };
+
+    match inst {
+      Instruction::Case() | Instruction::Join(_) | Instruction::Kont(_) => {
+        if in_block {
+          return Err(verify_error(offset, "unterminated block"));
+        }
+        in_block = true;
+      }
+      _ => {
+        if ! in_block {
+          return Err(verify_error(offset, "instruction outside of block"));
+        }
+      }
+    }
+
+    match inst {
+      Instruction::Case() => {
+        f.blocks.push(BlockKind::Case);
+      }
+      Instruction::Join(args) => {
+        check_types(&table, offset, &args)?;
+        f.blocks.push(BlockKind::Join(args.iter().collect()));
+        f.values.extend(args.iter());
+      }
+      Instruction::Kont(args) => {
+        check_types(&table, offset, &args)?;
+        f.blocks.push(BlockKind::Kont(args.iter().collect()));
+        f.values.extend(args.iter());
+      }
+      Instruction::ConstBool(_) => {
+        f.values.push(Type::BOOL);
+      }
+      Instruction::ConstI32(_) => {
+        f.values.push(Type::I32);
+      }
+      Instruction::ConstI64(_) => {
+        f.values.push(Type::I64);
+      }
+      Instruction::ConstI5(c) => {
+        if c >= 1 << 5 {
+          return Err(verify_error(offset, "constant out of range"));
+        }
+        f.values.push(Type::I5);
+      }
+      Instruction::ConstI6(c) => {
+        if c >= 1 << 6 {
+          return Err(verify_error(offset, "constant out of range"));
+        }
+        f.values.push(Type::I6);
+      }
+      Instruction::ConstI7(c) => {
+        if c >= 1 << 7 {
+          return Err(verify_error(offset, "constant out of range"));
+        }
+        f.values.push(Type::I7);
+      }
+      Instruction::ConstF32(_) => {
+        f.values.push(Type::F32);
+      }
+      Instruction::ConstF64(_) => {
+        f.values.push(Type::F64);
+      }
+      Instruction::Op1(t, x) => {
+        if ! t.is_valid() {
+          return Err(verify_error(offset, "unknown operation"));
+        }
+        f.value_of_type(offset, x, t.arg_type())?;
+        f.values.push(t.ret_type());
+      }
+      Instruction::Op2(t, x, y) => {
+        if ! t.is_valid() {
+          return Err(verify_error(offset, "unknown operation"));
+        }
+        let (a, b) = t.arg_types();
+        f.value_of_type(offset, x, a)?;
+        f.value_of_type(offset, y, b)?;
+        f.values.push(t.ret_type());
+      }
+      Instruction::Select(p, x, y) => {
+        f.value_of_type(offset, p, Type::BOOL)?;
+        let a = f.value(offset, x)?;
+        f.value_of_type(offset, y, a)?;
+        f.values.push(a);
+      }
+      Instruction::LetVariable(x) => {
+        let a = f.value(offset, x)?;
+        f.variables.push(a);
+      }
+      Instruction::GetVariable(v) => {
+        let a = f.variable(offset, v)?;
+        f.values.push(a);
+      }
+      Instruction::SetVariable(v, x) => {
+        let a = f.variable(offset, v)?;
+        f.value_of_type(offset, x, a)?;
+      }
+      Instruction::Alloca(_, align) => {
+        if align > MAX_ALIGN.trailing_zeros() as u8 {
+          return Err(verify_error(offset, "alignment too large"));
+        }
+        f.values.push(Type::Ref);
+      }
+      Instruction::Load(t, flags, x, _) => {
+        if ! t.is_valid() {
+          return Err(verify_error(offset, "unknown memory type"));
+        }
+        if ! flags.is_valid() {
+          return Err(verify_error(offset, "invalid memory flags"));
+        }
+        f.value_of_type(offset, x, Type::Ref)?;
+        f.values.push(t.value_type());
+      }
+      Instruction::Store(t, flags, x, _, y) => {
+        if ! t.is_valid() {
+          return Err(verify_error(offset, "unknown memory type"));
+        }
+        if ! flags.is_valid() {
+          return Err(verify_error(offset, "invalid memory flags"));
+        }
+        f.value_of_type(offset, x, Type::Ref)?;
+        f.value_of_type(offset, y, t.value_type())?;
+      }
+      Instruction::Build(t, args) => {
+        if table.get(t).is_none() {
+          return Err(verify_error(offset, "build of a type that isn't an aggregate"));
+        }
+        if args.iter().count() as u64 != table.num_fields(t) as u64 {
+          return Err(verify_error(offset, "wrong number of fields"));
+        }
+        for (i, x) in args.iter().enumerate() {
+          f.value_of_type(offset, x, table.field(t, i as u32))?;
+        }
+        f.values.push(t);
+      }
+      Instruction::Extract(x, i) => {
+        let t = f.value(offset, x)?;
+        if table.get(t).is_none() {
+          return Err(verify_error(offset, "field of a value that isn't an aggregate"));
+        }
+        if i >= table.num_fields(t) {
+          return Err(verify_error(offset, "field index out of range"));
+        }
+        f.values.push(table.field(t, i));
+      }
+      Instruction::Insert(x, i, y) => {
+        let t = f.value(offset, x)?;
+        if table.get(t).is_none() {
+          return Err(verify_error(offset, "field of a value that isn't an aggregate"));
+        }
+        if i >= table.num_fields(t) {
+          return Err(verify_error(offset, "field index out of range"));
+        }
+        f.value_of_type(offset, y, table.field(t, i))?;
+        f.values.push(t);
+      }
+      Instruction::If(p, a, b) => {
+        f.value_of_type(offset, p, Type::BOOL)?;
+        f.targets.push((offset, a, BlockKind::Case));
+        f.targets.push((offset, b, BlockKind::Case));
+        in_block = false;
+      }
+      Instruction::Goto(a, args) => {
+        let mut types = Vec::new();
+        for x in args.iter() {
+          types.push(f.value(offset, x)?);
+        }
+        f.targets.push((offset, a, BlockKind::Join(types)));
+        in_block = false;
+      }
+      Instruction::Return(index, args) => {
+        let Some(rets) = f.results.get(index as usize) else {
+          return Err(verify_error(offset, "return to undefined continuation"));
+        };
+        f.args(offset, &args, rets)?;
+        in_block = false;
+      }
+      Instruction::Call(g, args, konts) => {
+        let Some(g) = signatures.get(g as usize) else {
+          return Err(verify_error(offset, "call to undefined function"));
+        };
+        f.args(offset, &args, &g.params)?;
+        if konts.iter().count() != g.results.len() {
+          return Err(verify_error(offset, "wrong number of continuations"));
+        }
+        for (a, rets) in konts.iter().zip(g.results.iter()) {
+          f.targets.push((offset, a, BlockKind::Kont(rets.clone())));
+        }
+        in_block = false;
+      }
+      Instruction::TailCall(g, args) => {
+        let Some(g) = signatures.get(g as usize) else {
+          return Err(verify_error(offset, "call to undefined function"));
+        };
+        f.args(offset, &args, &g.params)?;
+        if g.results != f.results {
+          return Err(verify_error(offset, "tail call to function with different result types"));
+        }
+        in_block = false;
+      }
+      _ => {
+        return Err(verify_error(offset, "unsupported instruction"));
+      }
+    }
+  }
+}
+
+// A sequence of named functions.
+//
+// - `code` is the concatenated encodings of the functions
+// - `types` holds each function's signature, encoded as the number of
+//   parameters and their types, followed by the number of continuations and
+//   the number and types of the results of each
+// - `strings` holds the function names
+
+pub struct Module {
+  code: Vec<u8>,
+  functions: Vec<FunctionEntry>,
+  types: Vec<u8>,
+  strings: Vec<u8>,
+  // The first function with each name, for `find`.
+  index: HashMap<Box<[u8]>, u32>,
+}
+
+#[derive(Clone, Copy)]
+pub struct FunctionEntry {
+  pub name: (u32, u32),
+  pub offset: u32,
+  pub size: u32,
+  pub signature: u32,
+}
+
+#[derive(Clone, Copy)]
+pub struct ModuleHeader {
+  pub nfunctions: u32,
+  pub code_size: u32,
+  pub types_size: u32,
+  pub strings_size: u32,
+}
+
+impl Module {
+  pub fn new() -> Self {
+    Self {
+      code: Vec::new(),
+      functions: Vec::new(),
+      types: Vec::new(),
+      strings: Vec::new(),
+      index: HashMap::new(),
+    }
+  }
+
+  // A module from its tables, which are assumed to be consistent with each
+  // other.
+
+  pub(crate) fn from_parts(code: Vec<u8>, functions: Vec<FunctionEntry>, types: Vec<u8>, strings: Vec<u8>) -> Self {
+    let mut m = Self { code, functions, types, strings, index: HashMap::new() };
+    for i in 0 .. m.len() {
+      let name = m.name(i).into();
+      let _ = m.index.entry(name).or_insert(i);
+    }
+    m
+  }
+
+  pub fn header(&self) -> ModuleHeader {
+    ModuleHeader {
+      nfunctions: self.functions.len() as u32,
+      code_size: self.code.len() as u32,
+      types_size: self.types.len() as u32,
+      strings_size: self.strings.len() as u32,
+    }
+  }
+
+  // Appends a function, given by the encoding of exactly one function, and
+  // returns its index.
+
+  pub fn add_function(&mut self, name: &[u8], code: &[u8]) -> u32 {
+    let mut r = code;
+    let mut s = ReadState::new();
+    let Ok(Some(Instruction::Function(konts, args))) = read_with_state(&mut r, &mut s) else { panic!() };
+
+    let signature = self.types.len() as u32;
+    self.types.extend_from_slice(&(args.0.len() as u32).to_le_bytes());
+    self.types.extend_from_slice(args.0);
+    self.types.extend_from_slice(&konts.0.to_le_bytes());
+    self.types.extend_from_slice(konts.1);
+
+    let index = self.functions.len() as u32;
+    self.functions.push(FunctionEntry {
+      name: (self.strings.len() as u32, name.len() as u32),
+      offset: self.code.len() as u32,
+      size: code.len() as u32,
+      signature,
+    });
+    self.strings.extend_from_slice(name);
+    self.code.extend_from_slice(code);
+    let _ = self.index.entry(name.into()).or_insert(index);
+    index
+  }
+
+  pub fn len(&self) -> u32 {
+    self.functions.len() as u32
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.functions.is_empty()
+  }
+
+  pub fn entry(&self, i: u32) -> FunctionEntry {
+    self.functions[i as usize]
+  }
+
+  // The index of the first function named `name`.
+
+  pub fn find(&self, name: &[u8]) -> Option<u32> {
+    self.index.get(name).copied()
+  }
+
+  pub fn name(&self, i: u32) -> &[u8] {
+    let (offset, size) = self.entry(i).name;
+    &self.strings[offset as usize .. (offset + size) as usize]
+  }
+
+  pub fn function(&self, i: u32) -> &[u8] {
+    let e = self.entry(i);
+    &self.code[e.offset as usize .. (e.offset + e.size) as usize]
+  }
+
+  pub fn params(&self, i: u32) -> TypeList<'_> {
+    let mut r = &self.types[self.entry(i).signature as usize ..];
+    let n = r.pop_u32();
+    TypeList(r.pop_slice(n as usize))
+  }
+
+  pub fn results(&self, i: u32) -> KontList<'_> {
+    let mut r = &self.types[self.entry(i).signature as usize ..];
+    let n = r.pop_u32();
+    let _ = r.pop_slice(n as usize);
+    let nkonts = r.pop_u32();
+    KontList(nkonts, r)
+  }
+
+  // The functions in order, which `Call` and `TailCall` index into.
+
+  pub fn code(&self) -> &[u8] {
+    &self.code
+  }
+
+  pub fn types(&self) -> &[u8] {
+    &self.types
+  }
+
+  pub fn strings(&self) -> &[u8] {
+    &self.strings
+  }
+}
+
+impl Default for Module {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn division_by_zero() {
+    let x = 7u64;
+    assert!(Op2::DIV_S_I64.eval(x, 0) == u64::MAX);
+    assert!(Op2::DIV_U_I64.eval(x, 0) == u64::MAX);
+    assert!(Op2::REM_S_I64.eval(x, 0) == x);
+    assert!(Op2::REM_U_I64.eval(x, 0) == x);
+    assert!(Op2::DIV_S_I32.eval(x, 0) == u32::MAX as u64);
+    assert!(Op2::DIV_U_I32.eval(x, 0) == u32::MAX as u64);
+    assert!(Op2::REM_S_I32.eval(x, 0) == x);
+    assert!(Op2::REM_U_I32.eval(x, 0) == x);
+    // Only the low 32 bits of an i32 divisor count.
+    assert!(Op2::DIV_U_I32.eval(x, 1 << 32) == u32::MAX as u64);
+  }
+
+  #[test]
+  fn division_overflow() {
+    let min64 = i64::MIN as u64;
+    let min32 = i32::MIN as u32 as u64;
+    assert!(Op2::DIV_S_I64.eval(min64, u64::MAX) == min64);
+    assert!(Op2::REM_S_I64.eval(min64, u64::MAX) == 0);
+    assert!(Op2::DIV_S_I32.eval(min32, u32::MAX as u64) == min32);
+    assert!(Op2::REM_S_I32.eval(min32, u32::MAX as u64) == 0);
+    assert!(Op2::DIV_S_I32.eval(u32::MAX as u64, 2) == 0);
+    assert!(Op2::REM_S_I32.eval(u32::MAX as u64, 2) == u32::MAX as u64);
+  }
+
+  #[test]
+  fn shifts() {
+    assert!(Op2::SHL_I64.eval(1, 63) == 1 << 63);
+    assert!(Op2::SHR_S_I64.eval(1 << 63, 63) == u64::MAX);
+    assert!(Op2::SHR_U_I64.eval(1 << 63, 63) == 1);
+    assert!(Op2::SHL_I32.eval(1, 31) == 1 << 31);
+    assert!(Op2::SHL_I32.eval(3, 31) == 1 << 31);
+    assert!(Op2::SHR_S_I32.eval(1 << 31, 31) == u32::MAX as u64);
+    assert!(Op2::SHR_U_I32.eval(1 << 31, 31) == 1);
+    assert!(Op2::ROTL_I64.eval(1 << 63, 1) == 1);
+    assert!(Op2::ROTR_I32.eval(1, 1) == 1 << 31);
+    // Amounts are taken modulo the width.
+    assert!(Op2::SHL_I64.eval(1, 64) == 1);
+    assert!(Op2::SHL_I32.eval(1, 33) == 2);
+    assert!(Op2::SHR_S_I32.eval(1 << 31, 32) == 1 << 31);
+    assert!(Op2::ROTL_I32.eval(1, 32) == 1);
+  }
+
+  #[test]
+  fn small_shifts() {
+    assert!(Op2::SHL_I64_I6.eval(1, 63) == 1 << 63);
+    assert!(Op2::SHR_S_I64_I6.eval(1 << 63, 63) == u64::MAX);
+    assert!(Op2::SHR_U_I64_I6.eval(1 << 63, 63) == 1);
+    assert!(Op2::ROTL_I64_I6.eval(1 << 63, 1) == 1);
+    assert!(Op2::ROTR_I64_I6.eval(1, 63) == 2);
+    assert!(Op2::SHL_I32_I5.eval(3, 31) == 1 << 31);
+    assert!(Op2::SHR_S_I32_I5.eval(1 << 31, 31) == u32::MAX as u64);
+    assert!(Op2::SHR_U_I32_I5.eval(1 << 31, 31) == 1);
+    assert!(Op2::ROTL_I32_I5.eval(1 << 31, 1) == 1);
+    assert!(Op2::ROTR_I32_I5.eval(1, 31) == 2);
+    assert!(Op2::SHL_I64_I6.eval(5, 0) == 5 && Op2::SHR_U_I32_I5.eval(5, 0) == 5);
+  }
+
+  #[test]
+  fn bit_counts_fit_their_types() {
+    let ops = [
+      Op1::CLZ_I64_I7, Op1::CTZ_I64_I7, Op1::POPCOUNT_I64_I7,
+      Op1::CLZ_I32_I6, Op1::CTZ_I32_I6, Op1::POPCOUNT_I32_I6,
+    ];
+    for op in ops {
+      for x in [0, 1, 1 << 31, u32::MAX as u64, u64::MAX] {
+        let x = if op.arg_type() == Type::I32 { x as u32 as u64 } else { x };
+        assert!(op.ret_type().is_value(op.eval(x)));
+      }
+    }
+    assert!(Op1::CLZ_I64_I7.eval(0) == 64 && Op1::POPCOUNT_I64_I7.eval(u64::MAX) == 64);
+    assert!(Op1::CTZ_I32_I6.eval(0) == 32 && Op1::POPCOUNT_I32_I6.eval(u32::MAX as u64) == 32);
+    assert!(Op1::CLZ_I32_I6.eval(1) == 31 && Op1::CTZ_I64_I7.eval(1 << 63) == 63);
+  }
+
+  #[test]
+  fn is_value() {
+    assert!(Type::BOOL.is_value(1) && ! Type::BOOL.is_value(2));
+    assert!(Type::I5.is_value(31) && ! Type::I5.is_value(32));
+    assert!(Type::I6.is_value(63) && ! Type::I6.is_value(64));
+    assert!(Type::I7.is_value(127) && ! Type::I7.is_value(128));
+    assert!(Type::I32.is_value(u32::MAX as u64) && ! Type::I32.is_value(1 << 32));
+    assert!(Type::I64.is_value(u64::MAX) && Type::Ref.is_value(u64::MAX));
+    assert!(! Type::aggregate(0).is_value(0));
+  }
+
+  #[test]
+  fn bool_operations() {
+    for x in 0 .. 2 {
+      assert!(Op1::NOT_BOOL.eval(x) == 1 - x);
+      assert!(Op1::CAST_BOOL_I32.eval(x) == x && Op1::CAST_BOOL_I64.eval(x) == x);
+      for y in 0 .. 2 {
+        assert!(Op2::AND_BOOL.eval(x, y) == (x == 1 && y == 1) as u64);
+        assert!(Op2::OR_BOOL.eval(x, y) == (x == 1 || y == 1) as u64);
+        assert!(Op2::XOR_BOOL.eval(x, y) == (x != y) as u64);
+        assert!(Op2::IS_EQ_BOOL.eval(x, y) == (x == y) as u64);
+        assert!(Op2::IS_NE_BOOL.eval(x, y) == (x != y) as u64);
+      }
+    }
+    // Any non-zero value is true, but only the low 32 bits of an i32 count.
+    assert!(Op1::CAST_I32_BOOL.eval(0x8000_0000) == 1);
+    assert!(Op1::CAST_I32_BOOL.eval(1 << 32) == 0);
+    assert!(Op1::CAST_I64_BOOL.eval(1 << 32) == 1);
+    assert!(Op1::CAST_I64_BOOL.eval(0) == 0);
+  }
+
+  #[test]
+  fn results_are_zero_extended() {
+    assert!(Op2::ADD_I32.eval(u32::MAX as u64, 1) == 0);
+    assert!(Op2::SUB_I32.eval(0, 1) == u32::MAX as u64);
+    assert!(Op2::MUL_I32.eval(1 << 16, 1 << 16) == 0);
+    assert!(Op1::NEG_I32.eval(1) == u32::MAX as u64);
+    assert!(Op1::NOT_I32.eval(0) == u32::MAX as u64);
+    assert!(Op1::CAST_I32_I64_SX.eval(u32::MAX as u64) == u64::MAX);
+    assert!(Op1::CAST_I32_I64_ZX.eval(u32::MAX as u64) == u32::MAX as u64);
+    assert!(Op1::CLZ_I64.eval(0) == 64 && Op1::CTZ_I32.eval(0) == 32);
+  }
+
+  #[test]
+  fn nans_are_canonical() {
+    let nan32 = 0x7fc0_0000;
+    let nan64 = 0x7ff8_0000_0000_0000;
+    // A negative NaN with a payload.
+    let x32 = 0xffc0_1234;
+    let x64 = 0xfff8_0000_0000_1234;
+    let inf32 = f32::INFINITY.to_bits() as u64;
+    let inf64 = f64::INFINITY.to_bits();
+    assert!(Op2::ADD_F32.eval(x32, 0) == nan32);
+    assert!(Op2::SUB_F32.eval(inf32, inf32) == nan32);
+    assert!(Op2::DIV_F32.eval(0, 0) == nan32);
+    assert!(Op2::MIN_F32.eval(x32, 0) == nan32);
+    assert!(Op2::MAX_F32.eval(0, x32) == nan32);
+    assert!(Op1::SQRT_F32.eval((-1.0f32).to_bits() as u64) == nan32);
+    assert!(Op1::CAST_F64_F32.eval(x64) == nan32);
+    assert!(Op2::MUL_F64.eval(x64, 0) == nan64);
+    assert!(Op2::SUB_F64.eval(inf64, inf64) == nan64);
+    assert!(Op2::MIN_F64.eval(0, x64) == nan64);
+    assert!(Op1::CAST_F32_F64.eval(x32) == nan64);
+    // `neg`, `abs` and bitcasts only move bits.
+    assert!(Op1::NEG_F32.eval(x32) == 0x7fc0_1234);
+    assert!(Op1::ABS_F64.eval(x64) == 0x7ff8_0000_0000_1234);
+    assert!(Op1::BITCAST_F32_I32.eval(x32) == x32);
+    assert!(Op2::IS_EQ_F32.eval(nan32, nan32) == 0 && Op2::IS_NE_F64.eval(nan64, nan64) == 1);
+    assert!(Op2::IS_LT_F64.eval(nan64, 0) == 0 && Op2::IS_GE_F32.eval(0, nan32) == 0);
+  }
+
+  #[test]
+  fn signed_zeros() {
+    let neg = (-0.0f32).to_bits() as u64;
+    assert!(Op2::MIN_F32.eval(0, neg) == neg && Op2::MIN_F32.eval(neg, 0) == neg);
+    assert!(Op2::MAX_F32.eval(0, neg) == 0 && Op2::MAX_F32.eval(neg, 0) == 0);
+    assert!(Op2::IS_EQ_F32.eval(0, neg) == 1);
+  }
+
+  #[test]
+  fn float_to_int_saturates() {
+    let f32s = |x: f32| x.to_bits() as u64;
+    let f64s = |x: f64| x.to_bits();
+    assert!(Op1::CAST_F32_I32_S.eval(f32s(3e9)) == i32::MAX as u64);
+    assert!(Op1::CAST_F32_I32_S.eval(f32s(-3e9)) == i32::MIN as u32 as u64);
+    assert!(Op1::CAST_F32_I32_U.eval(f32s(-1.5)) == 0);
+    assert!(Op1::CAST_F32_I32_U.eval(f32s(f32::INFINITY)) == u32::MAX as u64);
+    assert!(Op1::CAST_F32_I64_S.eval(f32s(f32::NEG_INFINITY)) == i64::MIN as u64);
+    assert!(Op1::CAST_F32_I64_U.eval(f32s(1e20)) == u64::MAX);
+    assert!(Op1::CAST_F64_I32_S.eval(f64s(-2.9)) == (-2i32) as u32 as u64);
+    assert!(Op1::CAST_F64_I32_U.eval(f64s(1e10)) == u32::MAX as u64);
+    assert!(Op1::CAST_F64_I64_S.eval(f64s(1e19)) == i64::MAX as u64);
+    assert!(Op1::CAST_F64_I64_U.eval(f64s(-1e19)) == 0);
+    for op in [Op1::CAST_F32_I32_S, Op1::CAST_F32_I32_U, Op1::CAST_F32_I64_S, Op1::CAST_F32_I64_U] {
+      assert!(op.eval(0x7fc0_0000) == 0 && op.eval(0xffc0_1234) == 0);
+    }
+    for op in [Op1::CAST_F64_I32_S, Op1::CAST_F64_I32_U, Op1::CAST_F64_I64_S, Op1::CAST_F64_I64_U] {
+      assert!(op.eval(0x7ff8_0000_0000_0000) == 0);
+    }
+  }
+
+  #[test]
+  fn decode_errors() {
+    let code = [Tag::CONST_I32.0, 1, 2, 0xff];
+    let Err(e) = Reader::new(&code[.. 3]).next().unwrap() else { panic!() };
+    assert!(e.offset == 1 && e.tag == Tag::CONST_I32.0 && e.kind == DecodeErrorKind::Truncated { expected: "i32" });
+    let mut r = Reader::new(&code[3 ..]);
+    let Err(e) = r.next().unwrap() else { panic!() };
+    assert!(e.offset == 0 && e.tag == 0xff && e.kind == DecodeErrorKind::UnknownTag);
+    assert!(r.next().is_none());
+    let mut buf = &code[.. 3];
+    let mut s = ReadState::new();
+    assert!(read_with_state(&mut buf, &mut s).is_err() && buf.len() == 3);
+  }
+
+  #[test]
+  fn invalid_operands() {
+    let mut b = Builder::with_encoding(Encoding::Compact);
+    b.emit_function(0, 0);
+    let header = b.view().len();
+    let _ = b.emit_const_i32(1);
+    let mut code = b.view().to_vec();
+    // neg.i32 of the value two back, of which there is only one.
+    code.extend_from_slice(&[Tag::OP1.0, Op1::NEG_I32.0, 1]);
+    // A const.i32 of 1 << 31, which doesn't fit in a signed i32.
+    code.extend_from_slice(&[Tag::CONST_I32.0, 0x80, 0x80, 0x80, 0x80, 0x08]);
+    let mut r = Reader::new(&code);
+    assert!(matches!(r.next(), Some(Ok((0, Instruction::Function(..))))));
+    assert!(matches!(r.next(), Some(Ok((_, Instruction::ConstI32(1))))));
+    let Some(Err(e)) = r.next() else { panic!() };
+    assert!(e.offset == header + 4 && e.kind == DecodeErrorKind::Invalid { expected: "operand" });
+    let mut buf = &code[header + 5 ..];
+    let mut s = ReadState::new();
+    let mut h = &code[.. header];
+    assert!(matches!(read_with_state(&mut h, &mut s), Ok(Some(Instruction::Function(..)))));
+    let Err(e) = read_with_state(&mut buf, &mut s) else { panic!() };
+    assert!(e.offset == 1 && e.kind == DecodeErrorKind::Invalid { expected: "i32" } && buf.len() == 6);
+  }
+
+  #[test]
+  fn compact_round_trip() {
+    let mut b = Builder::with_encoding(Encoding::Compact);
+    b.emit_function(1, 2);
+    let x = b.emit_param(Type::I64);
+    let y = b.emit_param(Type::I64);
+    b.emit_results(1);
+    let _ = b.emit_type(Type::I64);
+    let _ = b.emit_const_i32(-5i32 as u32);
+    let z = b.emit_op2(Op2::ADD_I64, x, y);
+    let p = b.emit_op2(Op2::IS_EQ_I64, z, x);
+    let w = b.emit_select(p, y, z);
+    b.emit_return(0, 1);
+    b.emit_value(w);
+    let mut r = Reader::new(b.view());
+    assert!(matches!(r.next(), Some(Ok((0, Instruction::Function(..))))));
+    assert!(matches!(r.next(), Some(Ok((_, Instruction::ConstI32(0xffff_fffb))))));
+    assert!(matches!(r.next(), Some(Ok((_, Instruction::Op2(Op2::ADD_I64, Value(0), Value(1)))))));
+    assert!(matches!(r.next(), Some(Ok((_, Instruction::Op2(Op2::IS_EQ_I64, Value(3), Value(0)))))));
+    assert!(matches!(r.next(), Some(Ok((_, Instruction::Select(Value(4), Value(1), Value(3)))))));
+    let Some(Ok((_, Instruction::Return(0, args)))) = r.next() else { panic!() };
+    assert!(args.iter().eq([Value(5)]));
+    assert!(r.next().is_none());
+  }
+
+  #[test]
+  #[should_panic]
+  fn compact_operands_are_defined() {
+    let mut b = Builder::with_encoding(Encoding::Compact);
+    b.emit_function(0, 0);
+    let x = b.emit_const_i32(1);
+    b.emit_function(0, 0);
+    let _ = b.emit_op1(Op1::NEG_I32, x);
+  }
+
+  // A struct of an i64 and a bool, and an array of three of them.
+
+  fn aggregates(b: &mut Builder) -> (Type, Type) {
+    let s = b.emit_struct(2);
+    let _ = b.emit_type(Type::I64);
+    let _ = b.emit_type(Type::BOOL);
+    let a = b.emit_array(s, 3);
+    (s, a)
+  }
+
+  #[test]
+  fn type_table() {
+    let mut b = Builder::new();
+    let (s, a) = aggregates(&mut b);
+    b.emit_function(0, 0);
+    let table = TypeTable::read(b.view());
+    assert!(table.len() == 2);
+    assert!(table.get(Type::I64).is_none() && table.is_defined(Type::I64));
+    assert!(table.is_defined(a) && ! table.is_defined(Type::aggregate(2)));
+    assert!(table.num_fields(s) == 2 && table.num_fields(a) == 3);
+    assert!(table.field(s, 1) == Type::BOOL && table.field(a, 2) == s);
+    assert!(table.width(Type::I64) == 1 && table.width(s) == 2 && table.width(a) == 6);
+    assert!(table.offset(s, 1) == 1 && table.offset(a, 2) == 4);
+    let mut ts = Vec::new();
+    table.flatten(a, &mut ts);
+    assert!(ts == [Type::I64, Type::BOOL].repeat(3));
+  }
+
+  // A function of an i64 that builds a value of the struct type from it and
+  // `true`, and then applies `f`.
+
+  fn aggregate_op(f: impl FnOnce(&mut Builder, Type, Value, Value) -> Value) -> Builder {
+    let mut b = Builder::new();
+    let (s, _) = aggregates(&mut b);
+    b.emit_function(1, 1);
+    let x = b.emit_param(Type::I64);
+    b.emit_results(0);
+    let p = b.emit_const_bool(true);
+    let _ = f(&mut b, s, x, p);
+    b.emit_return(0, 0);
+    b
+  }
+
+  #[test]
+  fn aggregate_operations_are_checked() {
+    let error = |b: Builder| verify(b.view()).err().map(|e| e.message);
+    assert!(error(aggregate_op(|b, s, x, p| { let y = b.emit_build(s, &[x, p]); let z = b.emit_insert(y, 0, x); b.emit_extract(z, 1) })).is_none());
+    assert!(error(aggregate_op(|b, _, x, p| b.emit_build(Type::I64, &[x, p]))) == Some("build of a type that isn't an aggregate"));
+    assert!(error(aggregate_op(|b, s, x, _| b.emit_build(s, &[x]))) == Some("wrong number of fields"));
+    assert!(error(aggregate_op(|b, s, x, _| b.emit_build(s, &[x, x]))) == Some("operand has the wrong type"));
+    assert!(error(aggregate_op(|b, _, x, _| b.emit_extract(x, 0))) == Some("field of a value that isn't an aggregate"));
+    assert!(error(aggregate_op(|b, s, x, p| { let y = b.emit_build(s, &[x, p]); b.emit_extract(y, 2) })) == Some("field index out of range"));
+    assert!(error(aggregate_op(|b, s, x, p| { let y = b.emit_build(s, &[x, p]); b.emit_insert(y, 1, x) })) == Some("operand has the wrong type"));
+  }
+
+  #[test]
+  fn module_table() {
+    let mut f = Builder::new();
+    f.emit_function(1, 2);
+    let x = f.emit_param(Type::I64);
+    let _ = f.emit_param(Type::BOOL);
+    f.emit_results(1);
+    let _ = f.emit_type(Type::I64);
+    f.emit_return(0, 1);
+    f.emit_value(x);
+    let g = returning(1, &[Type::BOOL, Type::I32]);
+
+    let mut m = Module::new();
+    assert!(m.is_empty());
+    assert!(m.add_function(b"f", f.view()) == 0);
+    assert!(m.add_function(b"gg", g.view()) == 1);
+    assert!(!m.is_empty() && m.len() == 2);
+
+    let h = m.header();
+    assert!(h.nfunctions == 2);
+    assert!(h.code_size as usize == f.view().len() + g.view().len());
+    assert!(h.types_size as usize == m.types().len() && h.strings_size == 3);
+    assert!(m.entry(1).offset as usize == f.view().len());
+    assert!(m.function(0) == f.view() && m.function(1) == g.view());
+    assert!(m.name(0) == b"f" && m.name(1) == b"gg");
+
+    let types = |ts: TypeList<'_>| ts.iter().collect::<Vec<_>>();
+    assert!(types(m.params(0)) == [Type::I64, Type::BOOL]);
+    assert!(types(m.params(1)).is_empty());
+    assert!(m.results(0).iter().map(types).collect::<Vec<_>>() == [vec![Type::I64]]);
+    assert!(m.results(1).iter().map(types).collect::<Vec<_>>() == [vec![Type::I64], vec![Type::BOOL, Type::I32]]);
+    assert!(verify(m.code()).is_ok());
+  }
+
+  #[test]
+  fn module_find() {
+    let mut b = Builder::new();
+    b.emit_function(0, 0);
+    let mut m = Module::new();
+    for name in [b"f", b"g", b"f"] {
+      let _ = m.add_function(name, b.view());
+    }
+    assert!(m.find(b"f") == Some(0) && m.find(b"g") == Some(1) && m.find(b"h").is_none());
+    let entries = (0 .. m.len()).map(|i| m.entry(i)).collect();
+    let n = Module::from_parts(m.code().to_vec(), entries, m.types().to_vec(), m.strings().to_vec());
+    assert!(n.find(b"f") == Some(0) && n.find(b"g") == Some(1));
+  }
+
+  // Function 0 takes an i64 and makes a call to function `g` with `nargs`
+  // copies of it and `nkonts` continuations, or a tail call if `nkonts` is
+  // `None`. Function 1 takes an i64 and returns a value of type `t`.
+
+  fn call(g: u32, nargs: u32, nkonts: Option<u32>, t: Type) -> Builder {
+    let mut b = Builder::new();
+    b.emit_function(1, 1);
+    let x = b.emit_param(Type::I64);
+    b.emit_results(1);
+    let _ = b.emit_type(Type::I64);
+    match nkonts {
+      Some(nkonts) => {
+        b.emit_call(g, nargs, nkonts);
+        for _ in 0 .. nargs {
+          b.emit_value(x);
+        }
+        let points: Vec<_> = (0 .. nkonts).map(|_| b.emit_label(Label(0))).collect();
+        for point in points {
+          let k = b.emit_kont(1);
+          b.patch_label(point, k);
+          let y = b.emit_param(t);
+          b.emit_return(0, 1);
+          b.emit_value(y);
+        }
+      }
+      None => {
+        b.emit_tailcall(g, nargs);
+        for _ in 0 .. nargs {
+          b.emit_value(x);
+        }
+      }
+    }
+
+    b.emit_function(1, 1);
+    let x = b.emit_param(Type::I64);
+    b.emit_results(1);
+    let _ = b.emit_type(t);
+    let y = if t == Type::I64 { x } else { b.emit_op1(Op1::CAST_I64_I32, x) };
+    b.emit_return(0, 1);
+    b.emit_value(y);
+    b
+  }
+
+  #[test]
+  fn calls_are_checked() {
+    let error = |b: Builder| verify(b.view()).err().map(|e| e.message);
+    assert!(error(call(1, 1, Some(1), Type::I64)).is_none());
+    assert!(error(call(1, 1, None, Type::I64)).is_none());
+    assert!(error(call(0, 1, None, Type::I64)).is_none());
+    assert!(error(call(2, 1, Some(1), Type::I64)) == Some("call to undefined function"));
+    assert!(error(call(2, 1, None, Type::I64)) == Some("call to undefined function"));
+    assert!(error(call(1, 2, Some(1), Type::I64)) == Some("wrong number of arguments"));
+    assert!(error(call(1, 0, None, Type::I64)) == Some("wrong number of arguments"));
+    assert!(error(call(1, 1, Some(2), Type::I64)) == Some("wrong number of continuations"));
+    assert!(error(call(1, 1, None, Type::I32)) == Some("tail call to function with different result types"));
+  }
+
+  // Emits the header of a function of no parameters that returns an i64 to
+  // its first continuation and a bool and an i32 to its second.
+
+  fn header(b: &mut Builder) {
+    b.emit_function(2, 0);
+    b.emit_results(1);
+    let _ = b.emit_type(Type::I64);
+    b.emit_results(2);
+    let _ = b.emit_type(Type::BOOL);
+    let _ = b.emit_type(Type::I32);
+  }
+
+  // Emits a return of constants of types `ts` to the `index`th continuation.
+
+  fn return_constants(b: &mut Builder, index: u32, ts: &[Type]) {
+    let xs: Vec<_> =
+      ts.iter()
+        .map(|&t| {
+          match t {
+            Type::BOOL => b.emit_const_bool(true),
+            Type::I32 => b.emit_const_i32(1),
+            _ => b.emit_const_i64(1),
+          }
+        })
+        .collect();
+    b.emit_return(index, xs.len() as u32);
+    for x in xs {
+      b.emit_value(x);
+    }
+  }
+
+  fn returning(index: u32, ts: &[Type]) -> Builder {
+    let mut b = Builder::new();
+    header(&mut b);
+    return_constants(&mut b, index, ts);
+    b
+  }
+
+  // A function with that header that calls another, and receives what that
+  // returns in `kont` blocks with parameters of types `ts`.
+
+  fn calling(ts: [&[Type]; 2]) -> Builder {
+    let mut b = Builder::new();
+    header(&mut b);
+    b.emit_call(1, 0, 2);
+    let points = [b.emit_label(Label(0)), b.emit_label(Label(0))];
+    for (point, ts) in points.into_iter().zip(ts) {
+      let k = b.emit_kont(ts.len() as u32);
+      b.patch_label(point, k);
+      for &t in ts.iter() {
+        let _ = b.emit_param(t);
+      }
+      return_constants(&mut b, 0, &[Type::I64]);
+    }
+    header(&mut b);
+    return_constants(&mut b, 0, &[Type::I64]);
+    b
+  }
+
+  #[test]
+  fn continuations_have_result_types() {
+    let b = returning(0, &[Type::I64]);
+    let mut buf = b.view();
+    let Ok(Some(Instruction::Function(konts, args))) = read(&mut buf) else { panic!() };
+    assert!(args.iter().count() == 0);
+    assert!(konts.len() == 2);
+    let rets: Vec<Vec<_>> = konts.iter().map(|ts| ts.iter().collect()).collect();
+    assert!(rets == [vec![Type::I64], vec![Type::BOOL, Type::I32]]);
+
+    let error = |b: Builder| verify(b.view()).err().map(|e| e.message);
+    assert!(error(returning(1, &[Type::BOOL, Type::I32])).is_none());
+    assert!(error(returning(0, &[Type::I32])) == Some("operand has the wrong type"));
+    assert!(error(returning(1, &[Type::I64])) == Some("wrong number of arguments"));
+    assert!(error(returning(2, &[])) == Some("return to undefined continuation"));
+    assert!(error(calling([&[Type::I64], &[Type::BOOL, Type::I32]])).is_none());
+    assert!(error(calling([&[Type::I64], &[Type::BOOL]])) == Some("branch to block of the wrong kind or type"));
+    assert!(error(calling([&[Type::I32], &[Type::BOOL, Type::I32]])) == Some("branch to block of the wrong kind or type"));
+  }
+
+  #[test]
+  fn read_fixed() {
+    let mut b = Builder::with_encoding(Encoding::Fixed);
+    let x = b.emit_const_i32(7);
+    let _ = b.emit_op1(Op1::NEG_I32, x);
+    let mut buf = b.view();
+    assert!(matches!(read(&mut buf), Ok(Some(Instruction::ConstI32(7)))));
+    assert!(matches!(read(&mut buf), Ok(Some(Instruction::Op1(Op1::NEG_I32, Value(0))))));
+    assert!(matches!(read(&mut buf), Ok(None)));
+    let mut buf = &[Tag::FUNCTION_COMPACT.0, 0, 0, 0, 0][..];
+    assert!(matches!(read(&mut buf), Err(DecodeError { kind: DecodeErrorKind::NeedsState, .. })));
+  }
+}